@@ -0,0 +1,315 @@
+//! Compile-time parsing of spli code embedded in Rust.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Span, TokenStream, TokenTree};
+use proc_macro2::{Literal, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use spli::parser::{program, program_resilient};
+use spli::{Value, ValueKind};
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// spli source rebuilt from Rust tokens.
+#[derive(Default)]
+struct Source {
+    text: String,
+    /// Ranges of the source written by every token.
+    spans: Vec<(Range<usize>, Span)>,
+    /// Interpolated expressions by the offset of their `#` symbol.
+    interpolations: HashMap<usize, TokenStream2>,
+    /// End of the previous token, `None` if the next one must be separated.
+    last: Option<(usize, usize)>,
+}
+
+impl Source {
+    fn push(&mut self, text: &str, start: Span, end: Span) {
+        let (line, column) = (start.start().line(), start.start().column());
+        match self.last {
+            _ if self.text.is_empty() => {}
+            Some(last) if last == (line, column) => {}
+            Some((last_line, _)) if last_line < line => self.text.push('\n'),
+            _ => self.text.push(' '),
+        }
+        let offset = self.text.len();
+        self.text.push_str(text);
+        self.spans.push((offset..self.text.len(), start));
+        self.last = Some((end.end().line(), end.end().column()));
+    }
+
+    /// Writes `#` symbol standing for `expr`.
+    fn interpolate(&mut self, text: &str, span: Span, expr: TokenStream2) {
+        self.push(text, span, span);
+        self.interpolations
+            .insert(self.text.len() - text.len(), expr);
+        self.last = None;
+    }
+
+    fn tokens(&mut self, stream: TokenStream) {
+        let mut tokens = stream.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            match token {
+                TokenTree::Punct(punct) if punct.as_char() == '#' => match tokens.peek() {
+                    Some(TokenTree::Ident(ident)) => {
+                        let text = format!("#{}", ident);
+                        let expr = TokenStream2::from(TokenStream::from(tokens.next().unwrap()));
+                        self.interpolate(&text, punct.span(), expr);
+                    }
+                    Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => {
+                        let expr = TokenStream2::from(group.stream());
+                        tokens.next();
+                        self.interpolate("#", punct.span(), expr);
+                    }
+                    _ => self.push("#", punct.span(), punct.span()),
+                },
+                // Lifetime-like `'name`, whose quote spans the whole token
+                TokenTree::Punct(punct) if punct.as_char() == '\'' => match tokens.peek() {
+                    Some(TokenTree::Ident(ident)) => {
+                        let (text, end) = (format!("'{}", ident), ident.span());
+                        tokens.next();
+                        self.push(&text, punct.span(), end);
+                    }
+                    _ => self.push("'", punct.span(), punct.span()),
+                },
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::None => ("", ""),
+                    };
+                    if !open.is_empty() {
+                        self.push(open, group.span_open(), group.span_open());
+                    }
+                    self.tokens(group.stream());
+                    if !close.is_empty() {
+                        self.push(close, group.span_close(), group.span_close());
+                    }
+                }
+                // Rust escapes like `\u{e9}` are written as the chars they stand for
+                TokenTree::Literal(literal) => {
+                    let span = literal.span();
+                    let token = TokenStream2::from(TokenStream::from(TokenTree::Literal(literal)));
+                    match syn::parse2::<syn::LitStr>(token.clone()) {
+                        Ok(string) => {
+                            let text = ValueKind::String(string.value()).to_string();
+                            self.push(&text, span, span);
+                        }
+                        Err(_) => self.push(&token.to_string(), span, span),
+                    }
+                }
+                token => self.push(&token.to_string(), token.span(), token.span()),
+            }
+        }
+    }
+
+    /// Source of a string literal. Errors point to the chars of the literal
+    /// where the compiler supports that, and to the whole literal otherwise.
+    fn literal(&mut self, literal: &syn::LitStr) {
+        self.text = literal.value();
+        let token = literal.token();
+        let spans: Option<Vec<_>> = char_ranges(&token.to_string())
+            .into_iter()
+            .map(|(chars, range)| Some((chars, token.subspan(range)?.unwrap())))
+            .collect();
+        self.spans = match spans {
+            Some(spans) if !spans.is_empty() => spans,
+            _ => vec![(0..self.text.len(), literal.span().unwrap())],
+        };
+    }
+
+    /// Span of the token at `offset`, or of the last one before it.
+    fn span_at(&self, offset: usize) -> Span {
+        self.spans
+            .iter()
+            .rev()
+            .find(|(range, _)| range.start <= offset)
+            .map_or_else(Span::call_site, |(_, span)| *span)
+    }
+
+    fn offset_of(&self, s: &str) -> usize {
+        s.as_ptr() as usize - self.text.as_ptr() as usize
+    }
+
+    fn value(&self, value: &Value) -> TokenStream2 {
+        let (raw, sequential) = (value.raw, value.sequential);
+        if let ValueKind::Symbol(symbol) = value.kind {
+            if let Some(expr) = self.interpolations.get(&self.offset_of(symbol)) {
+                return quote! {{
+                    let mut value: ::spli::Value = ::core::convert::Into::into(#expr);
+                    value.raw |= #raw;
+                    value.sequential |= #sequential;
+                    value
+                }};
+            }
+        }
+        let kind = match &value.kind {
+            ValueKind::Symbol(symbol) => quote!(::spli::ValueKind::Symbol(#symbol)),
+            ValueKind::Integer(n) => {
+                let number = number(Literal::i64_suffixed(n.value), n.text());
+                quote!(::spli::ValueKind::Integer(#number))
+            }
+            ValueKind::Float(x) => {
+                let bits = x.value.to_bits();
+                let number = number(quote!(f64::from_bits(#bits)), x.text());
+                quote!(::spli::ValueKind::Float(#number))
+            }
+            ValueKind::String(s) => {
+                quote!(::spli::ValueKind::String(::std::string::String::from(#s)))
+            }
+            ValueKind::List(items) => {
+                let items = items.iter().map(|item| self.value(&item));
+                quote! {
+                    ::spli::ValueKind::List(::spli::list::List::from_double_ended_iter(
+                        ::std::vec![#(#items),*]
+                    ))
+                }
+            }
+            ValueKind::Error => quote!(::spli::ValueKind::Error),
+            ValueKind::Native(_) => unreachable!("the parser doesn't produce native values"),
+        };
+        quote! {
+            ::spli::Value {
+                raw: #raw,
+                sequential: #sequential,
+                kind: #kind,
+            }
+        }
+    }
+}
+
+/// Ranges of the chars in the value of a string literal, together with the
+/// ranges of the literal that write them, like `\u{e9}`.
+fn char_ranges(literal: &str) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let start = hashes + 2;
+        for (idx, c) in literal[start..literal.len() - hashes - 1].char_indices() {
+            ranges.push((
+                offset..offset + c.len_utf8(),
+                start + idx..start + idx + c.len_utf8(),
+            ));
+            offset += c.len_utf8();
+        }
+        return ranges;
+    }
+
+    let content = &literal[..literal.len() - 1];
+    let mut chars = content.char_indices().skip(1).peekable();
+    while let Some((start, c)) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => Some('\n'),
+                Some('r') => Some('\r'),
+                Some('t') => Some('\t'),
+                Some('0') => Some('\0'),
+                Some('x') => {
+                    let digits: String = chars.by_ref().take(2).map(|(_, c)| c).collect();
+                    u8::from_str_radix(&digits, 16).ok().map(char::from)
+                }
+                Some('u') => {
+                    let digits: String = chars
+                        .by_ref()
+                        .map(|(_, c)| c)
+                        .take_while(|&c| c != '}')
+                        .filter(|&c| c != '{' && c != '_')
+                        .collect();
+                    u32::from_str_radix(&digits, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                }
+                // A line break escaped together with the whitespace after it
+                Some('\n') => {
+                    while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+                    None
+                }
+                c => c,
+            },
+            c => Some(c),
+        };
+        if let Some(c) = c {
+            let end = chars.peek().map_or(content.len(), |&(idx, _)| idx);
+            ranges.push((offset..offset + c.len_utf8(), start..end));
+            offset += c.len_utf8();
+        }
+    }
+    ranges
+}
+
+fn number(value: impl quote::ToTokens, text: Option<&str>) -> TokenStream2 {
+    match text {
+        Some(text) => quote!(::spli::Number::with_text(#value, #text)),
+        None => quote!(::spli::Number::from(#value)),
+    }
+}
+
+/// Parses spli code at compile time and builds its program list, like
+/// [`spli::parser::program`] does.
+///
+/// `#name` and `#{ expr }` are replaced with the value of a Rust expression,
+/// which must be convertible into [`spli::Value`]. Syntax errors are reported
+/// at the offending tokens.
+///
+/// The code must consist of Rust tokens, so Rust doesn't accept `'(a b)`
+/// there. String literals in the code may use Rust escapes and raw strings.
+/// Code that isn't valid Rust can be written as a single string literal:
+/// `spli!(r#"'(a "b")"#)`, but it doesn't support interpolation. Errors in
+/// it point to the whole literal on stable compilers, which can't point
+/// into literals.
+#[proc_macro]
+pub fn spli(input: TokenStream) -> TokenStream {
+    let mut source = Source::default();
+    match syn::parse::<syn::LitStr>(input.clone()) {
+        Ok(literal) => source.literal(&literal),
+        Err(_) => source.tokens(input),
+    }
+    let expansion = match program(spli::parser::Span::new(&source.text)) {
+        Ok((_, value)) => source.value(&value),
+        Err(_) => {
+            let (_, errors) = program_resilient(&source.text);
+            let errors = errors.iter().map(|error| {
+                let span = proc_macro2::Span::from(source.span_at(error.range.start));
+                let message = match &error.label {
+                    Some(label) => format!("{}: {} [{}]", error.message, label, error.kind.code()),
+                    None => format!("{} [{}]", error.message, error.kind.code()),
+                };
+                quote_spanned!(span=> ::core::compile_error!(#message);)
+            });
+            quote!({ #(#errors)* ::spli::Value {
+                raw: false,
+                sequential: true,
+                kind: ::spli::ValueKind::Error,
+            } })
+        }
+    };
+    expansion.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(literal: &str) -> Vec<(usize, &str)> {
+        char_ranges(literal)
+            .into_iter()
+            .map(|(chars, range)| (chars.start, &literal[range]))
+            .collect()
+    }
+
+    #[test]
+    fn test_char_ranges() {
+        assert_eq!(
+            ranges(r#""a\n\u{e9}b""#),
+            [(0, "a"), (1, "\\n"), (2, "\\u{e9}"), (4, "b")]
+        );
+        assert_eq!(
+            ranges("\"\\x41\\\n   c\u{e9}\""),
+            [(0, "\\x41"), (1, "c"), (2, "\u{e9}")]
+        );
+        assert_eq!(ranges(r###"r#"a"\"#"###), [(0, "a"), (1, "\""), (2, "\\")]);
+        assert!(ranges(r#""""#).is_empty());
+    }
+}
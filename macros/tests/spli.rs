@@ -0,0 +1,49 @@
+use spli::{Value, ValueKind};
+use spli_macros::spli;
+
+fn forms(program: &Value) -> Vec<String> {
+    match &program.kind {
+        ValueKind::List(forms) => forms.iter().map(|form| form.to_string()).collect(),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_spli() {
+    let program = spli! {
+        (define x 0xFF)
+        'quoted !(a-b 1.5 "s\n")
+    };
+    assert!(program.sequential);
+    assert_eq!(
+        forms(&program),
+        ["(define x 0xFF)", "'quoted", "!(a-b 1.5 \"s\\n\")"]
+    );
+    assert_eq!(
+        program,
+        spli::parser::program_resilient(&forms(&program).join(" ")).0
+    );
+    assert_eq!(forms(&spli! {}), Vec::<String>::new());
+}
+
+#[test]
+fn test_raw_list() {
+    assert_eq!(forms(&spli!(r"'(a b)")), ["'(a b)"]);
+}
+
+#[test]
+fn test_interpolation() {
+    let x = 5;
+    let name = String::from("spli");
+    let program = spli!((greet #name !#{ x * 2 } #{ ValueKind::Symbol("s") }) #x);
+    assert_eq!(forms(&program), ["(greet \"spli\" !10 s)", "5"]);
+}
+
+#[test]
+fn test_rust_escapes() {
+    let program = spli!(("caf\u{e9}" r"a\b" "\x41\t\"" r#"'(c)"#));
+    assert_eq!(
+        forms(&program),
+        ["(\"café\" \"a\\\\b\" \"A\\t\\\"\" \"'(c)\")"]
+    );
+}
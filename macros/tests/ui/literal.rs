@@ -0,0 +1,5 @@
+use spli_macros::spli;
+
+fn main() {
+    spli!("(a 0x1g)\n(b");
+}
@@ -0,0 +1,8 @@
+use spli_macros::spli;
+
+fn main() {
+    spli! {
+        (define x 0123)
+        (print ~x)
+    };
+}
@@ -0,0 +1,10 @@
+use lsp_server::Connection;
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (connection, io_threads) = Connection::stdio();
+    spli::lsp::run(&connection)?;
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
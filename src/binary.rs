@@ -0,0 +1,459 @@
+//! Compact binary encoding of [`Value`] trees.
+//!
+//! An encoded value starts with the magic bytes `SPLB` and a version byte,
+//! followed by the symbol table and the value itself. Integers are LEB128
+//! varints, signed ones are zigzag-encoded first.
+//!
+//! * The symbol table is a count of entries, each a length-prefixed UTF-8
+//!   string. It holds every distinct symbol and source text of a number once.
+//! * A value is a tag byte followed by its payload. The low 3 bits of the tag
+//!   are the kind, then come the `raw` and `sequential` flags and a flag of a
+//!   number with source text. Payloads are a symbol table index for symbols,
+//!   a signed varint for integers, 8 little-endian bytes for floats,
+//!   a length-prefixed string for strings and an item count followed by the
+//!   items for lists. Numbers with source text are followed by its index.
+//!
+//! [`decode`] borrows symbols and number texts from the buffer. Strings are
+//! copied, since [`ValueKind::String`] owns its contents. Native values can't
+//! be encoded. Like the parser, [`decode`] rejects lists nested in more than
+//! [`MAX_DEPTH`] other lists.
+
+use crate::list::List;
+use crate::parser::MAX_DEPTH;
+use crate::{Number, Value, ValueKind};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+pub const MAGIC: &[u8] = b"SPLB";
+pub const VERSION: u8 = 1;
+
+const SYMBOL: u8 = 0;
+const INTEGER: u8 = 1;
+const FLOAT: u8 = 2;
+const STRING: u8 = 3;
+const LIST: u8 = 4;
+const ERROR: u8 = 5;
+const KIND_MASK: u8 = 0b111;
+const RAW: u8 = 1 << 3;
+const SEQUENTIAL: u8 = 1 << 4;
+const HAS_TEXT: u8 = 1 << 5;
+
+/// Error of [`encode`] and [`decode`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Value contains a native value of the type.
+    NativeValue(&'static str),
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    UnexpectedEof,
+    InvalidVarint,
+    InvalidUtf8,
+    InvalidTag(u8),
+    InvalidSymbol(u64),
+    TrailingBytes(usize),
+    /// A list is nested in more than [`MAX_DEPTH`] other lists.
+    NestingTooDeep,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NativeValue(type_name) => {
+                write!(fmt, "native value of type {} can't be encoded", type_name)
+            }
+            Self::InvalidMagic => fmt.write_str("not an encoded spli value"),
+            Self::UnsupportedVersion(version) => {
+                write!(fmt, "unsupported encoding version {}", version)
+            }
+            Self::UnexpectedEof => fmt.write_str("unexpected end of data"),
+            Self::InvalidVarint => fmt.write_str("varint doesn't fit into 64 bits"),
+            Self::InvalidUtf8 => fmt.write_str("string isn't valid UTF-8"),
+            Self::InvalidTag(tag) => write!(fmt, "invalid value tag {:#04x}", tag),
+            Self::InvalidSymbol(idx) => write!(fmt, "symbol index {} is out of the table", idx),
+            Self::TrailingBytes(count) => write!(fmt, "{} bytes after the value", count),
+            Self::NestingTooDeep => write!(fmt, "more than {} nested lists", MAX_DEPTH),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_varint(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Symbol table being built.
+#[derive(Default)]
+struct Symbols<'a> {
+    indices: HashMap<&'a str, u64>,
+    symbols: Vec<&'a str>,
+}
+
+impl<'a> Symbols<'a> {
+    fn add(&mut self, symbol: &'a str) {
+        let symbols = &mut self.symbols;
+        self.indices.entry(symbol).or_insert_with(|| {
+            symbols.push(symbol);
+            symbols.len() as u64 - 1
+        });
+    }
+
+    /// Adds symbols of `value`, walking it with a work stack rather than
+    /// recursively, so that deeply nested lists don't overflow the stack.
+    fn collect(&mut self, value: &Value<'a>) -> Result<(), Error> {
+        let mut stack = vec![value];
+        while let Some(value) = stack.pop() {
+            match &value.kind {
+                ValueKind::Symbol(symbol) => self.add(symbol),
+                ValueKind::Integer(n) => self.add_text(n.text()),
+                ValueKind::Float(x) => self.add_text(x.text()),
+                // Reversed, so that symbols are numbered in the order of writing
+                ValueKind::List(items) => {
+                    stack.extend(items.values().collect::<Vec<_>>().into_iter().rev())
+                }
+                ValueKind::Native(native) => return Err(Error::NativeValue(native.type_name())),
+                ValueKind::String(_) | ValueKind::Error => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn add_text(&mut self, text: Option<&'a str>) {
+        if let Some(text) = text {
+            self.add(text);
+        }
+    }
+}
+
+/// Writes `value` with a work stack rather than recursively, like
+/// [`Symbols::collect`] walks it.
+fn write_value(out: &mut Vec<u8>, symbols: &Symbols, value: &Value) {
+    let mut stack = vec![value];
+    while let Some(value) = stack.pop() {
+        let mut tag = 0;
+        if value.raw {
+            tag |= RAW;
+        }
+        if value.sequential {
+            tag |= SEQUENTIAL;
+        }
+        let text = match &value.kind {
+            ValueKind::Integer(n) => n.text(),
+            ValueKind::Float(x) => x.text(),
+            _ => None,
+        };
+        if text.is_some() {
+            tag |= HAS_TEXT;
+        }
+        match &value.kind {
+            ValueKind::Symbol(symbol) => {
+                out.push(tag | SYMBOL);
+                write_varint(out, symbols.indices[symbol]);
+            }
+            ValueKind::Integer(n) => {
+                out.push(tag | INTEGER);
+                write_varint(out, zigzag(n.value));
+            }
+            ValueKind::Float(x) => {
+                out.push(tag | FLOAT);
+                out.extend_from_slice(&x.value.to_le_bytes());
+            }
+            ValueKind::String(s) => {
+                out.push(tag | STRING);
+                write_str(out, s);
+            }
+            ValueKind::List(items) => {
+                out.push(tag | LIST);
+                let items: Vec<_> = items.values().collect();
+                write_varint(out, items.len() as u64);
+                // Items are written right after the count, as they are popped
+                stack.extend(items.into_iter().rev());
+            }
+            ValueKind::Error => out.push(tag | ERROR),
+            ValueKind::Native(_) => unreachable!("natives are rejected by `Symbols::collect`"),
+        }
+        if let Some(text) = text {
+            write_varint(out, symbols.indices[text]);
+        }
+    }
+}
+
+/// Encodes `value` with the current [`VERSION`].
+pub fn encode(value: &Value) -> Result<Vec<u8>, Error> {
+    let mut symbols = Symbols::default();
+    symbols.collect(value)?;
+    let mut out = Vec::from(MAGIC);
+    out.push(VERSION);
+    write_varint(&mut out, symbols.symbols.len() as u64);
+    for symbol in &symbols.symbols {
+        write_str(&mut out, symbol);
+    }
+    write_value(&mut out, &symbols, value);
+    Ok(out)
+}
+
+/// Reader of an encoded value, borrowing from the buffer.
+struct Reader<'a> {
+    data: &'a [u8],
+    symbols: Vec<&'a str>,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            return Err(Error::UnexpectedEof);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, Error> {
+        let mut result = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u64::from(byte & 0x7f);
+            if shift == 63 && bits > 1 {
+                return Err(Error::InvalidVarint);
+            }
+            result |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        Err(Error::InvalidVarint)
+    }
+
+    fn len(&mut self) -> Result<usize, Error> {
+        let len = self.varint()?;
+        // Every item takes at least a byte, so this also bounds allocations
+        match usize::try_from(len) {
+            Ok(len) if len <= self.data.len() => Ok(len),
+            _ => Err(Error::UnexpectedEof),
+        }
+    }
+
+    fn str(&mut self) -> Result<&'a str, Error> {
+        let len = self.len()?;
+        std::str::from_utf8(self.bytes(len)?).map_err(|_| Error::InvalidUtf8)
+    }
+
+    fn symbol(&mut self) -> Result<&'a str, Error> {
+        let idx = self.varint()?;
+        usize::try_from(idx)
+            .ok()
+            .and_then(|idx| self.symbols.get(idx))
+            .copied()
+            .ok_or(Error::InvalidSymbol(idx))
+    }
+
+    fn number<T>(&mut self, tag: u8, value: T) -> Result<Number<'a, T>, Error> {
+        if tag & HAS_TEXT != 0 {
+            Ok(Number::with_text(value, self.symbol()?))
+        } else {
+            Ok(value.into())
+        }
+    }
+
+    /// Reads a value nested in `depth` lists.
+    fn value(&mut self, depth: usize) -> Result<Value<'a>, Error> {
+        let tag = self.byte()?;
+        if tag & !(KIND_MASK | RAW | SEQUENTIAL | HAS_TEXT) != 0 {
+            return Err(Error::InvalidTag(tag));
+        }
+        let kind = match tag & KIND_MASK {
+            SYMBOL => ValueKind::Symbol(self.symbol()?),
+            INTEGER => {
+                let n = unzigzag(self.varint()?);
+                ValueKind::Integer(self.number(tag, n)?)
+            }
+            FLOAT => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.bytes(8)?);
+                ValueKind::Float(self.number(tag, f64::from_le_bytes(bytes))?)
+            }
+            STRING => ValueKind::String(self.str()?.into()),
+            LIST => {
+                if depth > MAX_DEPTH {
+                    return Err(Error::NestingTooDeep);
+                }
+                let len = self.len()?;
+                let items = (0..len)
+                    .map(|_| self.value(depth + 1))
+                    .collect::<Result<Vec<_>, _>>()?;
+                ValueKind::List(List::from_double_ended_iter(items))
+            }
+            ERROR => ValueKind::Error,
+            _ => return Err(Error::InvalidTag(tag)),
+        };
+        let is_number = matches!(kind, ValueKind::Integer(_) | ValueKind::Float(_));
+        if tag & HAS_TEXT != 0 && !is_number {
+            return Err(Error::InvalidTag(tag));
+        }
+        Ok(Value {
+            raw: tag & RAW != 0,
+            sequential: tag & SEQUENTIAL != 0,
+            kind,
+        })
+    }
+}
+
+/// Decodes a value encoded with [`encode`]. Symbols of the value borrow from `data`.
+pub fn decode(data: &[u8]) -> Result<Value<'_>, Error> {
+    let data = data.strip_prefix(MAGIC).ok_or(Error::InvalidMagic)?;
+    let mut reader = Reader {
+        data,
+        symbols: Vec::new(),
+    };
+    match reader.byte()? {
+        VERSION => {}
+        version => return Err(Error::UnsupportedVersion(version)),
+    }
+    let count = reader.len()?;
+    reader.symbols = (0..count).map(|_| reader.str()).collect::<Result<_, _>>()?;
+    let value = reader.value(0)?;
+    match reader.data.len() {
+        0 => Ok(value),
+        count => Err(Error::TrailingBytes(count)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::program_resilient;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_round_trip() {
+        let source = "(def 'x !(0xFF 1_000.5 \"str\\n\") (x x)) -5 0b1 \"\u{e9}\"";
+        let (value, _) = program_resilient(source);
+        let data = encode(&value).unwrap();
+        let decoded = decode(&data).unwrap();
+        assert_eq!(decoded, value);
+        assert_eq!(decoded.to_string(), value.to_string());
+    }
+
+    #[test]
+    fn test_symbol_table() {
+        let (value, _) = program_resilient("(symbol symbol symbol)");
+        let data = encode(&value).unwrap();
+        assert_eq!(
+            data.windows(6).filter(|window| window == b"symbol").count(),
+            1
+        );
+        let decoded = decode(&data).unwrap();
+        let range = data.as_ptr_range();
+        match &decoded.kind {
+            ValueKind::List(forms) => match &forms.iter().next().unwrap().kind {
+                ValueKind::List(items) => {
+                    for item in items.iter() {
+                        match item.kind {
+                            ValueKind::Symbol(symbol) => {
+                                assert!(range.contains(&symbol.as_ptr()))
+                            }
+                            _ => panic!("expected a symbol"),
+                        }
+                    }
+                }
+                _ => panic!("expected a list"),
+            },
+            _ => panic!("expected a list"),
+        }
+    }
+
+    #[test]
+    fn test_varint() {
+        for &n in &[0, 1, 127, 128, 300, u64::MAX] {
+            let mut out = Vec::new();
+            write_varint(&mut out, n);
+            let mut reader = Reader {
+                data: &out,
+                symbols: Vec::new(),
+            };
+            assert_eq!(reader.varint(), Ok(n));
+        }
+        for &n in &[0, -1, 1, i64::MIN, i64::MAX] {
+            assert_eq!(unzigzag(zigzag(n)), n);
+        }
+        assert_eq!(zigzag(-1), 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        let (value, _) = program_resilient("(a \"b\")");
+        let data = encode(&value).unwrap();
+        assert_eq!(decode(b"JSON"), Err(Error::InvalidMagic));
+        assert_eq!(decode(b"SPLB\x02"), Err(Error::UnsupportedVersion(2)));
+        assert_eq!(decode(&data[..data.len() - 1]), Err(Error::UnexpectedEof));
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert_eq!(decode(&trailing), Err(Error::TrailingBytes(1)));
+        assert_eq!(
+            decode(b"SPLB\x01\x00\x00\x07"),
+            Err(Error::InvalidSymbol(7))
+        );
+        assert_eq!(decode(b"SPLB\x01\x00\x06"), Err(Error::InvalidTag(6)));
+        assert_eq!(
+            decode(b"SPLB\x01\x00\x04\xff\xff\xff\x0f"),
+            Err(Error::UnexpectedEof)
+        );
+        let mut deep = b"SPLB\x01\x00".to_vec();
+        deep.extend(b"\x04\x01".repeat(100_000));
+        assert_eq!(decode(&deep), Err(Error::NestingTooDeep));
+        let native = Value::from(crate::native::Native::new((), "unit"));
+        assert_eq!(encode(&native), Err(Error::NativeValue("unit")));
+    }
+
+    #[test]
+    fn test_nesting() {
+        // The deepest program the parser accepts
+        let source = "(".repeat(MAX_DEPTH) + &")".repeat(MAX_DEPTH);
+        let (value, errors) = program_resilient(&source);
+        assert!(errors.is_empty());
+        assert_eq!(decode(&encode(&value).unwrap()), Ok(value));
+
+        // Deeper values are encoded without recursion, but can't be decoded
+        let mut value = Value::from(1);
+        for _ in 0..100_000 {
+            value = ValueKind::List(crate::list![value]).into();
+        }
+        let data = encode(&value).unwrap();
+        assert_eq!(data.len(), 6 + 2 * 100_000 + 2);
+        assert_eq!(decode(&data), Err(Error::NestingTooDeep));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_round_trip(source in "[()'!\"\\\\ \t\n0-9a-fxob._~\u{e9}]{0,40}") {
+            let (value, _) = program_resilient(&source);
+            let data = encode(&value).unwrap();
+            let decoded = decode(&data).unwrap();
+            prop_assert_eq!(decoded.to_string(), value.to_string());
+            prop_assert_eq!(decoded, value);
+        }
+    }
+}
@@ -0,0 +1,755 @@
+//! Deserializing Rust values from spli source with serde.
+//!
+//! Top-level forms of the source make up an implicit list, so a config file
+//! can be written as keys and values without enclosing parens:
+//!
+//! ```text
+//! :name "spli"
+//! :tags ("lisp" "parser")
+//! :mode strict
+//! ```
+//!
+//! * Lists map to sequences, tuples, structs and maps. Structs and maps are
+//!   written as keys followed by values, or as lists of a key and a value,
+//!   like `((a 1) (b 2))`. A leading `:` of a symbol key is dropped.
+//! * Symbols map to unit enum variants, `true` and `false` map to booleans,
+//!   and `nil` maps to `None`. Any symbol maps to a string, `true` included. Other enum variants are lists starting with the
+//!   variant name, like `(circle 1.5)` or `(point :x 1 :y 2)`. Variant names
+//!   may also be strings.
+//! * Strings, integers and floats map to the corresponding primitives. spli
+//!   has no negative literals, so negative numbers are written as symbols
+//!   like `-5` or `-1.5`.
+
+use crate::parser::{
+    negative_number, syntax_tree, NegativeNumber, ParseError, Report, SyntaxKind, SyntaxNode,
+};
+
+use serde::de::{self, DeserializeSeed, Visitor};
+
+use std::fmt;
+use std::ops::Range;
+
+/// Error of [`from_str`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The source has syntax errors.
+    Syntax(Vec<ParseError>),
+    /// A value doesn't match the type. The range is missing only for errors
+    /// not related to any value.
+    Value {
+        message: String,
+        range: Option<Range<usize>>,
+    },
+}
+
+impl Error {
+    fn at(self, range: Range<usize>) -> Self {
+        match self {
+            Self::Value {
+                message,
+                range: None,
+            } => Self::Value {
+                message,
+                range: Some(range),
+            },
+            err => err,
+        }
+    }
+
+    /// Report of the error in `source`, read from `filename`.
+    pub fn report(&self, filename: &str, source: &str) -> Report {
+        match self {
+            Self::Syntax(errors) => Report::new(filename, source, errors),
+            Self::Value { message, range } => {
+                let range = range.clone().unwrap_or(0..0);
+                Report::uncoded(filename, source, &range, message)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax(errors) => {
+                write!(fmt, "{} syntax error(s)", errors.len())?;
+                if let Some(error) = errors.first() {
+                    write!(fmt, ", first: {} at {:?}", error.message, error.range)?;
+                }
+                Ok(())
+            }
+            Self::Value {
+                message,
+                range: Some(range),
+            } => write!(fmt, "{} at {:?}", message, range),
+            Self::Value { message, .. } => fmt.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self::Value {
+            message: msg.to_string(),
+            range: None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Node with its absolute position in the source.
+#[derive(Clone, Copy)]
+struct Node<'a> {
+    node: &'a SyntaxNode,
+    start: usize,
+}
+
+impl<'a> Node<'a> {
+    fn range(self) -> Range<usize> {
+        self.start..self.start + self.node.len
+    }
+
+    fn children(self) -> Vec<Node<'a>> {
+        self.node
+            .children()
+            .iter()
+            .map(|child| Node {
+                node: &child.node,
+                start: self.start + child.offset,
+            })
+            .collect()
+    }
+
+    fn text(self, source: &str) -> &str {
+        let inner = self.node.inner_range();
+        &source[self.start + inner.start..self.start + inner.end]
+    }
+
+    fn description(self) -> &'static str {
+        match self.node.kind {
+            SyntaxKind::Symbol => "symbol",
+            SyntaxKind::Integer(_) => "integer",
+            SyntaxKind::Float(_) => "float",
+            SyntaxKind::String(_) => "string",
+            SyntaxKind::List(_) | SyntaxKind::Program(_) => "list",
+            SyntaxKind::Error => "error",
+        }
+    }
+}
+
+/// Deserializer of a single node of a syntax tree.
+pub struct Deserializer<'a, 'de> {
+    source: &'de str,
+    node: Node<'a>,
+    /// Drop the leading `:` of symbols, which mark keys of structs and maps.
+    key: bool,
+}
+
+impl<'a, 'de> Deserializer<'a, 'de> {
+    /// Deserializer of `node`, which was parsed from `source` and starts at `start` in it.
+    pub fn new(source: &'de str, node: &'a SyntaxNode, start: usize) -> Self {
+        Self::at(source, Node { node, start }, false)
+    }
+
+    fn at(source: &'de str, node: Node<'a>, key: bool) -> Self {
+        Self { source, node, key }
+    }
+
+    fn symbol(&self) -> Option<&'de str> {
+        match self.node.node.kind {
+            SyntaxKind::Symbol => {
+                let text = self.node.text(self.source);
+                Some(if self.key {
+                    text.strip_prefix(':').unwrap_or(text)
+                } else {
+                    text
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Items of a list, or of the whole program.
+    fn items(&self) -> Option<Vec<Node<'a>>> {
+        match self.node.node.kind {
+            SyntaxKind::List(_) | SyntaxKind::Program(_) => Some(self.node.children()),
+            _ => None,
+        }
+    }
+
+    /// The only top-level form, for types that aren't lists.
+    fn single_form(&self) -> Option<Self> {
+        match self.node.node.kind {
+            SyntaxKind::Program(_) => match self.node.children().as_slice() {
+                [form] => Some(Self::at(self.source, *form, false)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn invalid(&self, expected: &str) -> Error {
+        Error::Value {
+            message: format!("expected {}, found {}", expected, self.node.description()),
+            range: Some(self.node.range()),
+        }
+    }
+
+    fn seq_access(&self, expected: &str) -> Result<SeqAccess<'a, 'de>> {
+        let items = self.items().ok_or_else(|| self.invalid(expected))?;
+        Ok(SeqAccess {
+            source: self.source,
+            items: items.into_iter(),
+        })
+    }
+
+    fn map_access(&self, expected: &str) -> Result<MapAccess<'a, 'de>> {
+        let items = self.items().ok_or_else(|| self.invalid(expected))?;
+        MapAccess::new(self.source, items)
+    }
+
+    /// Like `deserialize_any`, but if `strings` is set, symbols are read as
+    /// strings, even `true`, `nil` or `-5`.
+    fn deserialize_value<V: Visitor<'de>>(self, visitor: V, strings: bool) -> Result<V::Value> {
+        if let Some(form) = self.single_form() {
+            return form.deserialize_value(visitor, strings);
+        }
+        let range = self.node.range();
+        let result = match &self.node.node.kind {
+            SyntaxKind::Symbol => match self.symbol() {
+                Some(symbol) if strings => visitor.visit_borrowed_str(symbol),
+                Some("true") => visitor.visit_bool(true),
+                Some("false") => visitor.visit_bool(false),
+                Some("nil") => visitor.visit_none(),
+                Some(symbol) => match negative_number(symbol) {
+                    Some(NegativeNumber::Integer(n)) => visitor.visit_i64(n),
+                    Some(NegativeNumber::Float(x)) => visitor.visit_f64(x),
+                    None => visitor.visit_borrowed_str(symbol),
+                },
+                None => unreachable!("the node is a symbol"),
+            },
+            SyntaxKind::Integer(n) => visitor.visit_i64(*n),
+            SyntaxKind::Float(x) => visitor.visit_f64(*x),
+            SyntaxKind::String(s) => visitor.visit_str(s),
+            SyntaxKind::List(_) | SyntaxKind::Program(_) => {
+                visitor.visit_seq(self.seq_access("list")?)
+            }
+            SyntaxKind::Error => Err(self.invalid("value")),
+        };
+        result.map_err(|err| err.at(range))
+    }
+}
+
+/// Forwards a `deserialize_*` method to `deserialize_any`.
+macro_rules! forward_to_any {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                de::Deserializer::deserialize_any(self, visitor)
+            }
+        )*
+    };
+}
+
+impl<'a, 'de> de::Deserializer<'de> for Deserializer<'a, 'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_value(visitor, false)
+    }
+
+    forward_to_any! {
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64 deserialize_ignored_any
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_value(visitor, true)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_value(visitor, true)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if let Some(form) = self.single_form() {
+            return form.deserialize_bool(visitor);
+        }
+        match self.symbol() {
+            Some("true") => visitor.visit_bool(true),
+            Some("false") => visitor.visit_bool(false),
+            _ => Err(self.invalid("`true` or `false`")),
+        }
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if let Some(form) = self.single_form() {
+            return form.deserialize_char(visitor);
+        }
+        match &self.node.node.kind {
+            SyntaxKind::String(s) if s.chars().count() == 1 => {
+                visitor.visit_char(s.chars().next().expect("the string has a char"))
+            }
+            _ => Err(self.invalid("single-char string")),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if let Some(form) = self.single_form() {
+            return form.deserialize_bytes(visitor);
+        }
+        match &self.node.node.kind {
+            SyntaxKind::String(s) => visitor.visit_bytes(s.as_bytes()),
+            _ => Err(self.invalid("string")),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if let Some(form) = self.single_form() {
+            return form.deserialize_option(visitor);
+        }
+        let range = self.node.range();
+        match self.symbol() {
+            Some("nil") => visitor.visit_none(),
+            _ => visitor.visit_some(self).map_err(|err| err.at(range)),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if let Some(form) = self.single_form() {
+            return form.deserialize_unit(visitor);
+        }
+        match self.items() {
+            Some(items) if items.is_empty() => visitor.visit_unit(),
+            _ if self.symbol() == Some("nil") => visitor.visit_unit(),
+            _ => Err(self.invalid("`()` or `nil`")),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let range = self.node.range();
+        visitor
+            .visit_seq(self.seq_access("list")?)
+            .map_err(|err| err.at(range))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let range = self.node.range();
+        visitor
+            .visit_map(self.map_access("list of keys and values")?)
+            .map_err(|err| err.at(range))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        if let Some(form) = self.single_form() {
+            return form.deserialize_enum(name, variants, visitor);
+        }
+        let range = self.node.range();
+        let is_name = matches!(
+            self.node.node.kind,
+            SyntaxKind::Symbol | SyntaxKind::String(_)
+        );
+        let access = match self.items() {
+            _ if is_name => EnumAccess {
+                source: self.source,
+                variant: self.node,
+                content: None,
+            },
+            Some(items) if !items.is_empty() => EnumAccess {
+                source: self.source,
+                variant: items[0],
+                content: Some((self.node, items[1..].to_vec())),
+            },
+            _ => return Err(self.invalid("variant name or list starting with it")),
+        };
+        visitor.visit_enum(access).map_err(|err| err.at(range))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let range = self.node.range();
+        match (self.symbol(), &self.node.node.kind) {
+            (Some(symbol), _) => visitor.visit_borrowed_str(symbol),
+            (None, SyntaxKind::String(s)) => visitor.visit_str(s),
+            _ => Err(self.invalid("symbol")),
+        }
+        .map_err(|err| err.at(range))
+    }
+}
+
+struct SeqAccess<'a, 'de> {
+    source: &'de str,
+    items: std::vec::IntoIter<Node<'a>>,
+}
+
+impl<'a, 'de> de::SeqAccess<'de> for SeqAccess<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        match self.items.next() {
+            Some(item) => seed
+                .deserialize(Deserializer::at(self.source, item, false))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+struct MapAccess<'a, 'de> {
+    source: &'de str,
+    items: std::vec::IntoIter<Node<'a>>,
+    value: Option<Node<'a>>,
+}
+
+impl<'a, 'de> MapAccess<'a, 'de> {
+    /// Entries are either keys followed by values, or lists of a key and
+    /// a value, if the first item is a list.
+    fn new(source: &'de str, items: Vec<Node<'a>>) -> Result<Self> {
+        let is_pairs = items
+            .first()
+            .is_some_and(|item| matches!(item.node.kind, SyntaxKind::List(_)));
+        let items = if is_pairs {
+            let mut flattened = Vec::with_capacity(items.len() * 2);
+            for item in items {
+                match item.children().as_slice() {
+                    [key, value] if matches!(item.node.kind, SyntaxKind::List(_)) => {
+                        flattened.extend([*key, *value])
+                    }
+                    _ => {
+                        return Err(Error::Value {
+                            message: "expected a list of a key and a value".into(),
+                            range: Some(item.range()),
+                        })
+                    }
+                }
+            }
+            flattened
+        } else {
+            items
+        };
+        Ok(Self {
+            source,
+            items: items.into_iter(),
+            value: None,
+        })
+    }
+}
+
+impl<'a, 'de> de::MapAccess<'de> for MapAccess<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let key = match self.items.next() {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        self.value = Some(self.items.next().ok_or_else(|| Error::Value {
+            message: "missing value for the key".into(),
+            range: Some(key.range()),
+        })?);
+        seed.deserialize(Deserializer::at(self.source, key, true))
+            .map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = self.value.take().expect("value is requested after its key");
+        seed.deserialize(Deserializer::at(self.source, value, false))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len() / 2)
+    }
+}
+
+struct EnumAccess<'a, 'de> {
+    source: &'de str,
+    variant: Node<'a>,
+    /// List with the variant, and values following the variant name in it.
+    content: Option<(Node<'a>, Vec<Node<'a>>)>,
+}
+
+impl<'a, 'de> de::EnumAccess<'de> for EnumAccess<'a, 'de> {
+    type Error = Error;
+    type Variant = VariantAccess<'a, 'de>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self::Variant)> {
+        let variant = seed.deserialize(Deserializer::at(self.source, self.variant, false))?;
+        Ok((
+            variant,
+            VariantAccess {
+                source: self.source,
+                variant: self.variant,
+                content: self.content,
+            },
+        ))
+    }
+}
+
+struct VariantAccess<'a, 'de> {
+    source: &'de str,
+    variant: Node<'a>,
+    content: Option<(Node<'a>, Vec<Node<'a>>)>,
+}
+
+impl<'a, 'de> VariantAccess<'a, 'de> {
+    fn content(self, expected: &str) -> Result<(Node<'a>, Vec<Node<'a>>)> {
+        let variant = self.variant;
+        self.content.ok_or_else(|| Error::Value {
+            message: format!("expected {} after the variant name", expected),
+            range: Some(variant.range()),
+        })
+    }
+}
+
+impl<'a, 'de> de::VariantAccess<'de> for VariantAccess<'a, 'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.content {
+            None => Ok(()),
+            Some((list, _)) => Err(Error::Value {
+                message: "expected a symbol for a unit variant, found list".into(),
+                range: Some(list.range()),
+            }),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        let source = self.source;
+        match self.content("a value")? {
+            (_, items) if items.len() == 1 => {
+                seed.deserialize(Deserializer::at(source, items[0], false))
+            }
+            (list, _) => Err(Error::Value {
+                message: "expected exactly one value after the variant name".into(),
+                range: Some(list.range()),
+            }),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        let source = self.source;
+        let (_, items) = self.content("values")?;
+        visitor.visit_seq(SeqAccess {
+            source,
+            items: items.into_iter(),
+        })
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let source = self.source;
+        let (_, items) = self.content("keys and values")?;
+        visitor.visit_map(MapAccess::new(source, items)?)
+    }
+}
+
+/// Deserializes `T` from top-level forms of `source`.
+pub fn from_str<'de, T: de::Deserialize<'de>>(source: &'de str) -> Result<T> {
+    let tree = syntax_tree(source);
+    let errors = tree.collect_errors(0);
+    if !errors.is_empty() {
+        return Err(Error::Syntax(errors));
+    }
+    T::deserialize(Deserializer::new(source, &tree, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "kebab-case")]
+    enum Mode {
+        Strict,
+        Lenient,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "kebab-case")]
+    enum Shape {
+        Circle(f64),
+        Rect(u32, u32),
+        Point { x: i32, y: i32 },
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Config<'a> {
+        name: String,
+        symbol: &'a str,
+        port: u16,
+        debug: bool,
+        parent: Option<String>,
+        tags: Vec<String>,
+        mode: Mode,
+        shapes: Vec<Shape>,
+        limits: BTreeMap<String, u64>,
+    }
+
+    #[test]
+    fn test_config() {
+        let source = r#"
+            :name "spli"
+            :symbol abc
+            :port 0x1F90
+            :debug false
+            :parent nil
+            :tags ("lisp" "parser")
+            :mode strict
+            :shapes ((circle 1.5) (rect 2 3) (point :x 1 :y 2))
+            :limits (:depth 10 :width 1_000)
+        "#;
+        let config: Config = from_str(source).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                name: "spli".into(),
+                symbol: "abc",
+                port: 8080,
+                debug: false,
+                parent: None,
+                tags: vec!["lisp".into(), "parser".into()],
+                mode: Mode::Strict,
+                shapes: vec![
+                    Shape::Circle(1.5),
+                    Shape::Rect(2, 3),
+                    Shape::Point { x: 1, y: 2 }
+                ],
+                limits: vec![("depth".into(), 10), ("width".into(), 1000)]
+                    .into_iter()
+                    .collect(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_single_form() {
+        assert_eq!(from_str::<i32>("42"), Ok(42));
+        assert_eq!(from_str::<Mode>(" lenient\n"), Ok(Mode::Lenient));
+        assert_eq!(from_str::<Vec<u8>>("1 2 3"), Ok(vec![1, 2, 3]));
+        assert_eq!(from_str::<(String, f64)>("\"a\" 2"), Ok(("a".into(), 2.0)));
+    }
+
+    #[test]
+    fn test_negative_numbers() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Offset {
+            x: f64,
+            off: i32,
+        }
+
+        assert_eq!(from_str::<Offset>("x 1.0 off -1"), Ok(Offset { x: 1.0, off: -1 }));
+        assert_eq!(from_str::<Offset>("x -0.5 off 2"), Ok(Offset { x: -0.5, off: 2 }));
+        assert_eq!(from_str::<Vec<i64>>("1 -2 -0x10"), Ok(vec![1, -2, -16]));
+        assert_eq!(from_str::<(f32, String)>("-1.5 -5"), Ok((-1.5, "-5".into())));
+        let err = from_str::<u8>("-1").unwrap_err();
+        assert_eq!(
+            err,
+            Error::Value {
+                message: "invalid value: integer `-1`, expected u8".into(),
+                range: Some(0..2),
+            }
+        );
+    }
+
+    #[test]
+    fn test_symbols_as_strings() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Cfg {
+            mode: String,
+        }
+
+        assert_eq!(from_str::<Cfg>("(mode true)"), Ok(Cfg { mode: "true".into() }));
+        assert_eq!(
+            from_str::<Vec<String>>("false nil -5"),
+            Ok(vec!["false".into(), "nil".into(), "-5".into()])
+        );
+        assert_eq!(from_str::<Option<String>>("nil"), Ok(None));
+    }
+
+    #[test]
+    fn test_errors() {
+        let source = ":port 1\n:max 70000";
+        let err = from_str::<BTreeMap<String, u16>>(source).unwrap_err();
+        assert_eq!(
+            err,
+            Error::Value {
+                message: "invalid value: integer `70000`, expected u16".into(),
+                range: Some(13..18),
+            }
+        );
+        let output = err.report("config.spli", source).to_string();
+        assert!(output.contains("error: invalid value: integer `70000`, expected u16"));
+        assert!(!output.contains("[E"));
+        assert!(output.contains("config.spli:2:6"));
+
+        let range = |err| match err {
+            Error::Value { range, .. } => range,
+            Error::Syntax(_) => None,
+        };
+        let err = from_str::<Mode>("(strict)").unwrap_err();
+        assert_eq!(range(err), Some(0..8));
+        let err = from_str::<BTreeMap<String, u16>>(":a 1 :b").unwrap_err();
+        assert_eq!(range(err), Some(5..7));
+        assert!(matches!(from_str::<i32>("(1"), Err(Error::Syntax(_))));
+    }
+}
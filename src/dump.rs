@@ -0,0 +1,290 @@
+//! Dumps of parsed values in formats meant for debugging and for tools.
+
+use crate::{Value, ValueKind};
+
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+/// Output format of [`dump`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Rust `Debug` representation.
+    Debug,
+    /// Indented S-expressions with explicit types, like `(integer 1)`.
+    Sexp,
+    /// JSON array of objects with `type`, `raw`, `sequential` and `value` or `items` keys.
+    /// Numbers also have the `text` they were written as.
+    Json,
+    /// Graphviz graph of the value tree.
+    Dot,
+}
+
+impl Format {
+    pub const ALL: &'static [Self] = &[Self::Debug, Self::Sexp, Self::Json, Self::Dot];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Sexp => "sexp",
+            Self::Json => "json",
+            Self::Dot => "dot",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownFormat(pub String);
+
+impl fmt::Display for UnknownFormat {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "unknown format: {}, expected one of:", self.0)?;
+        for format in Format::ALL {
+            write!(fmt, " {}", format.name())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnknownFormat {}
+
+impl FromStr for Format {
+    type Err = UnknownFormat;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == name)
+            .ok_or_else(|| UnknownFormat(name.into()))
+    }
+}
+
+fn escape_json(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn sexp(value: &Value, indent: usize, out: &mut String) {
+    write!(out, "({}", value.kind.type_name()).unwrap();
+    if value.raw {
+        out.push_str(" :raw");
+    }
+    if value.sequential {
+        out.push_str(" :sequential");
+    }
+    match &value.kind {
+        ValueKind::List(items) => {
+            for item in items.iter() {
+                write!(out, "\n{:indent$}", "", indent = indent + 2).unwrap();
+                sexp(&item, indent + 2, out);
+            }
+        }
+        ValueKind::Error => {}
+        kind => write!(out, " {}", kind).unwrap(),
+    }
+    out.push(')');
+}
+
+/// Writes the source spelling of a number, if it's known.
+fn json_text(text: Option<&str>, out: &mut String) {
+    if let Some(text) = text {
+        out.push_str(",\"text\":");
+        escape_json(text, out);
+    }
+}
+
+fn json(value: &Value, out: &mut String) {
+    write!(
+        out,
+        "{{\"type\":\"{}\",\"raw\":{},\"sequential\":{}",
+        value.kind.type_name(),
+        value.raw,
+        value.sequential
+    )
+    .unwrap();
+    match &value.kind {
+        ValueKind::Symbol(s) => {
+            out.push_str(",\"value\":");
+            escape_json(s, out);
+        }
+        ValueKind::String(s) => {
+            out.push_str(",\"value\":");
+            escape_json(s, out);
+        }
+        ValueKind::Native(native) => {
+            out.push_str(",\"value\":");
+            escape_json(&native.to_string(), out);
+        }
+        ValueKind::Integer(n) => {
+            write!(out, ",\"value\":{}", n.value).unwrap();
+            json_text(n.text(), out);
+        }
+        ValueKind::Float(x) => {
+            if x.value.is_finite() {
+                write!(out, ",\"value\":{:?}", x.value).unwrap();
+            } else {
+                out.push_str(",\"value\":null");
+            }
+            json_text(x.text(), out);
+        }
+        ValueKind::List(items) => {
+            out.push_str(",\"items\":[");
+            for (idx, item) in items.iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                json(&item, out);
+            }
+            out.push(']');
+        }
+        ValueKind::Error => {}
+    }
+    out.push('}');
+}
+
+/// Writes nodes of the `value` subtree, returns the id of its root.
+fn dot(value: &Value, next_id: &mut usize, out: &mut String) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    let mut label = value.kind.type_name().to_string();
+    match &value.kind {
+        ValueKind::List(_) | ValueKind::Error => {}
+        kind => write!(label, "\n{}", kind).unwrap(),
+    }
+    let mut annotations = Vec::new();
+    if value.raw {
+        annotations.push("raw");
+    }
+    if value.sequential {
+        annotations.push("sequential");
+    }
+    if !annotations.is_empty() {
+        write!(label, "\n[{}]", annotations.join(", ")).unwrap();
+    }
+    let style = if annotations.is_empty() { "" } else { ", style=bold" };
+    writeln!(out, "  n{} [label=\"{}\"{}];", id, escape_dot(&label), style).unwrap();
+    if let ValueKind::List(items) = &value.kind {
+        for item in items.iter() {
+            let child = dot(&item, next_id, out);
+            writeln!(out, "  n{} -> n{};", id, child).unwrap();
+        }
+    }
+    id
+}
+
+/// Dumps top-level `forms` in the given format, e.g. the items of a program or
+/// a single value.
+pub fn dump<'v, 'a: 'v>(forms: impl IntoIterator<Item = &'v Value<'a>>, format: Format) -> String {
+    let forms = forms.into_iter();
+    let mut out = String::new();
+    match format {
+        Format::Debug => {
+            for form in forms {
+                writeln!(out, "{:#?}", form).unwrap();
+            }
+        }
+        Format::Sexp => {
+            for form in forms {
+                sexp(form, 0, &mut out);
+                out.push('\n');
+            }
+        }
+        Format::Json => {
+            out.push('[');
+            for (idx, form) in forms.enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                json(form, &mut out);
+            }
+            out.push_str("]\n");
+        }
+        Format::Dot => {
+            out.push_str("digraph spli {\n  node [shape=box];\n");
+            let mut next_id = 0;
+            for form in forms {
+                dot(form, &mut next_id, &mut out);
+            }
+            out.push_str("}\n");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::program_resilient;
+
+    fn dump_source(source: &str, format: Format) -> String {
+        let (program, errors) = program_resilient(source);
+        assert!(errors.is_empty());
+        match &program.kind {
+            ValueKind::List(forms) => dump(forms.values(), format),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_sexp() {
+        assert_eq!(
+            dump_source("('f 1.0 \"a\\n\") x", Format::Sexp),
+            "(list\n  (symbol :raw f)\n  (float 1.0)\n  (string \"a\\n\"))\n(symbol x)\n"
+        );
+        assert_eq!(dump(Some(&Value::from(1)), Format::Sexp), "(integer 1)\n");
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            dump_source("!(\"\\\"\" 0b10)", Format::Json),
+            concat!(
+                r#"[{"type":"list","raw":false,"sequential":true,"items":["#,
+                r#"{"type":"string","raw":false,"sequential":false,"value":"\""},"#,
+                r#"{"type":"integer","raw":false,"sequential":false,"value":2,"text":"0b10"}]}]"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_dot() {
+        assert_eq!(
+            dump_source("('a)", Format::Dot),
+            concat!(
+                "digraph spli {\n",
+                "  node [shape=box];\n",
+                "  n0 [label=\"list\"];\n",
+                "  n1 [label=\"symbol\\na\\n[raw]\", style=bold];\n",
+                "  n0 -> n1;\n",
+                "}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_format_from_str() {
+        assert_eq!("dot".parse(), Ok(Format::Dot));
+        assert_eq!(
+            "xml".parse::<Format>().unwrap_err().to_string(),
+            "unknown format: xml, expected one of: debug sexp json dot"
+        );
+    }
+}
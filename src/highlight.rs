@@ -0,0 +1,226 @@
+//! Syntax highlighting of spli source with ANSI colors.
+
+use crate::parser::{syntax_tree, SyntaxKind, SyntaxNode};
+
+use termcolor::{Ansi, Color, ColorSpec, WriteColor};
+
+use std::io::Write;
+use std::ops::Range;
+
+/// Lexical category of a highlighted piece of source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Paren,
+    Modifier,
+    Symbol,
+    Number,
+    String,
+    /// Token that failed to parse.
+    Error,
+}
+
+/// Colors used for every [`Class`].
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub paren: ColorSpec,
+    pub modifier: ColorSpec,
+    pub symbol: ColorSpec,
+    pub number: ColorSpec,
+    pub string: ColorSpec,
+    pub error: ColorSpec,
+    /// Paren matching the one at the cursor.
+    pub matching_paren: ColorSpec,
+}
+
+impl Theme {
+    pub fn colored() -> Self {
+        let mut modifier = ColorSpec::new();
+        let mut number = ColorSpec::new();
+        let mut string = ColorSpec::new();
+        let mut error = ColorSpec::new();
+        let mut matching_paren = ColorSpec::new();
+        modifier.set_bold(true).set_fg(Some(Color::Magenta));
+        number.set_fg(Some(Color::Cyan));
+        string.set_fg(Some(Color::Green));
+        error.set_fg(Some(Color::Red)).set_underline(true);
+        matching_paren.set_bold(true).set_fg(Some(Color::Blue));
+        Self {
+            paren: ColorSpec::new(),
+            modifier,
+            symbol: ColorSpec::new(),
+            number,
+            string,
+            error,
+            matching_paren,
+        }
+    }
+
+    fn spec(&self, class: Class) -> &ColorSpec {
+        match class {
+            Class::Paren => &self.paren,
+            Class::Modifier => &self.modifier,
+            Class::Symbol => &self.symbol,
+            Class::Number => &self.number,
+            Class::String => &self.string,
+            Class::Error => &self.error,
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::colored()
+    }
+}
+
+fn collect(node: &SyntaxNode, start: usize, result: &mut Vec<(Range<usize>, Class)>) {
+    let inner = node.inner_range();
+    if let SyntaxKind::Error = node.kind {
+        result.push((start..start + node.len, Class::Error));
+        return;
+    }
+    let is_program = matches!(node.kind, SyntaxKind::Program(_));
+    if !is_program && (node.raw || node.sequential) {
+        result.push((start..start + 1, Class::Modifier));
+    }
+    let class = match node.kind {
+        SyntaxKind::Symbol => Class::Symbol,
+        SyntaxKind::Integer(_) | SyntaxKind::Float(_) => Class::Number,
+        SyntaxKind::String(_) => Class::String,
+        SyntaxKind::List(_) | SyntaxKind::Program(_) | SyntaxKind::Error => {
+            let is_list = matches!(node.kind, SyntaxKind::List(_));
+            if is_list {
+                result.push((start + inner.start - 1..start + inner.start, Class::Paren));
+            }
+            for child in node.children() {
+                collect(&child.node, start + child.offset, result);
+            }
+            if is_list && node.is_closed_list() {
+                result.push((start + inner.end..start + node.len, Class::Paren));
+            }
+            return;
+        }
+    };
+    result.push((start + inner.start..start + inner.end, class));
+}
+
+/// Classified pieces of `source`, in order. Whitespace isn't included.
+pub fn tokens(source: &str) -> Vec<(Range<usize>, Class)> {
+    let mut result = Vec::new();
+    collect(&syntax_tree(source), 0, &mut result);
+    result
+}
+
+/// Offset of the paren matching the one at `pos` or, failing that, right
+/// before it, like editors show it while typing. Parens inside strings
+/// aren't matched.
+pub fn matching_paren(source: &str, pos: usize) -> Option<usize> {
+    fn find(node: &SyntaxNode, start: usize, pos: usize) -> Option<usize> {
+        for child in node.children() {
+            let child_start = start + child.offset;
+            if child_start > pos {
+                break;
+            }
+            if let SyntaxKind::List(_) = child.node.kind {
+                let inner = child.node.inner_range();
+                let open = child_start + inner.start - 1;
+                let close = child_start + inner.end;
+                if child.node.is_closed_list() && pos == open {
+                    return Some(close);
+                }
+                if child.node.is_closed_list() && pos == close {
+                    return Some(open);
+                }
+                if let Some(found) = find(&child.node, child_start, pos) {
+                    return Some(found);
+                }
+            }
+        }
+        None
+    }
+
+    let tree = syntax_tree(source);
+    find(&tree, 0, pos).or_else(|| find(&tree, 0, pos.checked_sub(1)?))
+}
+
+/// Wraps pieces of `source` into ANSI escape sequences. A paren at
+/// `emphasized` is highlighted as [`Theme::matching_paren`].
+pub fn highlight_with(source: &str, theme: &Theme, emphasized: Option<usize>) -> String {
+    let mut output = Ansi::new(Vec::with_capacity(source.len() * 2));
+    let mut last = 0;
+    // Writing into a `Vec` can't fail
+    let mut write = |output: &mut Ansi<Vec<u8>>, range: Range<usize>, spec: &ColorSpec| {
+        output.write_all(&source.as_bytes()[last..range.start]).unwrap();
+        if spec.is_none() {
+            output.write_all(&source.as_bytes()[range.clone()]).unwrap();
+        } else {
+            output.set_color(spec).unwrap();
+            output.write_all(&source.as_bytes()[range.clone()]).unwrap();
+            output.reset().unwrap();
+        }
+        last = range.end;
+    };
+    for (range, class) in tokens(source) {
+        let spec = match (class, emphasized) {
+            (Class::Paren, Some(pos)) if range.start == pos => &theme.matching_paren,
+            _ => theme.spec(class),
+        };
+        write(&mut output, range, spec);
+    }
+    write(&mut output, source.len()..source.len(), &ColorSpec::new());
+    String::from_utf8(output.into_inner()).expect("only valid UTF-8 was written")
+}
+
+/// Wraps pieces of `source` into ANSI escape sequences.
+pub fn highlight(source: &str, theme: &Theme) -> String {
+    highlight_with(source, theme, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens() {
+        let classes: Vec<_> = tokens("('a 0x1 \"(\") )")
+            .into_iter()
+            .map(|(range, class)| (range.start, class))
+            .collect();
+        assert_eq!(
+            classes,
+            vec![
+                (0, Class::Paren),
+                (1, Class::Modifier),
+                (2, Class::Symbol),
+                (4, Class::Number),
+                (8, Class::String),
+                (11, Class::Paren),
+                (13, Class::Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_matching_paren() {
+        let source = "(a \"(\" '(b))";
+        assert_eq!(matching_paren(source, 0), Some(11));
+        assert_eq!(matching_paren(source, 12), Some(0));
+        assert_eq!(matching_paren(source, 8), Some(10));
+        assert_eq!(matching_paren(source, 4), None);
+        assert_eq!(matching_paren("(a", 0), None);
+    }
+
+    #[test]
+    fn test_highlight() {
+        let mut theme = Theme::colored();
+        theme.symbol.set_fg(Some(Color::Yellow));
+        assert_eq!(
+            highlight("(x 1)", &theme),
+            "(\x1b[0m\x1b[33mx\x1b[0m \x1b[0m\x1b[36m1\x1b[0m)"
+        );
+        assert_eq!(
+            highlight_with("(a)", &Theme::colored(), Some(2)),
+            "(a\x1b[0m\x1b[1m\x1b[34m)\x1b[0m"
+        );
+    }
+}
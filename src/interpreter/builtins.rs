@@ -0,0 +1,155 @@
+//! Functions available in every interpreter.
+
+use super::{from_args, is_truthy, nil, Error, Interpreter};
+use crate::list::List;
+use crate::{Value, ValueKind};
+
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy)]
+enum Numeric {
+    Integer(i64),
+    Float(f64),
+}
+
+impl Numeric {
+    fn of(value: &Value) -> Result<Self, Error> {
+        match value.kind {
+            ValueKind::Integer(n) => Ok(Self::Integer(n.value)),
+            ValueKind::Float(x) => Ok(Self::Float(x.value)),
+            ref kind => Err(Error::mismatch("number", kind)),
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Self::Integer(n) => n as f64,
+            Self::Float(x) => x,
+        }
+    }
+
+    fn into_value<'a>(self) -> Value<'a> {
+        match self {
+            Self::Integer(n) => n.into(),
+            Self::Float(x) => x.into(),
+        }
+    }
+}
+
+/// Folds numbers, switching to floats once there is a float.
+fn fold(
+    init: Numeric,
+    args: &[Value],
+    integer: fn(i64, i64) -> Option<i64>,
+    float: fn(f64, f64) -> f64,
+) -> Result<Numeric, Error> {
+    args.iter().try_fold(init, |acc, arg| {
+        Ok(match (acc, Numeric::of(arg)?) {
+            (Numeric::Integer(a), Numeric::Integer(b)) => Numeric::Integer(
+                integer(a, b)
+                    .ok_or_else(|| Error::custom("integer overflow or division by zero"))?,
+            ),
+            (a, b) => Numeric::Float(float(a.as_f64(), b.as_f64())),
+        })
+    })
+}
+
+/// Arithmetic of at least one argument, `(op x)` is `(op unit x)`.
+fn arithmetic<'a>(
+    unit: i64,
+    args: &[Value<'a>],
+    integer: fn(i64, i64) -> Option<i64>,
+    float: fn(f64, f64) -> f64,
+) -> Result<Value<'a>, Error> {
+    let result = match args {
+        [] => {
+            return Err(Error::Arity {
+                function: None,
+                expected: 1,
+                got: 0,
+            })
+        }
+        [arg] => fold(
+            Numeric::Integer(unit),
+            std::slice::from_ref(arg),
+            integer,
+            float,
+        )?,
+        [first, rest @ ..] => fold(Numeric::of(first)?, rest, integer, float)?,
+    };
+    Ok(result.into_value())
+}
+
+/// Whether every pair of adjacent numbers is ordered as `accept`s.
+fn compare<'a>(args: &[Value<'a>], accept: fn(Ordering) -> bool) -> Result<Value<'a>, Error> {
+    let numbers = args
+        .iter()
+        .map(Numeric::of)
+        .collect::<Result<Vec<_>, _>>()?;
+    let ordered = numbers.windows(2).all(|pair| {
+        let ordering = match (pair[0], pair[1]) {
+            (Numeric::Integer(a), Numeric::Integer(b)) => Some(a.cmp(&b)),
+            (a, b) => a.as_f64().partial_cmp(&b.as_f64()),
+        };
+        ordering.is_some_and(accept)
+    });
+    Ok(ordered.into())
+}
+
+pub(super) fn register(interpreter: &mut Interpreter) {
+    interpreter.register_fn("+", |args: &[Value]| {
+        fold(Numeric::Integer(0), args, i64::checked_add, |a, b| a + b).map(Numeric::into_value)
+    });
+    interpreter.register_fn("*", |args: &[Value]| {
+        fold(Numeric::Integer(1), args, i64::checked_mul, |a, b| a * b).map(Numeric::into_value)
+    });
+    interpreter.register_fn("-", |args: &[Value]| {
+        arithmetic(0, args, i64::checked_sub, |a, b| a - b)
+    });
+    interpreter.register_fn("/", |args: &[Value]| {
+        arithmetic(1, args, i64::checked_div, |a, b| a / b)
+    });
+    interpreter.register_fn("%", |args: &[Value]| {
+        let (a, b): (i64, i64) = from_args(args)?;
+        a.checked_rem(b)
+            .ok_or_else(|| Error::custom("integer overflow or division by zero"))
+    });
+
+    interpreter.register_fn("eq", |args: &[Value]| {
+        Ok(args.windows(2).all(|pair| pair[0] == pair[1]))
+    });
+    interpreter.register_fn("lt", |args: &[Value]| compare(args, Ordering::is_lt));
+    interpreter.register_fn("gt", |args: &[Value]| compare(args, Ordering::is_gt));
+    interpreter.register_fn("le", |args: &[Value]| compare(args, Ordering::is_le));
+    interpreter.register_fn("ge", |args: &[Value]| compare(args, Ordering::is_ge));
+    interpreter.register_fn("not", |args: &[Value]| {
+        let (value,): (Value,) = from_args(args)?;
+        Ok(!is_truthy(&value))
+    });
+
+    interpreter.register_fn("list", |args: &[Value]| {
+        Ok(ValueKind::List(List::from_double_ended_iter(args.to_vec())))
+    });
+    interpreter.register_fn("cons", |args: &[Value]| {
+        let (head, tail): (Value, List<Value>) = from_args(args)?;
+        Ok(ValueKind::List(tail.cons(head)))
+    });
+    interpreter.register_fn("head", |args: &[Value]| {
+        let (list,): (List<Value>,) = from_args(args)?;
+        Ok(list.head().map_or_else(nil, |head| (*head).clone()))
+    });
+    interpreter.register_fn("tail", |args: &[Value]| {
+        let (list,): (List<Value>,) = from_args(args)?;
+        Ok(ValueKind::List(list.tail().unwrap_or_default()))
+    });
+    interpreter.register_fn("len", |args: &[Value]| match args {
+        [Value {
+            kind: ValueKind::String(s),
+            ..
+        }] => Ok(s.chars().count() as i64),
+        _ => {
+            let (list,): (List<Value>,) = from_args(args)?;
+            Ok(list.len() as i64)
+        }
+    });
+}
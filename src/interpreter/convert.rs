@@ -0,0 +1,105 @@
+//! Conversion of arguments of native functions.
+
+use super::Error;
+use crate::list::List;
+use crate::native::Native;
+use crate::{Value, ValueKind};
+
+use std::convert::TryFrom;
+
+impl<'a> TryFrom<Value<'a>> for i64 {
+    type Error = Error;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Error> {
+        match value.into_kind() {
+            ValueKind::Integer(n) => Ok(n.value),
+            kind => Err(Error::mismatch("integer", &kind)),
+        }
+    }
+}
+
+impl<'a> TryFrom<Value<'a>> for f64 {
+    type Error = Error;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Error> {
+        match value.into_kind() {
+            ValueKind::Float(x) => Ok(x.value),
+            kind => Err(Error::mismatch("float", &kind)),
+        }
+    }
+}
+
+impl<'a> TryFrom<Value<'a>> for String {
+    type Error = Error;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Error> {
+        match value.into_kind() {
+            ValueKind::String(s) => Ok(s),
+            kind => Err(Error::mismatch("string", &kind)),
+        }
+    }
+}
+
+impl<'a> TryFrom<Value<'a>> for List<Value<'a>> {
+    type Error = Error;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Error> {
+        match value.into_kind() {
+            ValueKind::List(list) => Ok(list),
+            kind => Err(Error::mismatch("list", &kind)),
+        }
+    }
+}
+
+impl<'a> TryFrom<Value<'a>> for Native {
+    type Error = Error;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Error> {
+        match value.into_kind() {
+            ValueKind::Native(native) => Ok(native),
+            kind => Err(Error::mismatch("native", &kind)),
+        }
+    }
+}
+
+/// Arguments of a native function, converted from values with `TryFrom`.
+pub trait FromArgs<'a>: Sized {
+    fn from_args(args: &[Value<'a>]) -> Result<Self, Error>;
+}
+
+impl<'a> FromArgs<'a> for Vec<Value<'a>> {
+    fn from_args(args: &[Value<'a>]) -> Result<Self, Error> {
+        Ok(args.to_vec())
+    }
+}
+
+macro_rules! tuple_from_args {
+    ($count:expr => $($ty:ident $arg:ident),*) => {
+        impl<'a, $($ty),*> FromArgs<'a> for ($($ty,)*)
+        where
+            $($ty: TryFrom<Value<'a>>, Error: From<<$ty as TryFrom<Value<'a>>>::Error>),*
+        {
+            fn from_args(args: &[Value<'a>]) -> Result<Self, Error> {
+                match args {
+                    [$($arg),*] => Ok(($($ty::try_from($arg.clone())?,)*)),
+                    _ => Err(Error::Arity {
+                        function: None,
+                        expected: $count,
+                        got: args.len(),
+                    }),
+                }
+            }
+        }
+    };
+}
+
+tuple_from_args!(0 =>);
+tuple_from_args!(1 => A a);
+tuple_from_args!(2 => A a, B b);
+tuple_from_args!(3 => A a, B b, C c);
+tuple_from_args!(4 => A a, B b, C c, D d);
+
+/// Converts arguments of a native function, e.g. `let (n, s): (i64, String) = from_args(args)?`.
+pub fn from_args<'a, T: FromArgs<'a>>(args: &[Value<'a>]) -> Result<T, Error> {
+    T::from_args(args)
+}
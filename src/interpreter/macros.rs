@@ -0,0 +1,301 @@
+//! Macro expansion with hygienic renaming of bindings introduced by macros.
+
+use super::{items, symbol, Error, Interpreter, Step};
+use crate::list::List;
+use crate::{Value, ValueKind};
+
+use std::collections::HashSet;
+
+/// Marks of macro expansions, and the marked names they made.
+#[derive(Default)]
+pub(super) struct Marks {
+    /// Mark of the last expansion.
+    last: usize,
+    /// Marked names. The parser doesn't accept `~` in symbols, so they can't
+    /// clash with written names. Boxed names don't move when the set grows,
+    /// and are only freed with the interpreter.
+    names: HashSet<Box<str>>,
+}
+
+impl Marks {
+    /// Mark of a new expansion.
+    fn next(&mut self) -> usize {
+        self.last += 1;
+        self.last
+    }
+
+    /// Removes `mark` from `name` if it's its last mark, adds it otherwise.
+    fn toggle<'a>(&mut self, name: &'a str, mark: usize) -> &'a str {
+        let suffix = format!("~{}", mark);
+        if let Some(unmarked) = name.strip_suffix(suffix.as_str()) {
+            return unmarked;
+        }
+        let marked = name.to_owned() + &suffix;
+        let interned: *const str = match self.names.get(marked.as_str()) {
+            Some(interned) => &**interned,
+            None => {
+                let boxed = marked.into_boxed_str();
+                let interned: *const str = &*boxed;
+                self.names.insert(boxed);
+                interned
+            }
+        };
+        // SAFETY: names are never removed from the set, so they live as long
+        // as the interpreter. They only end up in expanded forms and macro
+        // arguments, which the interpreter and the VM that owns it keep, and
+        // `Interpreter::expand` ties its result to the interpreter.
+        unsafe { &*interned }
+    }
+
+    /// Toggles `mark` on every symbol of a value.
+    fn toggle_all<'a>(&mut self, value: &Value<'a>, mark: usize) -> Value<'a> {
+        match &value.kind {
+            ValueKind::Symbol(name) => Value {
+                kind: ValueKind::Symbol(self.toggle(name, mark)),
+                ..*value
+            },
+            ValueKind::List(items) => {
+                let items = items
+                    .values()
+                    .map(|item| self.toggle_all(item, mark))
+                    .collect();
+                list_like(value, items)
+            }
+            _ => value.clone(),
+        }
+    }
+}
+
+/// Name without its marks.
+fn unmarked(name: &str) -> &str {
+    name.split('~').next().unwrap_or(name)
+}
+
+/// Value with the marks removed from all its symbols.
+fn unmark_all<'a>(value: &Value<'a>) -> Value<'a> {
+    match &value.kind {
+        ValueKind::Symbol(name) => Value {
+            kind: ValueKind::Symbol(unmarked(name)),
+            ..*value
+        },
+        ValueKind::List(items) => list_like(value, items.values().map(unmark_all).collect()),
+        _ => value.clone(),
+    }
+}
+
+/// List with the modifiers of `template`.
+fn list_like<'a>(template: &Value<'a>, items: Vec<Value<'a>>) -> Value<'a> {
+    Value {
+        kind: ValueKind::List(List::from_double_ended_iter(items)),
+        ..*template
+    }
+}
+
+/// Adds the name bound by `name` to the scope.
+fn bind<'a>(name: &Value<'a>, scope: &mut HashSet<&'a str>) -> Value<'a> {
+    if let Some(symbol) = symbol(name) {
+        scope.insert(symbol);
+    }
+    name.clone()
+}
+
+fn resolve_all<'a>(items: &[&Value<'a>], scope: &HashSet<&'a str>) -> Vec<Value<'a>> {
+    items.iter().map(|item| resolve(item, scope)).collect()
+}
+
+/// Removes the marks of the symbols of an expanded form that aren't bound in
+/// the form, so that they refer to globals, functions and special forms.
+/// Quoted values lose all their marks.
+fn resolve<'a>(value: &Value<'a>, scope: &HashSet<&'a str>) -> Value<'a> {
+    if value.raw {
+        return unmark_all(value);
+    }
+    if let Some(symbol) = symbol(value) {
+        if scope.contains(symbol) {
+            return value.clone();
+        }
+        return Value {
+            kind: ValueKind::Symbol(unmarked(symbol)),
+            ..*value
+        };
+    }
+    let forms = match items(value) {
+        Some(forms) => forms,
+        None => return value.clone(),
+    };
+    let head = forms.first().and_then(|head| symbol(head)).map(unmarked);
+    let bindings = forms.get(1).and_then(|bindings| items(bindings));
+    let (unmarked_head, bindings) = match (head, bindings) {
+        (Some(head), Some(bindings)) if !value.sequential => (head, bindings),
+        _ => return list_like(value, resolve_all(&forms, scope)),
+    };
+    let head = resolve(forms[0], scope);
+    let mut scope = scope.clone();
+    let bindings = match unmarked_head {
+        // Bindings see the previous ones, like in `Interpreter::eval_let`
+        "let" => bindings
+            .iter()
+            .map(|binding| match items(binding).as_deref() {
+                Some([name, value]) => {
+                    let value = resolve(value, &scope);
+                    list_like(binding, vec![bind(name, &mut scope), value])
+                }
+                _ => resolve(binding, &scope),
+            })
+            .collect(),
+        "defn" | "defmacro" => match bindings.split_first() {
+            Some((name, params)) => std::iter::once(resolve(name, &scope))
+                .chain(params.iter().map(|param| bind(param, &mut scope)))
+                .collect(),
+            None => Vec::new(),
+        },
+        // Closures of the VM
+        "fn" => bindings
+            .iter()
+            .map(|param| bind(param, &mut scope))
+            .collect(),
+        _ => return list_like(value, resolve_all(&forms, &scope)),
+    };
+    let mut result = vec![head, list_like(forms[1], bindings)];
+    result.extend(resolve_all(&forms[2..], &scope));
+    list_like(value, result)
+}
+
+impl<'a> Interpreter<'a> {
+    /// Expands macro calls in a form, until there are none left.
+    ///
+    /// A macro defined with `(defmacro (name params…) body…)` is called with
+    /// unevaluated arguments, and returns the code to replace the call, e.g.
+    /// `(defmacro (unless c body) (list 'if c nil body))`. Expansion is
+    /// hygienic: every expansion marks the symbols the macro introduced, as
+    /// `name~n`, and a name bound by a `let`, `defn`, `defmacro` or `fn` form
+    /// only binds the symbols with the same marks, so bindings of a macro
+    /// don't capture the symbols passed to it. The marks of the symbols that
+    /// aren't bound are removed once the form is expanded.
+    ///
+    /// Quoted values, names being defined and names being bound aren't
+    /// expanded.
+    pub fn expand(&mut self, value: &Value<'a>) -> Result<Value<'_>, Error> {
+        self.expand_form(value)
+    }
+
+    /// Like [`Interpreter::expand`], but the marked names of the result are
+    /// owned by the interpreter, so it must not outlive it.
+    pub(crate) fn expand_form(&mut self, value: &Value<'a>) -> Result<Value<'a>, Error> {
+        let expanded = self.expand_at(value)?;
+        Ok(resolve(&expanded, &HashSet::new()))
+    }
+
+    fn expand_all(&mut self, items: &[&Value<'a>]) -> Result<Vec<Value<'a>>, Error> {
+        items.iter().map(|item| self.expand_at(item)).collect()
+    }
+
+    fn expand_at(&mut self, value: &Value<'a>) -> Result<Value<'a>, Error> {
+        let forms = match items(value) {
+            Some(forms) => forms,
+            None => return Ok(value.clone()),
+        };
+        let head = match forms.first().and_then(|head| symbol(head)) {
+            Some(head) if !value.sequential => unmarked(head),
+            _ => return Ok(list_like(value, self.expand_all(&forms)?)),
+        };
+        if let Some(definition) = self.macros.get(head).cloned() {
+            // Arguments are marked too, so that marking the expansion again
+            // removes their marks
+            let mark = self.marks.next();
+            let args = forms[1..]
+                .iter()
+                .map(|arg| self.marks.toggle_all(arg, mark))
+                .collect();
+            let expansion = self.trampoline(Step::Call(definition, args))?;
+            let expansion = self.marks.toggle_all(&expansion, mark);
+            return self.expand_at(&expansion);
+        }
+        let kept = match head {
+            "defn" | "defmacro" | "fn" => 2,
+            "let" => {
+                let bindings = match forms.get(1).and_then(|bindings| items(bindings)) {
+                    Some(bindings) => bindings,
+                    None => return Ok(list_like(value, self.expand_all(&forms)?)),
+                };
+                let bindings = bindings
+                    .iter()
+                    .map(|binding| match items(binding).as_deref() {
+                        Some([name, value]) => Ok(list_like(
+                            binding,
+                            vec![(**name).clone(), self.expand_at(value)?],
+                        )),
+                        _ => Ok((**binding).clone()),
+                    })
+                    .collect::<Result<_, Error>>()?;
+                let mut result = vec![(*forms[0]).clone(), list_like(forms[1], bindings)];
+                result.extend(self.expand_all(&forms[2..])?);
+                return Ok(list_like(value, result));
+            }
+            _ => 1,
+        };
+        let kept = kept.min(forms.len());
+        let mut result: Vec<_> = forms[..kept].iter().map(|form| (**form).clone()).collect();
+        result.extend(self.expand_all(&forms[kept..])?);
+        Ok(list_like(value, result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::program_resilient;
+
+    const MACROS: &str = "
+        (defmacro (unless c body) (list 'if c nil body))
+        (defmacro (my-or a b) (list 'let (list (list 't a)) (list 'if 't 't b)))
+    ";
+
+    #[test]
+    fn test_expand() {
+        let mut interpreter = Interpreter::new();
+        interpreter.run(MACROS).unwrap();
+        let (program, _) = program_resilient("(my-or x (unless t '(my-or y)))");
+        assert_eq!(
+            interpreter.expand(&program).unwrap().to_string(),
+            "!((let ((t~1 x)) (if t~1 t~1 (if t nil '(my-or y)))))"
+        );
+        assert_eq!(interpreter.run("(unless false 1)"), Ok(1.into()));
+    }
+
+    #[test]
+    fn test_hygiene() {
+        let mut interpreter = Interpreter::new();
+        interpreter.run(MACROS).unwrap();
+        assert_eq!(
+            interpreter.run("(let ((t 5)) (my-or false t))"),
+            Ok(5.into())
+        );
+        let source = "(defn (f t) (my-or (my-or nil false) t)) (f 7)";
+        assert_eq!(interpreter.run(source), Ok(7.into()));
+        // Template symbols passed on to another macro keep their origin
+        let source = "
+            (defmacro (bind-one name body) (list 'let (list (list name 1)) body))
+            (defmacro (m body) (list 'bind-one 'tmp (list 'list 'tmp body)))
+            (let ((tmp 5)) (m tmp))
+        ";
+        assert_eq!(interpreter.run(source).unwrap().to_string(), "(1 5)");
+    }
+
+    #[test]
+    fn test_definitions() {
+        let mut interpreter = Interpreter::new();
+        let source = "
+            (defmacro (defsquare name) (list 'defn (list name 'x) (list '* 'x 'x)))
+            (defsquare sq)
+            (defmacro (unless c) c)
+            (defn (unless x) x)
+            (sq 4)
+        ";
+        assert_eq!(interpreter.run(source), Ok(16.into()));
+        assert_eq!(
+            interpreter.run("(defsquare)").unwrap_err().to_string(),
+            "`defsquare` takes 1 arguments, got 0"
+        );
+    }
+}
@@ -0,0 +1,669 @@
+//! Tree-walking evaluator of spli programs.
+//!
+//! * Integers, floats and strings evaluate to themselves, and so do the
+//!   symbols `true`, `false` and `nil`. Other symbols are variables.
+//! * `'` quotes a value: `'x` and `'(a b)` evaluate to the symbol and the list.
+//! * `!(a b c)` evaluates the forms in order and gives the last one, like the
+//!   top level of a program does.
+//! * Other lists are calls of special forms or functions: `(def name value)`,
+//!   `(defn (name params…) body…)`, `(defmacro (name params…) body…)`,
+//!   `(if cond then else)`, `(let ((name value)…) body…)` and `(name args…)`.
+//! * `false` and `nil` are false, everything else is true.
+//!
+//! Calls in tail position, i.e. the last forms of bodies and branches of `if`,
+//! don't grow the stack, so loops can be written as recursive functions. Other
+//! calls and arguments nest evaluation, at most [`MAX_DEPTH`] levels deep.
+//!
+//! Functions have their own namespace, so a variable doesn't shadow a function
+//! of the same name. Native functions are added with
+//! [`Interpreter::register_fn`]. Macros are described in [`Interpreter::expand`].
+
+mod builtins;
+mod convert;
+mod macros;
+
+pub use convert::{from_args, FromArgs};
+
+use crate::list::List;
+use crate::parser::{program_resilient, ParseError};
+use crate::{Value, ValueKind};
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::rc::Rc;
+
+/// How deep evaluation can be nested by default, e.g. by calls out of tail
+/// position. Deeper evaluation fails with [`Error::NestingTooDeep`] instead of
+/// overflowing the stack: every level takes a few KiB of it.
+pub const MAX_DEPTH: usize = 128;
+
+/// Error of evaluation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The source has syntax errors.
+    Syntax(Vec<ParseError>),
+    UnboundVariable(String),
+    UnboundFunction(String),
+    /// Called value isn't a function name, holds its type name.
+    NotCallable(&'static str),
+    /// Wrong number of arguments. The function is missing for errors of
+    /// [`from_args`], until they're returned from the native function.
+    Arity {
+        function: Option<String>,
+        expected: usize,
+        got: usize,
+    },
+    /// Value has a wrong type, both are type names.
+    Mismatch {
+        expected: &'static str,
+        got: &'static str,
+    },
+    /// Malformed special form.
+    InvalidForm(&'static str),
+    /// Evaluation is nested deeper than the limit, see [`MAX_DEPTH`].
+    NestingTooDeep(usize),
+    /// Error of a native function.
+    Custom(String),
+}
+
+impl Error {
+    /// `expected` type, but got a value of `kind`.
+    pub fn mismatch(expected: &'static str, kind: &ValueKind) -> Self {
+        Self::Mismatch {
+            expected,
+            got: kind.type_name(),
+        }
+    }
+
+    pub fn custom(message: impl Into<String>) -> Self {
+        Self::Custom(message.into())
+    }
+
+    pub(crate) fn in_function(self, name: &str) -> Self {
+        match self {
+            Self::Arity {
+                function: None,
+                expected,
+                got,
+            } => Self::Arity {
+                function: Some(name.into()),
+                expected,
+                got,
+            },
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax(errors) => match errors.first() {
+                Some(error) => write!(fmt, "syntax error: {}", error.message),
+                None => fmt.write_str("syntax error"),
+            },
+            Self::UnboundVariable(name) => write!(fmt, "unbound variable `{}`", name),
+            Self::UnboundFunction(name) => write!(fmt, "unknown function `{}`", name),
+            Self::NotCallable(type_name) => write!(fmt, "{} can't be called", type_name),
+            Self::Arity {
+                function: Some(function),
+                expected,
+                got,
+            } => write!(
+                fmt,
+                "`{}` takes {} arguments, got {}",
+                function, expected, got
+            ),
+            Self::Arity {
+                function: None,
+                expected,
+                got,
+            } => write!(fmt, "expected {} arguments, got {}", expected, got),
+            Self::Mismatch { expected, got } => write!(fmt, "expected {}, got {}", expected, got),
+            Self::InvalidForm(message) => fmt.write_str(message),
+            Self::NestingTooDeep(limit) => {
+                write!(fmt, "evaluation is nested deeper than {} levels", limit)
+            }
+            Self::Custom(message) => fmt.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Infallible> for Error {
+    fn from(never: Infallible) -> Self {
+        match never {}
+    }
+}
+
+pub(crate) type NativeFn<'a> = Rc<dyn Fn(&[Value<'a>]) -> Result<Value<'a>, Error> + 'a>;
+
+/// Function defined with `defn` or macro defined with `defmacro`.
+pub(crate) struct Lambda<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) params: Vec<&'a str>,
+    pub(crate) body: List<Value<'a>>,
+}
+
+#[derive(Clone)]
+enum Function<'a> {
+    Native(NativeFn<'a>),
+    Lambda(Rc<Lambda<'a>>),
+}
+
+/// Result of evaluation up to a call in tail position.
+enum Step<'a> {
+    Done(Value<'a>),
+    /// Call of a function with evaluated arguments, made by the caller's
+    /// [`Interpreter::trampoline`] instead of a nested one.
+    Call(Rc<Lambda<'a>>, Vec<Value<'a>>),
+}
+
+pub(crate) fn nil<'a>() -> Value<'a> {
+    ValueKind::Symbol("nil").into()
+}
+
+pub(crate) fn is_truthy(value: &Value) -> bool {
+    !matches!(
+        value.kind,
+        ValueKind::Symbol("false") | ValueKind::Symbol("nil")
+    )
+}
+
+pub(crate) fn symbol<'a>(value: &Value<'a>) -> Option<&'a str> {
+    match value.kind {
+        ValueKind::Symbol(symbol) if !value.raw => Some(symbol),
+        _ => None,
+    }
+}
+
+pub(crate) fn items<'v, 'a>(value: &'v Value<'a>) -> Option<Vec<&'v Value<'a>>> {
+    match &value.kind {
+        ValueKind::List(items) if !value.raw => Some(items.values().collect()),
+        _ => None,
+    }
+}
+
+/// Name and function of a `defn` or `defmacro` form.
+pub(crate) fn lambda<'a>(args: &[&Value<'a>]) -> Option<(&'a str, Lambda<'a>)> {
+    let (signature, body) = args.split_first()?;
+    let signature = items(signature)?
+        .iter()
+        .map(|item| symbol(item))
+        .collect::<Option<Vec<_>>>()?;
+    let (name, params) = signature.split_first()?;
+    let lambda = Lambda {
+        name,
+        params: params.to_vec(),
+        body: List::from_double_ended_iter(body.iter().map(|form| (*form).clone())),
+    };
+    Some((name, lambda))
+}
+
+/// Evaluator with its global variables and functions.
+pub struct Interpreter<'a> {
+    globals: HashMap<String, Value<'a>>,
+    functions: HashMap<String, Function<'a>>,
+    macros: HashMap<String, Rc<Lambda<'a>>>,
+    /// Local variables of the called functions, innermost call last.
+    frames: Vec<Vec<(&'a str, Value<'a>)>>,
+    /// Levels of nested evaluation, up to `max_depth`.
+    depth: usize,
+    max_depth: usize,
+    /// Marks of macro expansions. Last, so that the marked names outlive the
+    /// forms holding them.
+    marks: macros::Marks,
+}
+
+impl Default for Interpreter<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    /// Interpreter with the builtin functions.
+    pub fn new() -> Self {
+        let mut interpreter = Self {
+            globals: HashMap::new(),
+            functions: HashMap::new(),
+            macros: HashMap::new(),
+            frames: Vec::new(),
+            depth: 0,
+            max_depth: MAX_DEPTH,
+            marks: Default::default(),
+        };
+        builtins::register(&mut interpreter);
+        interpreter
+    }
+
+    /// Adds a native function, replacing a function of the same name.
+    /// Arguments can be converted with [`from_args`].
+    pub fn register_fn<F, R>(&mut self, name: impl Into<String>, function: F)
+    where
+        F: Fn(&[Value<'a>]) -> Result<R, Error> + 'a,
+        R: Into<Value<'a>>,
+    {
+        let function = move |args: &[Value<'a>]| function(args).map(Into::into);
+        self.functions
+            .insert(name.into(), Function::Native(Rc::new(function)));
+    }
+
+    /// Sets how deep evaluation can be nested, instead of [`MAX_DEPTH`].
+    /// Raise it only for a thread with a larger stack.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Sets a global variable.
+    pub fn set(&mut self, name: impl Into<String>, value: Value<'a>) {
+        self.globals.insert(name.into(), value);
+    }
+
+    /// Value of a global variable.
+    pub fn get(&self, name: &str) -> Option<&Value<'a>> {
+        self.globals.get(name)
+    }
+
+    /// Native function of the name, if there is one.
+    pub(crate) fn native(&self, name: &str) -> Option<NativeFn<'a>> {
+        match self.functions.get(name) {
+            Some(Function::Native(function)) => Some(function.clone()),
+            _ => None,
+        }
+    }
+
+    /// Parses and evaluates a program, returns the value of the last form.
+    pub fn run(&mut self, source: &'a str) -> Result<Value<'a>, Error> {
+        let (program, errors) = program_resilient(source);
+        if !errors.is_empty() {
+            return Err(Error::Syntax(errors));
+        }
+        self.eval(&program)
+    }
+
+    /// Evaluates a value. Forms of a sequential list, like a program, are
+    /// expanded and evaluated one by one, so that a macro can be used in the
+    /// forms after its definition.
+    pub fn eval(&mut self, value: &Value<'a>) -> Result<Value<'a>, Error> {
+        match &value.kind {
+            ValueKind::List(forms) if value.sequential && !value.raw => {
+                let mut result = nil();
+                for form in forms.iter() {
+                    result = self.eval(&form)?;
+                }
+                Ok(result)
+            }
+            _ => {
+                let expanded = self.expand_form(value)?;
+                self.eval_expanded(&expanded)
+            }
+        }
+    }
+
+    fn eval_expanded(&mut self, value: &Value<'a>) -> Result<Value<'a>, Error> {
+        if self.depth >= self.max_depth {
+            return Err(Error::NestingTooDeep(self.max_depth));
+        }
+        self.depth += 1;
+        let result = self.eval_tail(value).and_then(|step| self.trampoline(step));
+        self.depth -= 1;
+        result
+    }
+
+    /// Makes calls until there are none in tail position. Every call replaces
+    /// the frame of the previous one.
+    fn trampoline(&mut self, mut step: Step<'a>) -> Result<Value<'a>, Error> {
+        let mut has_frame = false;
+        let result = loop {
+            let (lambda, args) = match step {
+                Step::Done(value) => break Ok(value),
+                Step::Call(lambda, args) => (lambda, args),
+            };
+            if lambda.params.len() != args.len() {
+                break Err(Error::Arity {
+                    function: Some(lambda.name.into()),
+                    expected: lambda.params.len(),
+                    got: args.len(),
+                });
+            }
+            if has_frame {
+                self.frames.pop();
+            }
+            self.frames
+                .push(lambda.params.iter().copied().zip(args).collect());
+            has_frame = true;
+            step = match self.eval_body(lambda.body.values()) {
+                Ok(step) => step,
+                Err(error) => break Err(error),
+            };
+        };
+        if has_frame {
+            self.frames.pop();
+        }
+        result
+    }
+
+    fn eval_tail(&mut self, value: &Value<'a>) -> Result<Step<'a>, Error> {
+        match &value.kind {
+            _ if value.raw => {
+                let mut value = value.clone();
+                value.raw = false;
+                Ok(Step::Done(value))
+            }
+            ValueKind::Symbol(name) => self.lookup(name).map(Step::Done),
+            ValueKind::List(items) if value.sequential => self.eval_body(items.values()),
+            ValueKind::List(items) => self.eval_list(&items.values().collect::<Vec<_>>()),
+            ValueKind::Error => Err(Error::InvalidForm("value has parsing errors")),
+            _ => Ok(Step::Done(value.clone())),
+        }
+    }
+
+    /// Calls a function with evaluated arguments.
+    pub fn call(&mut self, name: &str, args: &[Value<'a>]) -> Result<Value<'a>, Error> {
+        let function = self
+            .functions
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::UnboundFunction(name.into()))?;
+        match function {
+            Function::Native(function) => function(args).map_err(|error| error.in_function(name)),
+            Function::Lambda(lambda) => self.trampoline(Step::Call(lambda, args.to_vec())),
+        }
+    }
+
+    fn lookup(&self, name: &'a str) -> Result<Value<'a>, Error> {
+        let local = self
+            .frames
+            .last()
+            .and_then(|locals| locals.iter().rev().find(|(local, _)| *local == name));
+        if let Some((_, value)) = local {
+            return Ok(value.clone());
+        }
+        match self.globals.get(name) {
+            Some(value) => Ok(value.clone()),
+            None if matches!(name, "true" | "false" | "nil") => Ok(ValueKind::Symbol(name).into()),
+            None => Err(Error::UnboundVariable(name.into())),
+        }
+    }
+
+    /// Evaluates forms in order, the last one is in tail position.
+    fn eval_body<'v>(
+        &mut self,
+        forms: impl Iterator<Item = &'v Value<'a>>,
+    ) -> Result<Step<'a>, Error>
+    where
+        'a: 'v,
+    {
+        let mut forms = forms.peekable();
+        while let Some(form) = forms.next() {
+            if forms.peek().is_none() {
+                return self.eval_tail(form);
+            }
+            self.eval_expanded(form)?;
+        }
+        Ok(Step::Done(nil()))
+    }
+
+    fn eval_list(&mut self, items: &[&Value<'a>]) -> Result<Step<'a>, Error> {
+        let (head, args) = match items.split_first() {
+            Some(split) => split,
+            None => return Ok(Step::Done(ValueKind::List(List::new()).into())),
+        };
+        let name = symbol(head).ok_or_else(|| Error::NotCallable(head.kind.type_name()))?;
+        match name {
+            "def" => self.def(args).map(Step::Done),
+            "defn" => self.defn(args).map(Step::Done),
+            "defmacro" => self.defmacro(args).map(Step::Done),
+            "if" => self.eval_if(args),
+            "let" => self.eval_let(args),
+            _ => {
+                let function = self
+                    .functions
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| Error::UnboundFunction(name.into()))?;
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval_expanded(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match function {
+                    Function::Native(function) => function(&args)
+                        .map(Step::Done)
+                        .map_err(|error| error.in_function(name)),
+                    Function::Lambda(lambda) => Ok(Step::Call(lambda, args)),
+                }
+            }
+        }
+    }
+
+    fn def(&mut self, args: &[&Value<'a>]) -> Result<Value<'a>, Error> {
+        const USAGE: &str = "`def` takes a name and a value";
+        let (name, value) = match args {
+            [name, value] => (symbol(name).ok_or(Error::InvalidForm(USAGE))?, value),
+            _ => return Err(Error::InvalidForm(USAGE)),
+        };
+        let value = self.eval_expanded(value)?;
+        self.globals.insert(name.into(), value);
+        Ok(ValueKind::Symbol(name).into())
+    }
+
+    fn defn(&mut self, args: &[&Value<'a>]) -> Result<Value<'a>, Error> {
+        const USAGE: &str = "`defn` takes a signature like `(name params…)` and a body";
+        let (name, lambda) = lambda(args).ok_or(Error::InvalidForm(USAGE))?;
+        self.functions
+            .insert(name.to_string(), Function::Lambda(Rc::new(lambda)));
+        Ok(ValueKind::Symbol(name).into())
+    }
+
+    fn defmacro(&mut self, args: &[&Value<'a>]) -> Result<Value<'a>, Error> {
+        const USAGE: &str = "`defmacro` takes a signature like `(name params…)` and a body";
+        let (name, lambda) = lambda(args).ok_or(Error::InvalidForm(USAGE))?;
+        self.macros.insert(name.to_string(), Rc::new(lambda));
+        Ok(ValueKind::Symbol(name).into())
+    }
+
+    fn eval_if(&mut self, args: &[&Value<'a>]) -> Result<Step<'a>, Error> {
+        let (condition, then, otherwise) = match args {
+            [condition, then] => (condition, then, None),
+            [condition, then, otherwise] => (condition, then, Some(otherwise)),
+            _ => {
+                return Err(Error::InvalidForm(
+                    "`if` takes a condition and one or two branches",
+                ))
+            }
+        };
+        if is_truthy(&self.eval_expanded(condition)?) {
+            self.eval_tail(then)
+        } else {
+            otherwise.map_or_else(
+                || Ok(Step::Done(nil())),
+                |otherwise| self.eval_tail(otherwise),
+            )
+        }
+    }
+
+    fn eval_let(&mut self, args: &[&Value<'a>]) -> Result<Step<'a>, Error> {
+        let (bindings, body) = args
+            .split_first()
+            .and_then(|(bindings, body)| Some((items(bindings)?, body)))
+            .ok_or(Error::InvalidForm(
+                "`let` takes a list of bindings and a body",
+            ))?;
+        let outermost = self.frames.is_empty();
+        if outermost {
+            self.frames.push(Vec::new());
+        }
+        let scope_start = self.frames.last().map_or(0, Vec::len);
+        let result = self.bind_and_eval(&bindings, body);
+        if outermost {
+            self.frames.pop();
+        } else if let Some(locals) = self.frames.last_mut() {
+            locals.truncate(scope_start);
+        }
+        result
+    }
+
+    fn bind_and_eval(
+        &mut self,
+        bindings: &[&Value<'a>],
+        body: &[&Value<'a>],
+    ) -> Result<Step<'a>, Error> {
+        for binding in bindings {
+            let (name, value) = match items(binding).as_deref() {
+                Some(&[name, value]) => (symbol(name), value),
+                _ => (None, *binding),
+            };
+            let name = name.ok_or(Error::InvalidForm(
+                "`let` binding is a list of a name and a value",
+            ))?;
+            let value = self.eval_expanded(value)?;
+            if let Some(locals) = self.frames.last_mut() {
+                locals.push((name, value));
+            }
+        }
+        self.eval_body(body.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> Result<String, String> {
+        Interpreter::new()
+            .run(source)
+            .map(|value| value.to_string())
+            .map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(run("1 2.5 \"s\""), Ok("\"s\"".into()));
+        assert_eq!(run("'x '(a b) ()"), Ok("()".into()));
+        assert_eq!(run("'(a !(b))"), Ok("(a !(b))".into()));
+        assert_eq!(run("(+ 1 2 (* 3 4))"), Ok("15".into()));
+        assert_eq!(run("(/ 1.0 4)"), Ok("0.25".into()));
+        assert_eq!(run("(def x 2) !((def x 3) (+ x 1))"), Ok("4".into()));
+        assert_eq!(run("(if (lt 1 2) 'yes 'no) (if nil 1)"), Ok("nil".into()));
+        assert_eq!(
+            run("(let ((x 1) (y (+ x 1))) (list x y))"),
+            Ok("(1 2)".into())
+        );
+        assert_eq!(run("(head (tail (cons 1 '(2 3))))"), Ok("2".into()));
+    }
+
+    #[test]
+    fn test_defn() {
+        let source = "
+            (defn (fact n) (if (eq n 0) 1 (* n (fact (- n 1)))))
+            (defn (twice n) (let ((n (* n 2))) n))
+            (list (fact 10) (twice 21))
+        ";
+        assert_eq!(run(source), Ok("(3628800 42)".into()));
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let source = "
+            (defn (count n acc) (if (eq n 0) acc (count (- n 1) (+ acc 1))))
+            (defn (even n) (if (eq n 0) true (let ((m (- n 1))) (odd m))))
+            (defn (odd n) !((eq n 0) (if (eq n 0) false (even (- n 1)))))
+            (list (count 1000000 0) (even 1000000) (odd 1000001))
+        ";
+        assert_eq!(run(source), Ok("(1000000 true true)".into()));
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let source = "(defn (f n) (if (eq n 0) 0 (+ 1 (f (- n 1)))))";
+        let (deep, shallow) = ("(f 1000000)", "(f 100)");
+        let mut interpreter = Interpreter::new();
+        interpreter.run(source).unwrap();
+        assert_eq!(interpreter.run(deep), Err(Error::NestingTooDeep(MAX_DEPTH)));
+        assert_eq!(interpreter.run(shallow), Ok(100.into()));
+        interpreter.set_max_depth(10);
+        assert_eq!(interpreter.run(shallow), Err(Error::NestingTooDeep(10)));
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("repeat", |args: &[Value]| {
+            let (s, count): (String, i64) = from_args(args)?;
+            Ok(s.repeat(count as usize))
+        });
+        interpreter.set("greeting", Value::from(String::from("hi")));
+        assert_eq!(
+            interpreter.run("(repeat greeting 3)"),
+            Ok(Value::from(String::from("hihihi")))
+        );
+        assert_eq!(
+            interpreter.run("(repeat 1 2)").unwrap_err().to_string(),
+            "expected string, got integer"
+        );
+        assert_eq!(
+            interpreter.run("(repeat \"a\")").unwrap_err().to_string(),
+            "`repeat` takes 2 arguments, got 1"
+        );
+    }
+
+    #[test]
+    fn test_native() {
+        use crate::native::Native;
+        use std::sync::atomic::{AtomicI64, Ordering};
+
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("counter", |args: &[Value]| {
+            let (start,): (i64,) = from_args(args)?;
+            Ok(Native::new(AtomicI64::new(start), "counter"))
+        });
+        interpreter.register_fn("incr", |args: &[Value]| {
+            let (native,): (Native,) = from_args(args)?;
+            let counter = native.downcast_ref::<AtomicI64>().ok_or(Error::Mismatch {
+                expected: "counter",
+                got: native.type_name(),
+            })?;
+            Ok(counter.fetch_add(1, Ordering::Relaxed) + 1)
+        });
+        let source = "(def c (counter 5)) (incr c) (list (incr c) c (eq c c) (eq c (counter 5)))";
+        assert_eq!(
+            interpreter.run(source).map(|value| value.to_string()),
+            Ok("(7 <counter> true false)".into())
+        );
+        assert_eq!(
+            interpreter.run("(incr 1)").unwrap_err().to_string(),
+            "expected native, got integer"
+        );
+        interpreter.set("unit", Native::new((), "unit").into());
+        assert_eq!(
+            interpreter.run("(incr unit)").unwrap_err().to_string(),
+            "expected counter, got unit"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(run("x"), Err("unbound variable `x`".into()));
+        assert_eq!(run("(f)"), Err("unknown function `f`".into()));
+        assert_eq!(run("(1 2)"), Err("integer can't be called".into()));
+        assert_eq!(
+            run("(+ 1 \"a\")"),
+            Err("expected number, got string".into())
+        );
+        assert_eq!(
+            run("(defn (f x) x) (f)"),
+            Err("`f` takes 1 arguments, got 0".into())
+        );
+        assert_eq!(
+            run("(def 1 2)"),
+            Err("`def` takes a name and a value".into())
+        );
+        assert!(matches!(
+            Interpreter::new().run("(a"),
+            Err(Error::Syntax(_))
+        ));
+    }
+}
@@ -0,0 +1,255 @@
+//! Conversion between [`Value`]s and JSON.
+//!
+//! | spli                                   | JSON                       |
+//! |----------------------------------------|----------------------------|
+//! | integer, float                         | number                     |
+//! | string                                 | string                     |
+//! | `true`, `false`, `nil`                 | `true`, `false`, `null`    |
+//! | other symbol `name`                    | string `"'name"`           |
+//! | association list `((a 1) ("b c" 2))`   | object `{"a":1,"b c":2}`   |
+//! | other list                             | array                      |
+//!
+//! * A string that looks like a tagged symbol gets another `'`, so that it
+//!   isn't read back as a symbol: `"'a"` is converted to `"''a"`.
+//! * An association list is a non-empty list of two-item lists, whose first
+//!   items are distinct symbols or strings. Object keys are converted to
+//!   symbols if they are valid identifiers, and to strings otherwise.
+//! * spli has no negative literals, so negative numbers are converted to
+//!   symbols like `-5`, which are converted back to numbers.
+//! * `'` and `!` modifiers are dropped, and an empty object is converted to
+//!   an empty list.
+
+use crate::list::List;
+use crate::parser::{self, ident, NegativeNumber, Span};
+use crate::{Value, ValueKind};
+
+use serde_json::{Map, Number, Value as Json};
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+/// Error of [`to_json`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(pub String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+const SYMBOL_TAG: char = '\'';
+
+fn is_ident(s: &str) -> bool {
+    matches!(ident(Span::new(s)), Ok((rest, _)) if rest.fragment().is_empty())
+}
+
+/// Whether a JSON string is read as a symbol or as a string with an extra tag.
+fn is_tagged(s: &str) -> bool {
+    s.strip_prefix(SYMBOL_TAG)
+        .is_some_and(|rest| rest.starts_with(SYMBOL_TAG) || is_ident(rest))
+}
+
+/// Number written as a symbol, like `-5`.
+fn negative_number(symbol: &str) -> Option<Json> {
+    match parser::negative_number(symbol)? {
+        NegativeNumber::Integer(n) => Some(Json::from(n)),
+        NegativeNumber::Float(x) => Number::from_f64(x).map(Json::Number),
+    }
+}
+
+/// Key of an association list entry.
+fn key(value: &Value) -> Option<String> {
+    match &value.kind {
+        ValueKind::Symbol(symbol) => Some(symbol.to_string()),
+        ValueKind::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// Keys and values of an association list.
+fn entries<'a>(items: &[Arc<Value<'a>>]) -> Option<Vec<(String, Arc<Value<'a>>)>> {
+    if items.is_empty() {
+        return None;
+    }
+    let mut keys = HashSet::new();
+    let mut entries = Vec::with_capacity(items.len());
+    for item in items {
+        let pair: Vec<_> = match &item.kind {
+            ValueKind::List(pair) => pair.iter().collect(),
+            _ => return None,
+        };
+        match pair.as_slice() {
+            [key_value, value] => {
+                let key = key(key_value)?;
+                if !keys.insert(key.clone()) {
+                    return None;
+                }
+                entries.push((key, value.clone()));
+            }
+            _ => return None,
+        }
+    }
+    Some(entries)
+}
+
+/// Converts `value` to JSON.
+pub fn to_json(value: &Value) -> Result<Json, Error> {
+    Ok(match &value.kind {
+        ValueKind::Symbol("true") => Json::Bool(true),
+        ValueKind::Symbol("false") => Json::Bool(false),
+        ValueKind::Symbol("nil") => Json::Null,
+        ValueKind::Symbol(symbol) => match negative_number(symbol) {
+            Some(number) => number,
+            None => Json::String(format!("{}{}", SYMBOL_TAG, symbol)),
+        },
+        ValueKind::Integer(n) => Json::from(n.value),
+        ValueKind::Float(x) => Number::from_f64(x.value)
+            .map(Json::Number)
+            .ok_or_else(|| Error(format!("float {} can't be converted", x)))?,
+        ValueKind::String(s) if is_tagged(s) => Json::String(format!("{}{}", SYMBOL_TAG, s)),
+        ValueKind::String(s) => Json::String(s.clone()),
+        ValueKind::List(items) => {
+            let items: Vec<_> = items.iter().collect();
+            match entries(&items) {
+                Some(entries) => {
+                    let mut object = Map::new();
+                    for (key, value) in entries {
+                        object.insert(key, to_json(&value)?);
+                    }
+                    Json::Object(object)
+                }
+                None => Json::Array(
+                    items
+                        .iter()
+                        .map(|item| to_json(item))
+                        .collect::<Result<_, _>>()?,
+                ),
+            }
+        }
+        ValueKind::Native(native) => {
+            return Err(Error(format!(
+                "native value of type {} can't be converted",
+                native.type_name()
+            )))
+        }
+        ValueKind::Error => {
+            return Err(Error("value with parsing errors can't be converted".into()))
+        }
+    })
+}
+
+fn simple(kind: ValueKind) -> Value {
+    Value {
+        raw: false,
+        sequential: false,
+        kind,
+    }
+}
+
+/// Converts JSON to a value. Symbols borrow from `json`.
+pub fn from_json(json: &Json) -> Value<'_> {
+    simple(match json {
+        Json::Null => ValueKind::Symbol("nil"),
+        Json::Bool(true) => ValueKind::Symbol("true"),
+        Json::Bool(false) => ValueKind::Symbol("false"),
+        // Negative numbers are printed like `-5`, which is read back as a symbol
+        Json::Number(n) => match n.as_i64() {
+            Some(n) => ValueKind::Integer(n.into()),
+            None => ValueKind::Float(n.as_f64().unwrap_or(f64::NAN).into()),
+        },
+        Json::String(s) => match s.strip_prefix(SYMBOL_TAG) {
+            Some(rest) if rest.starts_with(SYMBOL_TAG) => ValueKind::String(rest.into()),
+            Some(symbol) if is_ident(symbol) => ValueKind::Symbol(symbol),
+            _ => ValueKind::String(s.clone()),
+        },
+        Json::Array(items) => ValueKind::List(List::from_double_ended_iter(
+            items.iter().map(from_json).collect::<Vec<_>>(),
+        )),
+        Json::Object(object) => ValueKind::List(List::from_double_ended_iter(
+            object
+                .iter()
+                .map(|(key, value)| {
+                    let key = if is_ident(key) {
+                        ValueKind::Symbol(key)
+                    } else {
+                        ValueKind::String(key.clone())
+                    };
+                    simple(ValueKind::List(List::from_double_ended_iter(vec![
+                        simple(key),
+                        from_json(value),
+                    ])))
+                })
+                .collect::<Vec<_>>(),
+        )),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::program_resilient;
+    use serde_json::json;
+
+    fn form(source: &str) -> Arc<Value<'_>> {
+        let (program, errors) = program_resilient(source);
+        assert!(errors.is_empty());
+        match &program.kind {
+            ValueKind::List(forms) => forms.iter().next().unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_to_json() {
+        let value = form(
+            r#"(1 2.5 "s" "'a" "'a b" sym true nil -5 ((name "x") ("a b" (-1.5))) ((a 1) (a 2)))"#,
+        );
+        assert_eq!(
+            to_json(&value),
+            Ok(json!([
+                1,
+                2.5,
+                "s",
+                "''a",
+                "'a b",
+                "'sym",
+                true,
+                null,
+                -5,
+                {"name": "x", "a b": [-1.5]},
+                [["'a", 1], ["'a", 2]]
+            ]))
+        );
+        let (program, _) = program_resilient("0123");
+        assert!(to_json(&program).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = json!({
+            "name": "spli",
+            "tags": ["'lisp", "''quoted", "'not ident", "plain"],
+            "version": [0, 1.5, -2],
+            "nested": {"ok": true, "none": null, "with space": {}},
+        });
+        let value = from_json(&json);
+        assert_eq!(
+            value.to_string(),
+            concat!(
+                r#"((name "spli") (nested ((none nil) (ok true) ("with space" ()))) "#,
+                r#"(tags (lisp "'quoted" "'not ident" "plain")) (version (0 1.5 -2)))"#,
+            )
+        );
+        let mut expected = json.clone();
+        expected["nested"]["with space"] = json!([]);
+        assert_eq!(to_json(&form(&value.to_string())), Ok(expected));
+
+        let json = json!({"big": 1e300, "small": 1e-7, "negative": [-1e20, -2.5e-8]});
+        let value = from_json(&json);
+        assert_eq!(to_json(&form(&value.to_string())), Ok(json.clone()));
+    }
+}
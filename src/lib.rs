@@ -1,49 +1,272 @@
+pub mod binary;
+#[cfg(feature = "serde")]
+pub mod de;
+pub mod dump;
+pub mod highlight;
+pub mod interpreter;
+#[cfg(feature = "json")]
+pub mod json;
 pub mod list;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod native;
 pub mod parser;
+#[cfg(feature = "serde")]
+pub mod ser;
+pub mod vm;
 
 #[cfg(test)]
 pub mod test_helpers;
 
 use list::List;
+use native::Native;
 use std::fmt::{self, Write};
+use std::sync::Arc;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ValueKind<'a> {
     Symbol(&'a str),
-    Integer(i64),
-    Float(f64),
+    Integer(Number<'a, i64>),
+    Float(Number<'a, f64>),
     String(String),
     List(List<Value<'a>>),
+    /// Host object, never produced by the parser.
+    Native(Native),
+    /// Placeholder for a token that failed to parse in resilient mode.
+    Error,
 }
 
-#[derive(Debug, PartialEq)]
+/// Numeric literal. Keeps the text it was parsed from, so that printing it
+/// gives back the author's notation, e.g. `0xFF` or `1_000.50`. Literals are
+/// equal if their values are, regardless of the notation.
+#[derive(Debug, Clone, Copy)]
+pub struct Number<'a, T> {
+    pub value: T,
+    text: Option<&'a str>,
+}
+
+impl<'a, T> Number<'a, T> {
+    /// Number written as `text` in the source.
+    pub fn with_text(value: T, text: &'a str) -> Self {
+        Self {
+            value,
+            text: Some(text),
+        }
+    }
+
+    /// Source text of the number, if it was parsed.
+    pub fn text(&self) -> Option<&'a str> {
+        self.text
+    }
+}
+
+impl Number<'_, i64> {
+    /// Radix of the literal, `10` for numbers that weren't parsed.
+    pub fn radix(&self) -> u32 {
+        match self.text.and_then(|text| text.get(..2)) {
+            Some("0x") => 16,
+            Some("0o") => 8,
+            Some("0b") => 2,
+            _ => 10,
+        }
+    }
+}
+
+impl<T> From<T> for Number<'_, T> {
+    fn from(value: T) -> Self {
+        Self { value, text: None }
+    }
+}
+
+impl<T: PartialEq> PartialEq for Number<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl fmt::Display for Number<'_, i64> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.text {
+            Some(text) => fmt.write_str(text),
+            None => write!(fmt, "{}", self.value),
+        }
+    }
+}
+
+impl fmt::Display for Number<'_, f64> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.text {
+            Some(text) => fmt.write_str(text),
+            // Unlike `Debug`, `Display` never uses an exponent, which spli can't
+            // read. The fractional part is kept, so the number is read back as a float
+            None => {
+                let text = self.value.to_string();
+                fmt.write_str(&text)?;
+                if self.value.is_finite() && !text.contains('.') {
+                    fmt.write_str(".0")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Value<'a> {
     pub raw: bool,
     pub sequential: bool,
     pub kind: ValueKind<'a>,
 }
 
+impl<'a> From<ValueKind<'a>> for Value<'a> {
+    fn from(kind: ValueKind<'a>) -> Self {
+        Self {
+            raw: false,
+            sequential: false,
+            kind,
+        }
+    }
+}
+
+impl From<i64> for Value<'_> {
+    fn from(value: i64) -> Self {
+        ValueKind::Integer(value.into()).into()
+    }
+}
+
+impl From<f64> for Value<'_> {
+    fn from(value: f64) -> Self {
+        ValueKind::Float(value.into()).into()
+    }
+}
+
+impl From<bool> for Value<'_> {
+    fn from(value: bool) -> Self {
+        ValueKind::Symbol(if value { "true" } else { "false" }).into()
+    }
+}
+
+impl From<Native> for Value<'_> {
+    fn from(value: Native) -> Self {
+        ValueKind::Native(value).into()
+    }
+}
+
+impl From<String> for Value<'_> {
+    fn from(value: String) -> Self {
+        ValueKind::String(value).into()
+    }
+}
+
+/// Writes a string literal, escaping only what the parser requires, so that it's read back as is.
+fn write_string(s: &str, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '\n' => fmt.write_str("\\n")?,
+            '\t' => fmt.write_str("\\t")?,
+            '"' => fmt.write_str("\\\"")?,
+            '\\' => fmt.write_str("\\\\")?,
+            c => fmt.write_char(c)?,
+        }
+    }
+    fmt.write_char('"')
+}
+
 impl fmt::Display for ValueKind<'_> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Symbol(s) => fmt.write_str(s),
             Self::Integer(n) => write!(fmt, "{}", n),
             Self::Float(x) => write!(fmt, "{}", x),
-            Self::String(s) => write!(fmt, "{:?}", s),
-            Self::List(xs) => write!(fmt, "{}", xs),
+            Self::String(s) => write_string(s, fmt),
+            Self::List(xs) => write_nested(vec![Pending::List(xs)], fmt),
+            Self::Native(native) => write!(fmt, "{}", native),
+            Self::Error => fmt.write_str("<error>"),
         }
     }
 }
 
 impl fmt::Display for Value<'_> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.raw {
-            fmt.write_char('\'')?;
+        write_nested(vec![Pending::Value(self)], fmt)
+    }
+}
+
+/// Part of a value that is left to write.
+enum Pending<'v, 'a> {
+    Value(&'v Value<'a>),
+    List(&'v List<Value<'a>>),
+    Str(&'static str),
+}
+
+/// Writes values with a work stack rather than recursively, so that deeply
+/// nested lists don't overflow the stack.
+fn write_nested(mut stack: Vec<Pending>, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+    while let Some(pending) = stack.pop() {
+        match pending {
+            Pending::Value(value) => {
+                if value.raw {
+                    fmt.write_char('\'')?;
+                }
+                if value.sequential {
+                    fmt.write_char('!')?;
+                }
+                match &value.kind {
+                    ValueKind::List(items) => stack.push(Pending::List(items)),
+                    kind => write!(fmt, "{}", kind)?,
+                }
+            }
+            Pending::List(items) => {
+                fmt.write_char('(')?;
+                stack.push(Pending::Str(")"));
+                let items: Vec<_> = items.values().collect();
+                for (idx, item) in items.into_iter().enumerate().rev() {
+                    stack.push(Pending::Value(item));
+                    if idx > 0 {
+                        stack.push(Pending::Str(" "));
+                    }
+                }
+            }
+            Pending::Str(s) => fmt.write_str(s)?,
         }
-        if self.sequential {
-            fmt.write_char('!')?;
+    }
+    Ok(())
+}
+
+impl<'a> Value<'a> {
+    /// Takes the kind out of the value. Values implement `Drop`, so the
+    /// kind can't be moved out of them directly.
+    pub fn into_kind(mut self) -> ValueKind<'a> {
+        std::mem::replace(&mut self.kind, ValueKind::Error)
+    }
+}
+
+/// Nested lists are dropped with a work stack rather than recursively, so
+/// that dropping a deeply nested value doesn't overflow the stack.
+impl Drop for Value<'_> {
+    fn drop(&mut self) {
+        let mut list = match &mut self.kind {
+            ValueKind::List(items) => std::mem::take(items),
+            _ => return,
+        };
+        let mut lists = Vec::new();
+        loop {
+            // Nodes and items still referenced elsewhere are only released
+            while let Some(node) = list.pop_unique() {
+                if let Some(Value {
+                    kind: ValueKind::List(items),
+                    ..
+                }) = Arc::into_inner(node.val).as_mut()
+                {
+                    lists.push(std::mem::take(items));
+                }
+            }
+            match lists.pop() {
+                Some(next) => list = next,
+                None => break,
+            }
         }
-        write!(fmt, "{}", self.kind)
     }
 }
 
@@ -54,7 +277,76 @@ impl ValueKind<'_> {
             Self::Integer(_) => "integer",
             Self::Float(_) => "float",
             Self::String(_) => "string",
-            Self::List(_) => "list"
+            Self::List(_) => "list",
+            Self::Native(native) => native.type_name(),
+            Self::Error => "error",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nested(depth: usize) -> Value<'static> {
+        let mut value = Value::from(1);
+        for _ in 0..depth {
+            value = ValueKind::List(List::new().cons(value).cons(2.into())).into();
+        }
+        value
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        const DEPTH: usize = 100_000;
+        let value = nested(DEPTH);
+        let shared = value.clone();
+        let text = value.to_string();
+        assert_eq!(text.len(), 4 * DEPTH + 1);
+        assert!(text.starts_with("(2 (2 ("));
+        assert!(text.ends_with(&format!("(2 1{}", ")".repeat(DEPTH))));
+        drop(value);
+        assert_eq!(shared.kind.to_string(), text);
+        drop(shared);
+        drop(nested(DEPTH));
+
+        // Lists sharing a long tail release only their own nodes
+        let tail = List::reverse_from_iter((0..DEPTH as i64).map(Value::from));
+        for n in 0..DEPTH as i64 {
+            drop(Value::from(ValueKind::List(tail.cons(n.into()))));
+            drop(tail.cons(n.into()));
+        }
+        assert_eq!(tail.len(), DEPTH);
+    }
+
+    #[test]
+    fn test_display() {
+        let value = Value {
+            raw: true,
+            sequential: false,
+            kind: ValueKind::List(list![
+                Value::from(1),
+                Value {
+                    raw: false,
+                    sequential: true,
+                    kind: ValueKind::List(list![ValueKind::Symbol("a").into()]),
+                },
+                ValueKind::List(List::new()).into(),
+            ]),
+        };
+        assert_eq!(value.to_string(), "'(1 !(a) ())");
+        assert_eq!(value.into_kind().to_string(), "(1 !(a) ())");
+    }
+
+    #[test]
+    fn test_display_float() {
+        let display = |x: f64| Value::from(x).to_string();
+        assert_eq!(display(1e20), "100000000000000000000.0");
+        assert_eq!(display(1e-7), "0.0000001");
+        assert_eq!(display(2.5), "2.5");
+        assert_eq!(display(-0.0), "-0.0");
+        for x in [1e20, 1e-7, 1.5e300, 3e-300] {
+            assert_eq!(parser::float(parser::Span::new(&display(x))).unwrap().1, x);
         }
     }
 }
@@ -28,6 +28,10 @@ impl<T> List<T> {
         self.length
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
     pub fn cons(&self, val: T) -> Self {
         Self {
             head: Some(Arc::new(ListNode {
@@ -52,10 +56,27 @@ impl<T> List<T> {
     pub fn pop_node(&mut self) -> Link<T> {
         self.length = self.length.saturating_sub(1);
         replace_with_or_abort_and_return(&mut self.head, move |head| {
-            (head.clone(), head.map(|node| node.next.clone()).flatten())
+            (head.clone(), head.and_then(|node| node.next.clone()))
         })
     }
 
+    /// Takes the first node out of the list if no other list shares it.
+    /// Otherwise the list lets go of its nodes and becomes empty, which takes
+    /// a single update of the reference count.
+    pub(crate) fn pop_unique(&mut self) -> Option<ListNode<T>> {
+        let node = Arc::into_inner(self.head.take()?);
+        let mut node = match node {
+            Some(node) => node,
+            None => {
+                self.length = 0;
+                return None;
+            }
+        };
+        self.head = node.next.take();
+        self.length -= 1;
+        Some(node)
+    }
+
     pub fn pop(&mut self) -> Option<Arc<T>> {
         self.pop_node().map(|node| node.val.clone())
     }
@@ -82,6 +103,12 @@ impl<T> List<T> {
     pub fn iter(&self) -> impl Iterator<Item = Arc<T>> {
         self.clone().into_iter()
     }
+
+    /// Iterates over borrowed items, without touching reference counts.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        std::iter::successors(self.head.as_deref(), |node| node.next.as_deref())
+            .map(|node| &*node.val)
+    }
 }
 
 impl<T: PartialEq> List<T> {
@@ -101,9 +128,11 @@ impl<T> Default for List<T> {
     }
 }
 
+/// Nodes are dropped in a loop rather than recursively, up to the first one
+/// shared with another list.
 impl<T> Drop for List<T> {
     fn drop(&mut self) {
-        while let Some(_) = self.pop_node() {}
+        while self.pop_unique().is_some() {}
     }
 }
 
@@ -242,6 +271,16 @@ mod test {
         assert_eq!(list.pop_node(), None);
     }
 
+    #[test]
+    fn test_pop_unique() {
+        let mut list = make_123();
+        let shared = list.tail().expect("too few items");
+        assert_eq!(list.pop_unique().map(|node| node.val), Some(Arc::new(1)));
+        assert_eq!(list.pop_unique(), None);
+        assert!(list.is_empty());
+        assert_eq!(shared, list![2, 3]);
+    }
+
     #[test]
     fn test_reverse_from_iter() {
         struct OneWayIter<'a, T>(&'a [T], usize);
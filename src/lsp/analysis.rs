@@ -0,0 +1,270 @@
+use crate::parser::{Document, SyntaxKind, SyntaxNode};
+
+use lsp_types::{SemanticToken, SemanticTokenType, SymbolKind};
+
+use super::line_index::LineIndex;
+
+use std::ops::Range;
+
+/// Semantic token types reported by the server, in legend order.
+pub(crate) const TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::STRING,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::OPERATOR,
+];
+
+const KEYWORD: u32 = 0;
+const FUNCTION: u32 = 1;
+const VARIABLE: u32 = 2;
+const STRING: u32 = 3;
+const NUMBER: u32 = 4;
+const OPERATOR: u32 = 5;
+
+/// Heads of lists that define something, like `def`, `define` or `defn`.
+fn is_definition_keyword(symbol: &str) -> bool {
+    symbol.starts_with("def")
+}
+
+/// Text of a symbol node starting at `start`, without the modifier.
+fn symbol<'a>(text: &'a str, start: usize, node: &SyntaxNode) -> Option<&'a str> {
+    match node.kind {
+        SyntaxKind::Symbol => {
+            let inner = node.inner_range();
+            Some(&text[start + inner.start..start + inner.end])
+        }
+        _ => None,
+    }
+}
+
+/// Text of the first element of a list, if it is a symbol.
+fn head<'a>(text: &'a str, start: usize, node: &SyntaxNode) -> Option<&'a str> {
+    match &node.kind {
+        SyntaxKind::List(children) => children
+            .first()
+            .and_then(|child| symbol(text, start + child.offset, &child.node)),
+        _ => None,
+    }
+}
+
+/// Top-level definition, like `(def x 1)` or `(defn (f x) x)`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Definition {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub range: Range<usize>,
+    pub name_range: Range<usize>,
+}
+
+pub(crate) fn definitions(text: &str, tree: &SyntaxNode) -> Vec<Definition> {
+    tree.children()
+        .iter()
+        .filter_map(|form| {
+            let children = form.node.children();
+            if !is_definition_keyword(head(text, form.offset, &form.node)?) {
+                return None;
+            }
+            let target = children.get(1)?;
+            let target_start = form.offset + target.offset;
+            let (name_start, name_node, kind) = match &target.node.kind {
+                // `(defn (f x) body)`
+                SyntaxKind::List(signature) => {
+                    let name = signature.first()?;
+                    (target_start + name.offset, &name.node, SymbolKind::FUNCTION)
+                }
+                _ => {
+                    let is_lambda = children.get(2).is_some_and(|value| {
+                        matches!(
+                            head(text, form.offset + value.offset, &value.node),
+                            Some("lambda") | Some("fn")
+                        )
+                    });
+                    let kind = if is_lambda {
+                        SymbolKind::FUNCTION
+                    } else {
+                        SymbolKind::VARIABLE
+                    };
+                    (target_start, &target.node, kind)
+                }
+            };
+            let name = symbol(text, name_start, name_node)?;
+            let inner = name_node.inner_range();
+            Some(Definition {
+                name: name.to_string(),
+                kind,
+                range: form.offset..form.offset + form.node.len,
+                name_range: name_start + inner.start..name_start + inner.end,
+            })
+        })
+        .collect()
+}
+
+/// Innermost node containing `offset`, together with its start.
+pub(crate) fn node_at(tree: &SyntaxNode, offset: usize) -> Option<(usize, &SyntaxNode)> {
+    let mut found = None;
+    let mut start = 0;
+    let mut node = tree;
+    while let Some(child) = node.children().iter().find(|child| {
+        start + child.offset <= offset && offset < start + child.offset + child.node.len
+    }) {
+        start += child.offset;
+        node = &child.node;
+        found = Some((start, node));
+    }
+    found
+}
+
+/// Collects semantic tokens, already delta-encoded for the client.
+struct TokenCollector<'a> {
+    index: &'a LineIndex<'a>,
+    text: &'a str,
+    tokens: Vec<SemanticToken>,
+    last_line: u32,
+    last_start: u32,
+}
+
+impl TokenCollector<'_> {
+    fn push(&mut self, range: Range<usize>, token_type: u32) {
+        // Clients may not support multiline tokens, so split them into lines
+        let mut line_start = range.start;
+        for line in self.text[range].split_inclusive('\n') {
+            let line_end = line_start + line.trim_end_matches(['\r', '\n']).len();
+            if line_end > line_start {
+                self.push_line(line_start..line_end, token_type);
+            }
+            line_start += line.len();
+        }
+    }
+
+    fn push_line(&mut self, range: Range<usize>, token_type: u32) {
+        let position = self.index.position(range.start);
+        let length = self.text[range].encode_utf16().count() as u32;
+        let delta_line = position.line - self.last_line;
+        let delta_start = if delta_line == 0 {
+            position.character - self.last_start
+        } else {
+            position.character
+        };
+        self.tokens.push(SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type,
+            token_modifiers_bitset: 0,
+        });
+        self.last_line = position.line;
+        self.last_start = position.character;
+    }
+
+    fn node(&mut self, start: usize, node: &SyntaxNode, is_head: bool) {
+        let inner = node.inner_range();
+        let modifier_len = match node.kind {
+            SyntaxKind::Program(_) | SyntaxKind::Error => 0,
+            _ => (node.raw || node.sequential) as usize,
+        };
+        if modifier_len > 0 {
+            self.push(start..start + modifier_len, OPERATOR);
+        }
+        let range = start + inner.start..start + inner.end;
+        match &node.kind {
+            SyntaxKind::Symbol => {
+                let token_type = if !is_head {
+                    VARIABLE
+                } else if is_definition_keyword(&self.text[range.clone()]) {
+                    KEYWORD
+                } else {
+                    FUNCTION
+                };
+                self.push(range, token_type);
+            }
+            SyntaxKind::Integer(_) | SyntaxKind::Float(_) => self.push(range, NUMBER),
+            SyntaxKind::String(_) => self.push(range, STRING),
+            SyntaxKind::List(children) | SyntaxKind::Program(children) => {
+                let is_list = matches!(node.kind, SyntaxKind::List(_));
+                for (idx, child) in children.iter().enumerate() {
+                    self.node(start + child.offset, &child.node, is_list && idx == 0);
+                }
+            }
+            SyntaxKind::Error => {}
+        }
+    }
+}
+
+pub(crate) fn semantic_tokens(document: &Document) -> Vec<SemanticToken> {
+    let index = LineIndex::new(document);
+    let text = document.text();
+    let mut collector = TokenCollector {
+        index: &index,
+        text,
+        tokens: Vec::new(),
+        last_line: 0,
+        last_start: 0,
+    };
+    collector.node(0, document.tree(), false);
+    collector.tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::syntax_tree;
+
+    #[test]
+    fn test_definitions() {
+        let text = "(def x 1)\n(defn (f a) a)\n(def g (lambda (b) b))\n(print x)\n(def)";
+        let found = definitions(text, &syntax_tree(text));
+        let names: Vec<_> = found
+            .iter()
+            .map(|definition| (definition.name.as_str(), definition.kind))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("x", SymbolKind::VARIABLE),
+                ("f", SymbolKind::FUNCTION),
+                ("g", SymbolKind::FUNCTION),
+            ]
+        );
+        assert_eq!(found[0].range, 0..9);
+        assert_eq!(found[1].name_range, 17..18);
+    }
+
+    #[test]
+    fn test_node_at() {
+        let text = "(a '(b 12))";
+        let tree = syntax_tree(text);
+        let (start, node) = node_at(&tree, 8).unwrap();
+        assert_eq!((start, node.kind.clone()), (7, SyntaxKind::Integer(12)));
+        assert_eq!(node_at(&tree, 3).unwrap().0, 3);
+        assert!(node_at(&tree, 11).is_none());
+    }
+
+    #[test]
+    fn test_semantic_tokens() {
+        let text = "(def s \"a\nb\")\n'x";
+        let tokens: Vec<_> = semantic_tokens(&Document::new(text))
+            .iter()
+            .map(|token| {
+                (
+                    token.delta_line,
+                    token.delta_start,
+                    token.length,
+                    token.token_type,
+                )
+            })
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (0, 1, 3, KEYWORD),
+                (0, 4, 1, VARIABLE),
+                (0, 2, 2, STRING),
+                (1, 0, 2, STRING),
+                (1, 0, 1, OPERATOR),
+                (0, 1, 1, VARIABLE),
+            ]
+        );
+    }
+}
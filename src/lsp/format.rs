@@ -0,0 +1,145 @@
+use crate::parser::{SyntaxKind, SyntaxNode};
+
+/// Lists that fit into this many columns are kept on one line.
+const MAX_WIDTH: usize = 80;
+const INDENT: usize = 2;
+
+fn width(s: &str) -> usize {
+    s.chars().count()
+}
+
+struct Formatter<'a> {
+    text: &'a str,
+    output: String,
+}
+
+impl Formatter<'_> {
+    fn modifier(node: &SyntaxNode) -> &'static str {
+        if node.raw {
+            "'"
+        } else if node.sequential {
+            "!"
+        } else {
+            ""
+        }
+    }
+
+    /// Node on a single line, or `None` if it contains a multiline string.
+    fn flat(&self, start: usize, node: &SyntaxNode) -> Option<String> {
+        match &node.kind {
+            SyntaxKind::List(children) => {
+                let mut result = format!("{}(", Self::modifier(node));
+                for (idx, child) in children.iter().enumerate() {
+                    if idx > 0 {
+                        result.push(' ');
+                    }
+                    result.push_str(&self.flat(start + child.offset, &child.node)?);
+                }
+                result.push(')');
+                Some(result)
+            }
+            _ => {
+                let token = &self.text[start..start + node.len];
+                if token.contains('\n') {
+                    None
+                } else {
+                    Some(token.to_string())
+                }
+            }
+        }
+    }
+
+    fn column(&self) -> usize {
+        width(&self.output[self.output.rfind('\n').map_or(0, |idx| idx + 1)..])
+    }
+
+    fn node(&mut self, start: usize, node: &SyntaxNode, indent: usize) {
+        if let Some(flat) = self.flat(start, node) {
+            if self.column() + width(&flat) <= MAX_WIDTH {
+                self.output.push_str(&flat);
+                return;
+            }
+        }
+        match &node.kind {
+            SyntaxKind::List(children) => {
+                self.output.push_str(Self::modifier(node));
+                self.output.push('(');
+                let indent = indent + Self::modifier(node).len() + INDENT;
+                for (idx, child) in children.iter().enumerate() {
+                    if idx > 0 {
+                        self.output.push('\n');
+                        self.output.push_str(&" ".repeat(indent));
+                    }
+                    self.node(start + child.offset, &child.node, indent);
+                }
+                self.output.push(')');
+            }
+            _ => self.output.push_str(&self.text[start..start + node.len]),
+        }
+    }
+}
+
+/// Pretty-prints a program parsed without errors. Lists that don't fit into
+/// a line are broken with one element per line, and a single blank line
+/// between top-level forms is preserved.
+pub(crate) fn format(text: &str, tree: &SyntaxNode) -> String {
+    let mut formatter = Formatter {
+        text,
+        output: String::new(),
+    };
+    let mut prev_end = None;
+    for child in tree.children() {
+        if let Some(prev_end) = prev_end {
+            let gap = &text[prev_end..child.offset];
+            formatter.output.push('\n');
+            if gap.matches('\n').count() > 1 {
+                formatter.output.push('\n');
+            }
+        }
+        formatter.node(child.offset, &child.node, 0);
+        prev_end = Some(child.offset + child.node.len);
+    }
+    if prev_end.is_some() {
+        formatter.output.push('\n');
+    }
+    formatter.output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::syntax_tree;
+
+    fn format_str(text: &str) -> String {
+        format(text, &syntax_tree(text))
+    }
+
+    #[test]
+    fn test_flat() {
+        assert_eq!(
+            format_str("  ( a   0xFF\n'(b) )  !c\n\n\n\"s\""),
+            "(a 0xFF '(b))\n!c\n\n\"s\"\n"
+        );
+        assert_eq!(format_str(""), "");
+    }
+
+    #[test]
+    fn test_break_long_lists() {
+        let long = "x".repeat(40);
+        let text = format!("(def f '(lambda ({} {}) \"a\nb\"))", long, long);
+        assert_eq!(
+            format_str(&text),
+            format!(
+                "(def\n  f\n  '(lambda\n     ({}\n       {})\n     \"a\nb\"))\n",
+                long, long
+            )
+        );
+    }
+
+    #[test]
+    fn test_idempotent() {
+        let text = format!("(a (b {}) (c d))", "y ".repeat(50));
+        let formatted = format_str(&text);
+        assert_eq!(format_str(&formatted), formatted);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::parser::Document;
+
+use lsp_types::{Position, Range as LspRange};
+
+use std::ops::Range;
+
+/// Converts between byte offsets and LSP positions, which count UTF-16 code
+/// units from the start of a line. Uses the lines kept by the [`Document`].
+pub(crate) struct LineIndex<'a> {
+    text: &'a str,
+    line_starts: &'a [usize],
+}
+
+impl<'a> LineIndex<'a> {
+    pub(crate) fn new(document: &'a Document) -> Self {
+        Self {
+            text: document.text(),
+            line_starts: document.line_starts(),
+        }
+    }
+
+    pub(crate) fn position(&self, offset: usize) -> Position {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let character = self.text[self.line_starts[line]..offset]
+            .encode_utf16()
+            .count();
+        Position::new(line as u32, character as u32)
+    }
+
+    pub(crate) fn range(&self, range: &Range<usize>) -> LspRange {
+        LspRange::new(self.position(range.start), self.position(range.end))
+    }
+
+    /// Byte offset of `position`, clamped to the end of its line.
+    pub(crate) fn offset(&self, position: Position) -> usize {
+        let line_start = match self.line_starts.get(position.line as usize) {
+            Some(&start) => start,
+            None => return self.text.len(),
+        };
+        let mut units = 0;
+        for (idx, c) in self.text[line_start..].char_indices() {
+            if c == '\n' || units >= position.character as usize {
+                return line_start + idx;
+            }
+            units += c.len_utf16();
+        }
+        self.text.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positions() {
+        let document = Document::new("(a\n\"\u{1f600}\" b)\n");
+        let index = LineIndex::new(&document);
+        assert_eq!(index.position(0), Position::new(0, 0));
+        assert_eq!(index.position(3), Position::new(1, 0));
+        // The emoji is four bytes, but two UTF-16 code units
+        assert_eq!(index.position(8), Position::new(1, 3));
+        assert_eq!(index.position(100), Position::new(2, 0));
+        assert_eq!(index.offset(Position::new(1, 3)), 8);
+        assert_eq!(index.offset(Position::new(0, 10)), 2);
+        assert_eq!(index.offset(Position::new(5, 0)), 13);
+    }
+}
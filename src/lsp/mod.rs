@@ -0,0 +1,507 @@
+//! Language server for spli, speaking LSP over any [`Connection`].
+//!
+//! Documents are synced incrementally and reparsed with [`Document::edit`].
+
+mod analysis;
+mod format;
+mod line_index;
+
+use crate::parser::{Document, ParseError, TextEdit};
+
+use analysis::{definitions, node_at, semantic_tokens, TOKEN_TYPES};
+use line_index::LineIndex;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+        Notification as LspNotification, PublishDiagnostics,
+    },
+    request::{
+        DocumentSymbolRequest, Formatting, GotoDefinition, HoverRequest, Request as LspRequest,
+        SemanticTokensFullRequest,
+    },
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, DocumentFormattingParams,
+    DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability, Location,
+    MarkupContent, MarkupKind, NumberOrString, OneOf, PublishDiagnosticsParams, SemanticTokens,
+    SemanticTokensFullOptions, SemanticTokensLegend, SemanticTokensOptions, SemanticTokensParams,
+    SemanticTokensResult, SemanticTokensServerCapabilities, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Uri,
+};
+
+use std::collections::HashMap;
+use std::error::Error;
+
+pub fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::INCREMENTAL,
+        )),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: SemanticTokensLegend {
+                    token_types: TOKEN_TYPES.to_vec(),
+                    token_modifiers: Vec::new(),
+                },
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..SemanticTokensOptions::default()
+            },
+        )),
+        ..ServerCapabilities::default()
+    }
+}
+
+/// Runs the server until the client asks it to shut down.
+pub fn run(connection: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+    let mut server = Server::default();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                connection
+                    .sender
+                    .send(Message::Response(server.handle_request(request)))?;
+            }
+            Message::Notification(notification) => {
+                if let Some(reply) = server.handle_notification(notification) {
+                    connection.sender.send(Message::Notification(reply))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn respond<R: LspRequest>(
+    request: Request,
+    handler: impl FnOnce(R::Params) -> R::Result,
+) -> Response {
+    match serde_json::from_value(request.params) {
+        Ok(params) => Response::new_ok(request.id, handler(params)),
+        Err(err) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, err.to_string()),
+    }
+}
+
+fn notification_params<N: LspNotification>(notification: Notification) -> Option<N::Params> {
+    notification.extract(N::METHOD).ok()
+}
+
+fn diagnostic(uri: &Uri, index: &LineIndex, error: &ParseError) -> Diagnostic {
+    let message = match &error.label {
+        Some(label) => format!("{}: {}", error.message, label),
+        None => error.message.clone(),
+    };
+    let related = error
+        .notes
+        .iter()
+        .map(|note| DiagnosticRelatedInformation {
+            location: Location::new(uri.clone(), index.range(&note.range)),
+            message: note.message.clone(),
+        })
+        .collect::<Vec<_>>();
+    Diagnostic {
+        range: index.range(&error.range),
+        severity: Some(DiagnosticSeverity::ERROR),
+        code: Some(NumberOrString::String(error.kind.code().to_string())),
+        source: Some("spli".to_string()),
+        message,
+        related_information: if related.is_empty() {
+            None
+        } else {
+            Some(related)
+        },
+        ..Diagnostic::default()
+    }
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<Uri, Document>,
+}
+
+impl Server {
+    fn handle_request(&mut self, request: Request) -> Response {
+        match request.method.as_str() {
+            DocumentSymbolRequest::METHOD => {
+                respond::<DocumentSymbolRequest>(request, |params| self.document_symbols(params))
+            }
+            GotoDefinition::METHOD => {
+                respond::<GotoDefinition>(request, |params| self.definition(params))
+            }
+            HoverRequest::METHOD => respond::<HoverRequest>(request, |params| self.hover(params)),
+            Formatting::METHOD => respond::<Formatting>(request, |params| self.format(params)),
+            SemanticTokensFullRequest::METHOD => {
+                respond::<SemanticTokensFullRequest>(request, |params| self.semantic_tokens(params))
+            }
+            _ => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unknown method {}", request.method),
+            ),
+        }
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> Option<Notification> {
+        let uri = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params = notification_params::<DidOpenTextDocument>(notification)?;
+                let document = params.text_document;
+                self.documents
+                    .insert(document.uri.clone(), Document::new(document.text));
+                document.uri
+            }
+            DidChangeTextDocument::METHOD => {
+                let params = notification_params::<DidChangeTextDocument>(notification)?;
+                let document = self.documents.get_mut(&params.text_document.uri)?;
+                for change in params.content_changes {
+                    match change.range {
+                        Some(range) => {
+                            let index = LineIndex::new(document);
+                            let (start, end) = (index.offset(range.start), index.offset(range.end));
+                            // Reversed ranges are sent by broken clients only, but
+                            // shouldn't take the server down
+                            let range = start.min(end)..start.max(end);
+                            document.edit(&TextEdit {
+                                range,
+                                text: change.text,
+                            });
+                        }
+                        None => *document = Document::new(change.text),
+                    }
+                }
+                params.text_document.uri
+            }
+            DidCloseTextDocument::METHOD => {
+                let params = notification_params::<DidCloseTextDocument>(notification)?;
+                self.documents.remove(&params.text_document.uri);
+                let params =
+                    PublishDiagnosticsParams::new(params.text_document.uri, Vec::new(), None);
+                return Some(Notification::new(
+                    PublishDiagnostics::METHOD.to_string(),
+                    params,
+                ));
+            }
+            _ => return None,
+        };
+        let document = &self.documents[&uri];
+        let index = LineIndex::new(document);
+        let diagnostics = document
+            .errors()
+            .iter()
+            .map(|error| diagnostic(&uri, &index, error))
+            .collect();
+        let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+        Some(Notification::new(
+            PublishDiagnostics::METHOD.to_string(),
+            params,
+        ))
+    }
+
+    fn document_symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let document = self.documents.get(&params.text_document.uri)?;
+        let index = LineIndex::new(document);
+        let symbols = definitions(document.text(), document.tree())
+            .into_iter()
+            .map(|definition| {
+                #[allow(deprecated)]
+                DocumentSymbol {
+                    name: definition.name,
+                    detail: None,
+                    kind: definition.kind,
+                    tags: None,
+                    deprecated: None,
+                    range: index.range(&definition.range),
+                    selection_range: index.range(&definition.name_range),
+                    children: None,
+                }
+            })
+            .collect();
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri;
+        let document = self.documents.get(&uri)?;
+        let index = LineIndex::new(document);
+        let (start, node) = node_at(document.tree(), index.offset(position.position))?;
+        let inner = node.inner_range();
+        let name = &document.text()[start + inner.start..start + inner.end];
+        let definition = definitions(document.text(), document.tree())
+            .into_iter()
+            .find(|definition| definition.name == name)?;
+        Some(GotoDefinitionResponse::Scalar(Location::new(
+            uri.clone(),
+            index.range(&definition.name_range),
+        )))
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let position = params.text_document_position_params;
+        let document = self.documents.get(&position.text_document.uri)?;
+        let index = LineIndex::new(document);
+        let (start, node) = node_at(document.tree(), index.offset(position.position))?;
+        let value = node.to_value(document.text(), start);
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::PlainText,
+                value: value.kind.type_name().to_string(),
+            }),
+            range: Some(index.range(&(start..start + node.len))),
+        })
+    }
+
+    fn format(&self, params: DocumentFormattingParams) -> Option<Vec<lsp_types::TextEdit>> {
+        let document = self.documents.get(&params.text_document.uri)?;
+        // Formatting a broken document could lose text inside error nodes
+        if document.tree().has_errors() {
+            return None;
+        }
+        let formatted = format::format(document.text(), document.tree());
+        if formatted == document.text() {
+            return Some(Vec::new());
+        }
+        let index = LineIndex::new(document);
+        Some(vec![lsp_types::TextEdit::new(
+            index.range(&(0..document.text().len())),
+            formatted,
+        )])
+    }
+
+    fn semantic_tokens(&self, params: SemanticTokensParams) -> Option<SemanticTokensResult> {
+        let document = self.documents.get(&params.text_document.uri)?;
+        Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data: semantic_tokens(document),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_server::RequestId;
+    use lsp_types::{Position, Range};
+    use serde_json::{json, Value as Json};
+    use std::thread;
+
+    /// In-process client talking to a server running in another thread.
+    struct Client {
+        connection: Connection,
+        server: Option<thread::JoinHandle<()>>,
+        next_id: i32,
+    }
+
+    const URI: &str = "file:///test.spli";
+
+    impl Client {
+        fn start() -> Self {
+            let (server, connection) = Connection::memory();
+            let server = thread::spawn(move || run(&server).unwrap());
+            let mut client = Self {
+                connection,
+                server: Some(server),
+                next_id: 0,
+            };
+            client.request("initialize", json!({ "capabilities": {} }));
+            client.notify("initialized", json!({}));
+            client
+        }
+
+        fn request(&mut self, method: &str, params: Json) -> Json {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            let request = Request::new(id.clone(), method.to_string(), params);
+            self.connection
+                .sender
+                .send(Message::Request(request))
+                .unwrap();
+            match self.connection.receiver.recv().unwrap() {
+                Message::Response(response) => {
+                    assert_eq!(response.id, id);
+                    assert!(response.error.is_none(), "{:?}", response.error);
+                    response.result.unwrap_or(Json::Null)
+                }
+                message => panic!("expected a response, got {:?}", message),
+            }
+        }
+
+        fn notify(&self, method: &str, params: Json) {
+            let notification = Notification::new(method.to_string(), params);
+            self.connection
+                .sender
+                .send(Message::Notification(notification))
+                .unwrap();
+        }
+
+        fn diagnostics(&self) -> Vec<Json> {
+            match self.connection.receiver.recv().unwrap() {
+                Message::Notification(notification) => {
+                    assert_eq!(notification.method, PublishDiagnostics::METHOD);
+                    serde_json::from_value(notification.params["diagnostics"].clone()).unwrap()
+                }
+                message => panic!("expected diagnostics, got {:?}", message),
+            }
+        }
+
+        fn open(&self, text: &str) -> Vec<Json> {
+            self.notify(
+                DidOpenTextDocument::METHOD,
+                json!({
+                    "textDocument": { "uri": URI, "languageId": "spli", "version": 1, "text": text }
+                }),
+            );
+            self.diagnostics()
+        }
+
+        fn position_request(&mut self, method: &str, line: u32, character: u32) -> Json {
+            self.request(
+                method,
+                json!({ "textDocument": { "uri": URI }, "position": Position::new(line, character) }),
+            )
+        }
+    }
+
+    impl Drop for Client {
+        fn drop(&mut self) {
+            self.request("shutdown", Json::Null);
+            self.notify("exit", Json::Null);
+            if let Some(server) = self.server.take() {
+                server.join().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let client = Client::start();
+        let diagnostics = client.open("(a 0123)\n(b");
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0]["code"], "E0004");
+        assert_eq!(
+            diagnostics[0]["range"],
+            json!(Range::new(Position::new(0, 3), Position::new(0, 7)))
+        );
+        assert_eq!(diagnostics[1]["code"], "E0001");
+
+        client.notify(
+            DidChangeTextDocument::METHOD,
+            json!({
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [
+                    { "range": Range::new(Position::new(0, 3), Position::new(0, 4)), "text": "" },
+                    { "range": Range::new(Position::new(1, 2), Position::new(1, 2)), "text": ")" },
+                ]
+            }),
+        );
+        assert!(client.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_reversed_range() {
+        let mut client = Client::start();
+        client.open("(a\nb) (c)");
+        client.notify(
+            DidChangeTextDocument::METHOD,
+            json!({
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [
+                    { "range": Range::new(Position::new(1, 1), Position::new(0, 2)), "text": " x" },
+                ]
+            }),
+        );
+        assert!(client.diagnostics().is_empty());
+        let hover = client.position_request(HoverRequest::METHOD, 0, 6);
+        assert_eq!(
+            hover["range"],
+            json!(Range::new(Position::new(0, 6), Position::new(0, 9)))
+        );
+    }
+
+    #[test]
+    fn test_related_information() {
+        let client = Client::start();
+        let diagnostics = client.open("(a))");
+        assert_eq!(diagnostics[0]["code"], "E0008");
+        assert_eq!(
+            diagnostics[0]["relatedInformation"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_symbols_and_definition() {
+        let mut client = Client::start();
+        client.open("(def x 1)\n(defn (f a) (g x))");
+        let symbols = client.request(
+            DocumentSymbolRequest::METHOD,
+            json!({ "textDocument": { "uri": URI } }),
+        );
+        let names: Vec<_> = symbols
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|symbol| symbol["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["x", "f"]);
+
+        let definition = client.position_request(GotoDefinition::METHOD, 1, 15);
+        assert_eq!(
+            definition["range"],
+            json!(Range::new(Position::new(0, 5), Position::new(0, 6)))
+        );
+        assert_eq!(
+            client.position_request(GotoDefinition::METHOD, 1, 13),
+            Json::Null
+        );
+    }
+
+    #[test]
+    fn test_hover() {
+        let mut client = Client::start();
+        client.open("(a 0xFF \"\u{1f600}\" 1.5)");
+        let hover = client.position_request(HoverRequest::METHOD, 0, 4);
+        assert_eq!(hover["contents"]["value"], "integer");
+        let hover = client.position_request(HoverRequest::METHOD, 0, 13);
+        assert_eq!(hover["contents"]["value"], "float");
+        let hover = client.position_request(HoverRequest::METHOD, 0, 7);
+        assert_eq!(hover["contents"]["value"], "list");
+    }
+
+    #[test]
+    fn test_formatting() {
+        let mut client = Client::start();
+        client.open("( a  b )");
+        let params = json!({
+            "textDocument": { "uri": URI },
+            "options": { "tabSize": 2, "insertSpaces": true }
+        });
+        let edits = client.request(Formatting::METHOD, params.clone());
+        assert_eq!(edits[0]["newText"], "(a b)\n");
+
+        client.open("(a");
+        assert_eq!(client.request(Formatting::METHOD, params), Json::Null);
+    }
+
+    #[test]
+    fn test_semantic_tokens() {
+        let mut client = Client::start();
+        client.open("(f 1)");
+        let tokens = client.request(
+            SemanticTokensFullRequest::METHOD,
+            json!({ "textDocument": { "uri": URI } }),
+        );
+        assert_eq!(tokens["data"], json!([0, 1, 1, 1, 0, 0, 2, 1, 4, 0]));
+    }
+}
@@ -1,64 +1,209 @@
-use spli::parser::{program, token, Error, Span, determine_error};
-use std::io::{self, BufRead, Read, Write};
-use show_my_errors::{AnnotationList, Stylesheet};
+mod repl;
 
-fn show_error(filename: &str, content: &str, err: &Error) -> io::Result<()> {
-    let annotation = determine_error(content, err).unwrap();
-    let mut annotation_list = AnnotationList::new(filename, content);
-    annotation_list.add(annotation).unwrap();
-    annotation_list.show_stderr(&Stylesheet::colored())
+use spli::dump::{dump, Format};
+use spli::highlight::{highlight, Theme};
+use spli::ValueKind;
+use spli::parser::{program_resilient, FormReader, ParseError, ParseErrorKind, Report};
+use std::io::{self, IsTerminal, Read, Write};
+use show_my_errors::Stylesheet;
+use termcolor::{Ansi, NoColor};
+
+/// Whether output to `stream` should be colored: it's a terminal and `NO_COLOR` isn't set.
+pub(crate) fn use_color(stream: &impl IsTerminal) -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && stream.is_terminal()
 }
 
-fn repl() -> io::Result<()> {
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
-    stdout.write(b"spli parser> ")?;
-    stdout.flush()?;
-    for line in stdin.lock().lines() {
-        let line = line?;
-        if line.len() != 0 {
-            match token(Span::new(&line)) {
-                Ok((rest, parsed)) => {
-                    if rest.fragment().len() == 0 {
-                        println!("{} :: {}", parsed, parsed.kind.type_name())
-                    } else {
-                        println!("Parsed: {}", parsed);
-                        println!("Rest: {}", rest);
-                    }
-                }
-                Err(nom::Err::Error(err)) | Err(nom::Err::Failure(err)) => {
-                    show_error("<stdin>", &line, &err)?;
-                }
-                Err(nom::Err::Incomplete(_)) => unreachable!(),
+pub(crate) fn show_errors(filename: &str, content: &str, errors: &[ParseError]) -> io::Result<()> {
+    show_report(&Report::new(filename, content, errors))
+}
+
+fn show_report(report: &Report) -> io::Result<()> {
+    let stderr = io::stderr();
+    if use_color(&stderr) {
+        report.show(Ansi::new(stderr.lock()), &Stylesheet::colored())
+    } else {
+        report.show(NoColor::new(stderr.lock()), &Stylesheet::monochrome())
+    }
+}
+
+fn explain(code: &str) -> io::Result<()> {
+    match code.parse::<ParseErrorKind>() {
+        Ok(kind) => print!("{}", kind.explanation()),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// Prints top-level forms of `filename` (or stdin) one per line, as soon as they are read.
+fn stream(filename: Option<String>) -> io::Result<()> {
+    let (filename, reader): (_, Box<dyn Read>) = match filename {
+        Some(filename) if filename != "-" => {
+            let file = std::fs::File::open(&filename)?;
+            (filename, Box::new(file))
+        }
+        _ => ("<stdin>".to_string(), Box::new(io::stdin())),
+    };
+    let stdout = io::stdout();
+    let theme = use_color(&stdout).then(Theme::colored);
+    let mut stdout = stdout.lock();
+    for form in FormReader::new(reader) {
+        let form = form?;
+        if form.errors().is_empty() {
+            let text = form.value().to_string();
+            match &theme {
+                Some(theme) => writeln!(stdout, "{}", highlight(&text, theme))?,
+                None => writeln!(stdout, "{}", text)?,
+            }
+        } else {
+            stdout.flush()?;
+            let report = Report::new(&filename, form.text(), form.errors());
+            show_report(&report.starting_at(form.line(), form.column()))?;
+        }
+    }
+    Ok(())
+}
+
+fn read_input(filename: Option<String>) -> io::Result<(String, String)> {
+    let mut contents = String::new();
+    match filename {
+        Some(filename) if filename != "-" => {
+            std::fs::File::open(&filename)?.read_to_string(&mut contents)?;
+            Ok((filename, contents))
+        }
+        _ => {
+            io::stdin().read_to_string(&mut contents)?;
+            Ok(("<stdin>".to_string(), contents))
+        }
+    }
+}
+
+/// Prints the parsed forms of `filename` (or stdin) in the requested format.
+fn parse(mut args: impl Iterator<Item = String>) -> io::Result<()> {
+    const USAGE: &str = "usage: spli parse [--format debug|sexp|json|dot] [file|-]";
+    let mut format = Format::Sexp;
+    let mut filename = None;
+    while let Some(arg) = args.next() {
+        let name = if arg == "--format" {
+            args.next()
+        } else if let Some(name) = arg.strip_prefix("--format=") {
+            Some(name.to_string())
+        } else if filename.is_none() {
+            filename = Some(arg);
+            continue;
+        } else {
+            None
+        };
+        match name.map(|name| name.parse()) {
+            Some(Ok(parsed)) => format = parsed,
+            Some(Err(err)) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+            None => {
+                eprintln!("{}", USAGE);
+                std::process::exit(1);
+            }
+        }
+    }
+    let (filename, contents) = read_input(filename)?;
+    let (program, errors) = program_resilient(&contents);
+    if !errors.is_empty() {
+        show_errors(&filename, &contents, &errors)?;
+        std::process::exit(1);
+    }
+    if let ValueKind::List(forms) = &program.kind {
+        print!("{}", dump(forms.values(), format));
+    }
+    Ok(())
+}
+
+/// Converts `filename` (or stdin) between spli and JSON.
+#[cfg(feature = "json")]
+fn convert(mut args: impl Iterator<Item = String>) -> io::Result<()> {
+    use spli::json::{from_json, to_json};
+    use spli::ser::pretty_value;
+
+    const USAGE: &str = "usage: spli convert --from json|spli --to json|spli [file|-]";
+    let fail = |message: &dyn std::fmt::Display| -> ! {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    };
+    let (mut from, mut to, mut filename) = (None, None, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" => from = args.next(),
+            "--to" => to = args.next(),
+            _ if filename.is_none() => filename = Some(arg),
+            _ => fail(&USAGE),
+        }
+    }
+    let (filename, contents) = read_input(filename)?;
+    match (from.as_deref(), to.as_deref()) {
+        (Some("json"), Some("spli")) => {
+            let json = serde_json::from_str(&contents)
+                .unwrap_or_else(|err| fail(&format_args!("{}: {}", filename, err)));
+            println!("{}", pretty_value(&from_json(&json), 80));
+        }
+        (Some("spli"), Some("json")) => {
+            let (program, errors) = program_resilient(&contents);
+            if !errors.is_empty() {
+                show_errors(&filename, &contents, &errors)?;
+                std::process::exit(1);
             }
+            let mut forms: Vec<_> = match &program.kind {
+                ValueKind::List(forms) => forms.iter().collect(),
+                _ => Vec::new(),
+            };
+            // A single form is converted as is, several forms make an array
+            let json = match forms.len() {
+                1 => to_json(&forms.remove(0)),
+                _ => to_json(&program),
+            }
+            .unwrap_or_else(|err| fail(&err));
+            let json = serde_json::to_string_pretty(&json).unwrap_or_else(|err| fail(&err));
+            println!("{}", json);
         }
-        stdout.write(b"spli parser> ")?;
-        stdout.flush()?;
+        _ => fail(&USAGE),
     }
     Ok(())
 }
 
 fn main() -> io::Result<()> {
-    if let Some(filename) = std::env::args().skip(1).next() {
+    let mut args = std::env::args().skip(1);
+    if let Some(filename) = args.next() {
+        if filename == "explain" {
+            return match args.next() {
+                Some(code) => explain(&code),
+                None => {
+                    eprintln!("usage: spli explain <code>");
+                    std::process::exit(1);
+                }
+            };
+        }
+        if filename == "stream" {
+            return stream(args.next());
+        }
+        if filename == "parse" {
+            return parse(args);
+        }
+        #[cfg(feature = "json")]
+        if filename == "convert" {
+            return convert(args);
+        }
         let mut file = std::fs::File::open(&filename)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
-        match program(Span::new(&contents)) {
-            Ok((rest, parsed)) => {
-                if rest.fragment().len() == 0 {
-                    println!("Syntax OK");
-                } else {
-                    println!("Parsed this: {}", parsed);
-                    println!("Extra symbols at the end of program: {}", rest);
-                }
-            }
-            Err(nom::Err::Error(err)) | Err(nom::Err::Failure(err)) => {
-                show_error(&filename, &contents, &err)?;
-            }
-            Err(nom::Err::Incomplete(_)) => unreachable!(),
+        let (_, errors) = program_resilient(&contents);
+        if errors.is_empty() {
+            println!("Syntax OK");
+        } else {
+            show_errors(&filename, &contents, &errors)?;
         }
         Ok(())
     } else {
-        repl()
+        repl::run()
     }
 }
@@ -0,0 +1,112 @@
+//! Opaque host objects passed through spli code.
+
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+
+type DisplayFn = fn(&(dyn Any + Send + Sync), &mut fmt::Formatter<'_>) -> fmt::Result;
+
+/// Shared handle to a Rust object, like a connection or a file. Natives are
+/// equal only if they're clones of the same handle.
+#[derive(Clone)]
+pub struct Native {
+    object: Arc<dyn Any + Send + Sync>,
+    type_name: &'static str,
+    display: Option<DisplayFn>,
+}
+
+impl Native {
+    /// Native displayed as `<type_name>`.
+    pub fn new<T: Any + Send + Sync>(object: T, type_name: &'static str) -> Self {
+        Self::from_arc(Arc::new(object), type_name)
+    }
+
+    pub fn from_arc(object: Arc<dyn Any + Send + Sync>, type_name: &'static str) -> Self {
+        Self {
+            object,
+            type_name,
+            display: None,
+        }
+    }
+
+    /// Native displayed with the `Display` of the object.
+    pub fn with_display<T: Any + Send + Sync + fmt::Display>(
+        object: T,
+        type_name: &'static str,
+    ) -> Self {
+        Self {
+            display: Some(|object, fmt| match object.downcast_ref::<T>() {
+                Some(object) => fmt::Display::fmt(object, fmt),
+                None => unreachable!("display function of another type"),
+            }),
+            ..Self::new(object, type_name)
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.object.is::<T>()
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.object.downcast_ref()
+    }
+
+    pub fn downcast<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.object.clone().downcast().ok()
+    }
+}
+
+impl PartialEq for Native {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.object, &other.object)
+    }
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "Native({} @ {:p})",
+            self.type_name,
+            Arc::as_ptr(&self.object)
+        )
+    }
+}
+
+impl fmt::Display for Native {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.display {
+            Some(display) => display(&*self.object, fmt),
+            None => write!(fmt, "<{}>", self.type_name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_native() {
+        let native = Native::new(vec![1, 2], "pair");
+        assert_eq!(native.type_name(), "pair");
+        assert_eq!(native.to_string(), "<pair>");
+        assert!(native.is::<Vec<i32>>());
+        assert_eq!(native.downcast_ref::<Vec<i32>>(), Some(&vec![1, 2]));
+        assert_eq!(native.downcast_ref::<String>(), None);
+        assert_eq!(native.downcast::<Vec<i32>>().map(|v| v.len()), Some(2));
+        assert_eq!(native.clone(), native);
+        assert_ne!(Native::new(vec![1, 2], "pair"), native);
+    }
+
+    #[test]
+    fn test_display() {
+        let native = Native::with_display(String::from("db:5432"), "connection");
+        assert_eq!(native.to_string(), "db:5432");
+        assert_eq!(native.type_name(), "connection");
+    }
+}
@@ -1,110 +1,846 @@
-use super::{Span, Error as ParsingError};
+use super::{is_token_end, Span, Error as ParsingError};
 use nom::error::ErrorKind;
 use nom_greedyerror::GreedyErrorKind;
-use show_my_errors::{Annotation, Result};
+use show_my_errors::{Annotation, Result, Severity, Stylesheet};
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::str::FromStr;
+use termcolor::WriteColor;
 
-fn unknown_error(err: &ParsingError) -> Result<Annotation> {
-    let (span, kind) = match err.errors.iter().next() {
-        Some(error) => error,
-        None => unreachable!(),
-    };
-    let offset = span.location_offset();
-    let message = format!("unknown parsing error: {:?}", kind);
-    Annotation::error(offset..offset + 1, message, "somewhere here")
+/// Contexts attached to parser errors via `nom::error::context`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Context {
+    Escape,
+    Ident,
+    Number,
+    String,
+    List,
+    Nesting,
+}
+
+impl Context {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Escape => "escape",
+            Self::Ident => "ident",
+            Self::Number => "number",
+            Self::String => "string",
+            Self::List => "list",
+            Self::Nesting => "nesting",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "escape" => Some(Self::Escape),
+            "ident" => Some(Self::Ident),
+            "number" => Some(Self::Number),
+            "string" => Some(Self::String),
+            "list" => Some(Self::List),
+            "nesting" => Some(Self::Nesting),
+            _ => None,
+        }
+    }
+
+    fn of(kind: &GreedyErrorKind) -> Option<Self> {
+        match kind {
+            GreedyErrorKind::Context(name) => Self::from_name(name),
+            _ => None,
+        }
+    }
+}
+
+/// Kind of a parsing error. Every kind has a stable code, which never changes
+/// between releases and can be explained with `spli explain <code>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParseErrorKind {
+    UnclosedList,
+    UnclosedString,
+    UnknownEscape,
+    InvalidNumber,
+    InvalidIdent,
+    ExpectedWhitespace,
+    Unknown,
+    UnmatchedParen,
+    /// Lists are nested deeper than [`MAX_DEPTH`](super::MAX_DEPTH).
+    NestingTooDeep,
+}
+
+impl ParseErrorKind {
+    pub const ALL: &'static [Self] = &[
+        Self::UnclosedList,
+        Self::UnclosedString,
+        Self::UnknownEscape,
+        Self::InvalidNumber,
+        Self::InvalidIdent,
+        Self::ExpectedWhitespace,
+        Self::Unknown,
+        Self::UnmatchedParen,
+        Self::NestingTooDeep,
+    ];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::UnclosedList => "E0001",
+            Self::UnclosedString => "E0002",
+            Self::UnknownEscape => "E0003",
+            Self::InvalidNumber => "E0004",
+            Self::InvalidIdent => "E0005",
+            Self::ExpectedWhitespace => "E0006",
+            Self::Unknown => "E0007",
+            Self::UnmatchedParen => "E0008",
+            Self::NestingTooDeep => "E0010",
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            Self::UnclosedList => "unclosed list",
+            Self::UnclosedString => "unclosed string",
+            Self::UnknownEscape => "unknown escape code",
+            Self::InvalidNumber => "invalid number",
+            Self::InvalidIdent => "invalid identifier",
+            Self::ExpectedWhitespace => "expected whitespace after token",
+            Self::Unknown => "unknown parsing error",
+            Self::UnmatchedParen => "unmatched closing paren",
+            Self::NestingTooDeep => "nesting too deep",
+        }
+    }
+
+    /// Long explanation of the error, in Markdown.
+    pub fn explanation(self) -> &'static str {
+        match self {
+            Self::UnclosedList => include_str!("explanations/E0001.md"),
+            Self::UnclosedString => include_str!("explanations/E0002.md"),
+            Self::UnknownEscape => include_str!("explanations/E0003.md"),
+            Self::InvalidNumber => include_str!("explanations/E0004.md"),
+            Self::InvalidIdent => include_str!("explanations/E0005.md"),
+            Self::ExpectedWhitespace => include_str!("explanations/E0006.md"),
+            Self::Unknown => include_str!("explanations/E0007.md"),
+            Self::UnmatchedParen => include_str!("explanations/E0008.md"),
+            Self::NestingTooDeep => include_str!("explanations/E0010.md"),
+        }
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(self.code())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownErrorCode(pub String);
+
+impl fmt::Display for UnknownErrorCode {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "unknown error code: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownErrorCode {}
+
+impl FromStr for ParseErrorKind {
+    type Err = UnknownErrorCode;
+
+    fn from_str(code: &str) -> std::result::Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.code().eq_ignore_ascii_case(code))
+            .ok_or_else(|| UnknownErrorCode(code.into()))
+    }
+}
+
+/// Secondary label attached to a parsing error, e.g. pointing at a related paren.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    pub range: Range<usize>,
+    pub message: String,
+    pub label: Option<String>,
+}
+
+/// Fix for a parsing error: replace `range` of the source with `replacement`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+/// Parsing error, classified and located in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub range: Range<usize>,
+    pub message: String,
+    pub label: Option<String>,
+    pub notes: Vec<Note>,
+    pub suggestion: Option<Suggestion>,
+}
+
+impl ParseError {
+    pub(crate) fn new(kind: ParseErrorKind, range: Range<usize>, label: Option<&str>) -> Self {
+        Self {
+            kind,
+            range,
+            message: kind.message().into(),
+            label: label.map(Into::into),
+            notes: Vec::new(),
+            suggestion: None,
+        }
+    }
+
+    fn ranges_mut(&mut self) -> impl Iterator<Item = &mut Range<usize>> {
+        std::iter::once(&mut self.range)
+            .chain(self.notes.iter_mut().map(|note| &mut note.range))
+            .chain(self.suggestion.iter_mut().map(|suggestion| &mut suggestion.range))
+    }
+
+    /// Moves every range of the error by `delta` bytes.
+    pub fn shifted(mut self, delta: isize) -> Self {
+        for range in self.ranges_mut() {
+            *range = range.start.saturating_add_signed(delta)..range.end.saturating_add_signed(delta);
+        }
+        self
+    }
+
+    /// Updates ranges of the error after text in `edit` was replaced with text
+    /// `delta` bytes longer. Ranges overlapping the edit are stretched.
+    pub(crate) fn adjust_for_edit(&mut self, edit: &Range<usize>, delta: isize) {
+        for range in self.ranges_mut() {
+            if range.start >= edit.end {
+                range.start = range.start.saturating_add_signed(delta);
+            }
+            if range.end > edit.start || range.start >= edit.end {
+                range.end = range.end.saturating_add_signed(delta).max(range.start);
+            }
+        }
+    }
+
+    fn note(mut self, range: Range<usize>, message: &str, label: Option<&str>) -> Self {
+        self.notes.push(Note {
+            range,
+            message: message.into(),
+            label: label.map(Into::into),
+        });
+        self
+    }
+
+    /// Primary annotation of the error, without notes.
+    pub fn annotation(&self) -> Result<Annotation> {
+        let header = format!("[{}] {}", self.kind.code(), self.message);
+        Annotation::error(self.range.clone(), header, self.label.clone())
+    }
+
+    /// Primary annotation followed by an info annotation for each note.
+    pub fn annotations(&self) -> Result<Vec<Annotation>> {
+        let mut result = vec![self.annotation()?];
+        for note in &self.notes {
+            result.push(Annotation::info(note.range.clone(), note.message.clone(), note.label.clone())?);
+        }
+        Ok(result)
+    }
+}
+
+/// Clamps `offset` into `source` and onto a char boundary, in case an error
+/// comes from a different input than the one it is classified against.
+fn clamp_offset(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+fn offset_of(source: &str, span: &Span) -> usize {
+    clamp_offset(source, span.location_offset())
+}
+
+fn token_at(source: &str, offset: usize) -> &str {
+    let rest = &source[offset..];
+    &rest[..rest.find(is_token_end).unwrap_or(rest.len())]
+}
+
+/// Finds the last top-level list closed before `offset`, as offsets of its parens.
+fn last_closed_list(source: &str, offset: usize) -> Option<(usize, usize)> {
+    let mut open_parens = Vec::new();
+    let mut last_closed = None;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (idx, c) in source[..offset].char_indices() {
+        if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '(' => open_parens.push(idx),
+            ')' => {
+                if let Some(open) = open_parens.pop() {
+                    if open_parens.is_empty() {
+                        last_closed = Some((open, idx));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    last_closed
+}
+
+fn unknown_error(source: &str, err: &ParsingError) -> ParseError {
+    match err.errors.first() {
+        Some((span, kind)) => {
+            let offset = offset_of(source, span);
+            let mut error = ParseError::new(ParseErrorKind::Unknown, offset..offset + 1, Some("somewhere here"));
+            error.message = format!("{}: {:?}", error.message, kind);
+            error
+        }
+        None => ParseError::new(ParseErrorKind::Unknown, source.len()..source.len(), None),
+    }
+}
+
+fn unmatched_paren_error(source: &str, offset: usize) -> ParseError {
+    unmatched_paren(offset, last_closed_list(source, offset))
+}
+
+/// Error for a stray `)` at `offset`. `last_list` is the offsets of parens of the
+/// last top-level list before it, if any.
+pub(crate) fn unmatched_paren(offset: usize, last_list: Option<(usize, usize)>) -> ParseError {
+    let error = ParseError::new(ParseErrorKind::UnmatchedParen, offset..offset + 1, Some("unmatched `)`"));
+    match last_list {
+        Some((open, close)) => error
+            .note(open..open + 1, "this `(`...", None)
+            .note(close..close + 1, "...is closed by this `)`", None),
+        None => error,
+    }
 }
 
-fn whitespace_error(offset: usize) -> Result<Annotation> {
-    Annotation::error(offset..offset + 1, "expected whitespace after token", "here")
+/// Error for a list at `offset` nested in `max_depth` other lists.
+pub(crate) fn nesting_too_deep(offset: usize, max_depth: usize) -> ParseError {
+    let label = format!("more than {} nested lists", max_depth);
+    ParseError::new(ParseErrorKind::NestingTooDeep, offset..offset + 1, Some(&label))
 }
 
-fn escape_context_error(span: &Span) -> Result<Annotation> {
-    let offset = span.location_offset();
-    Annotation::error(offset..offset + 1, "unknown escape code", None)
+fn radix_name(number: &str) -> Option<&'static str> {
+    let number = number.trim_start_matches(['\'', '!']);
+    if number.starts_with("0x") {
+        Some("hexadecimal")
+    } else if number.starts_with("0o") {
+        Some("octal")
+    } else if number.starts_with("0b") {
+        Some("binary")
+    } else {
+        None
+    }
 }
 
-fn unclosed_list_error(span: &Span, err: &ParsingError) -> Result<Annotation> {
-    let first_list = err.errors.iter().filter_map(|(span, kind)| {
-        if let GreedyErrorKind::Context("list") = kind {
+pub(crate) fn whitespace_error(source: &str, offset: usize) -> ParseError {
+    let offset = clamp_offset(source, offset);
+    let next = source[offset..].chars().next();
+    if next == Some(')') {
+        return unmatched_paren_error(source, offset);
+    }
+
+    let token_start = source[..offset].rfind(is_token_end).map_or(0, |idx| idx + 1);
+    if let (Some(radix), Some(digit)) = (radix_name(&source[token_start..offset]), next) {
+        if digit.is_ascii_alphanumeric() {
+            let end = offset + token_at(source, offset).len();
+            let label = format!("invalid digit `{}` in {} literal", digit, radix);
+            return ParseError::new(ParseErrorKind::InvalidNumber, token_start..end, Some(&label));
+        }
+    }
+
+    let mut error = ParseError::new(ParseErrorKind::ExpectedWhitespace, offset..offset + 1, Some("here"));
+    error.suggestion = Some(Suggestion {
+        range: offset..offset,
+        replacement: " ".into(),
+    });
+    if source[..offset].ends_with(')') {
+        error.label = Some("add a space after the list".into());
+        error.note(offset - 1..offset, "list ends here", None)
+    } else if next == Some('(') {
+        error.label = Some("add a space before the list".into());
+        error
+    } else {
+        error
+    }
+}
+
+fn escape_context_error(source: &str, span: &Span) -> ParseError {
+    let offset = offset_of(source, span);
+    ParseError::new(ParseErrorKind::UnknownEscape, offset..offset + 1, None)
+}
+
+fn unclosed_list_error(source: &str, span: &Span, err: &ParsingError) -> ParseError {
+    let first_list = err.errors.iter().find_map(|(span, kind)| {
+        if Context::of(kind) == Some(Context::List) {
             Some(span)
         } else {
             None
         }
-    }).next();
+    });
     if let Some(list_span) = first_list {
-        let offset = list_span.location_offset();
-        Annotation::error(offset..offset + 1, "unclosed list", "started here")
+        let offset = offset_of(source, list_span);
+        ParseError::new(ParseErrorKind::UnclosedList, offset..offset + 1, Some("started here"))
     } else {
-        let offset = span.location_offset() - 1;
-        Annotation::error(offset..offset, "unclosed list", None)
+        let offset = offset_of(source, span).saturating_sub(1);
+        ParseError::new(ParseErrorKind::UnclosedList, offset..offset, None)
     }
 }
 
-fn invalid_ident_error(span: &Span) -> Result<Annotation> {
-    let ident = span.fragment().split_ascii_whitespace().next().unwrap_or("");
-    let offset = span.location_offset();
-    Annotation::error(offset..offset + ident.chars().count(), "invalid identifier", None)
+fn invalid_ident_error(source: &str, span: &Span) -> ParseError {
+    let offset = offset_of(source, span);
+    if span.fragment().starts_with(')') || span.fragment().is_empty() {
+        if source[..offset].ends_with(['\'', '!']) {
+            let label = "expected a token after the modifier";
+            return ParseError::new(ParseErrorKind::InvalidIdent, offset - 1..offset, Some(label));
+        }
+        if !span.fragment().is_empty() {
+            return unmatched_paren_error(source, offset);
+        }
+    }
+    let ident = token_at(source, offset);
+    ParseError::new(ParseErrorKind::InvalidIdent, offset..offset + ident.len(), None)
 }
 
-fn number_error(span: &Span) -> Result<Annotation> {
-    let fragment = span.fragment();
-    let number = fragment.split_ascii_whitespace().next().unwrap_or("");
-    let offset = span.location_offset();
-    Annotation::error(offset..offset + number.chars().count(), "invalid number", None)
+fn number_error(source: &str, span: &Span) -> ParseError {
+    let offset = offset_of(source, span);
+    let number = token_at(source, offset);
+    let range = offset..offset + number.len();
+    let significant = number.trim_start_matches('0');
+    let has_leading_zeros = number.len() > 1
+        && significant.len() < number.len()
+        && !significant.is_empty()
+        && significant.chars().all(|c| c.is_ascii_digit());
+    if !has_leading_zeros {
+        return ParseError::new(ParseErrorKind::InvalidNumber, range, None);
+    }
+
+    let replacement = if significant.chars().all(|c| c < '8') {
+        format!("0o{}", significant)
+    } else {
+        significant.to_owned()
+    };
+    let label = format!("leading zeros not allowed; did you mean `{}`?", replacement);
+    let mut error = ParseError::new(ParseErrorKind::InvalidNumber, range.clone(), Some(&label));
+    error.suggestion = Some(Suggestion { range, replacement });
+    error
 }
 
-fn string_error(span: &Span, err: &ParsingError) -> Result<Annotation> {
+fn string_error(source: &str, span: &Span, err: &ParsingError) -> ParseError {
     match err.errors.first() {
         Some((_, GreedyErrorKind::Char('"'))) => {
-            let offset = span.location_offset();
-            Annotation::error(offset..offset + 1, "unclosed string", "started here")
+            let offset = offset_of(source, span);
+            ParseError::new(ParseErrorKind::UnclosedString, offset..offset + 1, Some("started here"))
         },
-        None => unreachable!(),
-        _ => unknown_error(err)
+        _ => unknown_error(source, err)
     }
 }
 
-fn list_error(source: &str, span: &Span, err: &ParsingError) -> Result<Annotation> {
+fn list_error(source: &str, span: &Span, err: &ParsingError) -> ParseError {
     match err.errors.first() {
         Some((first_span, GreedyErrorKind::Nom(ErrorKind::MultiSpace))) => {
-            if first_span.location_offset() == source.len() {
-                unclosed_list_error(span, err)
+            if first_span.location_offset() >= source.len() {
+                unclosed_list_error(source, span, err)
             } else {
-                whitespace_error(first_span.location_offset())
+                whitespace_error(source, first_span.location_offset())
             }
         },
-        None => unreachable!(),
-        _ => unknown_error(err)
+        _ => unknown_error(source, err)
     }
 }
 
-pub fn determine_error(source: &str, err: &ParsingError) -> Result<Annotation> {
-    let context = err.errors.iter().filter_map(|(span, kind)| {
-        if let GreedyErrorKind::Context(context) = kind {
-            Some((context, span))
+pub fn classify_error(source: &str, err: &ParsingError) -> ParseError {
+    let context = err.errors.iter().find_map(|(span, kind)| {
+        if let GreedyErrorKind::Context(name) = kind {
+            Some((Context::from_name(name), span))
         } else {
             None
         }
-    }).next();
+    });
 
     match context {
-        Some((&"escape", span)) => escape_context_error(span),
-        Some((&"ident", span)) => {
-            if span.fragment().len() == 0 && span.location_offset() == source.len() {
-                unclosed_list_error(span, err)
+        Some((Some(Context::Escape), span)) => escape_context_error(source, span),
+        Some((Some(Context::Ident), span)) => {
+            let after_modifier = source.ends_with(['\'', '!']);
+            if span.fragment().is_empty() && span.location_offset() >= source.len() && !after_modifier {
+                unclosed_list_error(source, span, err)
             } else {
-                invalid_ident_error(span)
+                invalid_ident_error(source, span)
             }
         },
-        Some((&"number", span)) => number_error(span),
-        Some((&"string", span)) => string_error(span, err),
-        Some((&"list", span)) => list_error(source, span, err),
+        Some((Some(Context::Number), span)) => number_error(source, span),
+        Some((Some(Context::String), span)) => string_error(source, span, err),
+        Some((Some(Context::List), span)) => list_error(source, span, err),
+        Some((Some(Context::Nesting), span)) => {
+            nesting_too_deep(offset_of(source, span), super::MAX_DEPTH)
+        }
+        Some((None, _)) => unknown_error(source, err),
         None => {
             if let Some((span, GreedyErrorKind::Nom(ErrorKind::MultiSpace))) = err.errors.first() {
-                whitespace_error(span.location_offset())
+                whitespace_error(source, span.location_offset())
             } else {
-                unknown_error(err)
+                unknown_error(source, err)
+            }
+        }
+    }
+}
+
+/// Classifies any error returned by a parser, including `Incomplete`, which
+/// the complete parsers used here shouldn't produce.
+pub fn classify_nom_error(source: &str, err: &nom::Err<ParsingError>) -> ParseError {
+    match err {
+        nom::Err::Error(err) | nom::Err::Failure(err) => classify_error(source, err),
+        nom::Err::Incomplete(_) => {
+            ParseError::new(ParseErrorKind::Unknown, source.len()..source.len(), None)
+        }
+    }
+}
+
+pub fn determine_error(source: &str, err: &ParsingError) -> Result<Annotation> {
+    classify_error(source, err).annotation()
+}
+
+/// Parse errors prepared for display, laid out like `AnnotationList` does.
+///
+/// `AnnotationList` finds lines by char index but slices them by byte index,
+/// which garbles every line after a non-ASCII char, and rejects ranges that
+/// don't fit into a single line. `Report` finds the line of each annotation
+/// itself, counts columns in chars and fits every range into its line, so
+/// that any error can be shown with the source as it is.
+#[derive(Debug, Clone)]
+pub struct Report {
+    filename: String,
+    /// Annotations with char ranges into their lines, in the order of lines.
+    lines: Vec<ReportLine>,
+}
+
+#[derive(Debug, Clone)]
+struct ReportLine {
+    /// Line number, starting from 1.
+    number: usize,
+    /// Column of the start of `content` in the line, starting from 0.
+    first_column: usize,
+    content: String,
+    annotation: Annotation,
+}
+
+impl ReportLine {
+    fn new(
+        source: &str,
+        severity: Severity,
+        range: &Range<usize>,
+        header: String,
+        text: Option<String>,
+    ) -> Option<Self> {
+        let (number, content, columns) = line_of(source, range);
+        // Can't fail: the range of columns isn't reversed
+        let annotation = Annotation::new(columns, severity, header, text).ok()?;
+        Some(Self {
+            number,
+            first_column: 0,
+            content: content.into(),
+            annotation,
+        })
+    }
+}
+
+impl Report {
+    pub fn new(filename: impl Into<String>, source: &str, errors: &[ParseError]) -> Self {
+        let mut lines = Vec::new();
+        for error in errors {
+            let annotations = std::iter::once((
+                Severity::Error,
+                &error.range,
+                format!("[{}] {}", error.kind.code(), error.message),
+                error.label.clone(),
+            ))
+            .chain(error.notes.iter().map(|note| {
+                (Severity::Info, &note.range, note.message.clone(), note.label.clone())
+            }));
+            for (severity, range, header, text) in annotations {
+                lines.extend(ReportLine::new(source, severity, range, header, text));
+            }
+        }
+        lines.sort_by_key(|line| line.number);
+        Self { filename: filename.into(), lines }
+    }
+
+    /// Report of a single error that isn't a parsing error and has no code,
+    /// like a value that doesn't match the type it's deserialized into.
+    pub fn uncoded(
+        filename: impl Into<String>,
+        source: &str,
+        range: &Range<usize>,
+        message: impl Into<String>,
+    ) -> Self {
+        let line = ReportLine::new(source, Severity::Error, range, message.into(), None);
+        Self {
+            filename: filename.into(),
+            lines: line.into_iter().collect(),
+        }
+    }
+
+    /// Report for a source that starts at `line` and `column` (both counting
+    /// from 1) of the file, like a form read from a stream.
+    pub fn starting_at(mut self, line: usize, column: usize) -> Self {
+        for report_line in &mut self.lines {
+            if report_line.number == 1 {
+                report_line.first_column = column.saturating_sub(1);
+            }
+            report_line.number += line.saturating_sub(1);
+        }
+        self
+    }
+
+    /// Writes the annotations to `stream`, colored with `stylesheet`.
+    pub fn show(&self, mut stream: impl WriteColor, stylesheet: &Stylesheet) -> io::Result<()> {
+        for (idx, line) in self.lines.iter().enumerate() {
+            let annotation = &line.annotation;
+            let range = annotation.range();
+            if idx > 0 {
+                writeln!(stream)?;
+            }
+            let severity_color = stylesheet.by_severity(&annotation.severity);
+            stream.set_color(severity_color)?;
+            write!(stream, "{}:", annotation.severity)?;
+            match &annotation.header {
+                Some(header) => writeln!(stream, " {}", header)?,
+                None => writeln!(stream)?,
+            }
+            let number = line.number.to_string();
+            let gutter = " ".repeat(number.len() + 2);
+            stream.set_color(&stylesheet.linenr)?;
+            write!(stream, "{}--> ", &gutter[1..])?;
+            stream.set_color(&stylesheet.filename)?;
+            let column = line.first_column + range.start + 1;
+            writeln!(stream, "{}:{}:{}", self.filename, number, column)?;
+            stream.set_color(&stylesheet.linenr)?;
+            write!(stream, "{}|\n {} | ", gutter, number)?;
+            stream.set_color(&stylesheet.content)?;
+            writeln!(stream, "{}", line.content)?;
+            stream.set_color(&stylesheet.linenr)?;
+            write!(stream, "{}|", gutter)?;
+            if !range.is_empty() {
+                stream.set_color(severity_color)?;
+                write!(stream, "{}{}", " ".repeat(range.start + 1), "^".repeat(range.len()))?;
+                if let Some(text) = &annotation.text {
+                    write!(stream, " {}", text)?;
+                }
+            }
+            writeln!(stream)?;
+            stream.reset()?;
+        }
+        Ok(())
+    }
+}
+
+/// Shows the report without colors.
+impl fmt::Display for Report {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buffer = termcolor::NoColor::new(Vec::new());
+        self.show(&mut buffer, &Stylesheet::monochrome()).map_err(|_| fmt::Error)?;
+        fmt.write_str(&String::from_utf8_lossy(&buffer.into_inner()))
+    }
+}
+
+/// Finds the line of a byte range in `source`: its number, its text without
+/// the line break, and the range as chars of the line that doesn't go past
+/// the line break.
+fn line_of<'s>(source: &'s str, range: &Range<usize>) -> (usize, &'s str, Range<usize>) {
+    let mut start = clamp_offset(source, range.start);
+    // The end of input is shown at the last line break, not on an empty line
+    if start == source.len() && source.ends_with('\n') {
+        start -= 1;
+    }
+    let line_start = source[..start].rfind('\n').map_or(0, |idx| idx + 1);
+    let line_end = source[start..].find('\n').map_or(source.len(), |idx| start + idx);
+    let content = &source[line_start..line_end];
+    let column = |offset: usize| source[line_start..offset].chars().count();
+    let end = clamp_offset(source, range.end).clamp(start, line_end);
+    let mut columns = column(start)..column(end);
+    if range.end > line_end && line_end < source.len() {
+        // The line break itself
+        columns.end += 1;
+    }
+    (source[..line_start].matches('\n').count() + 1, content, columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{program, program_resilient, token, MAX_DEPTH};
+    use proptest::prelude::*;
+
+    fn classify(source: &str) -> ParseError {
+        match program(Span::new(source)) {
+            Err(nom::Err::Error(err)) | Err(nom::Err::Failure(err)) => classify_error(source, &err),
+            _ => panic!("{:?} parsed successfully", source),
+        }
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        assert_eq!(classify("(a b").kind, ParseErrorKind::UnclosedList);
+        assert_eq!(classify("\"abc").kind, ParseErrorKind::UnclosedString);
+        assert_eq!(classify("\"a\\q\"").kind, ParseErrorKind::UnknownEscape);
+        assert_eq!(classify("0123").kind, ParseErrorKind::InvalidNumber);
+        assert_eq!(classify("(a ~b)").kind, ParseErrorKind::InvalidIdent);
+        assert_eq!(classify("(a)(b)").kind, ParseErrorKind::ExpectedWhitespace);
+        assert_eq!(classify(")").kind, ParseErrorKind::UnmatchedParen);
+        assert_eq!(classify("(a ')").range, 3..4);
+        let deep = "(".repeat(MAX_DEPTH + 1) + &")".repeat(MAX_DEPTH + 1);
+        let error = classify(&deep);
+        assert_eq!(error.kind, ParseErrorKind::NestingTooDeep);
+        assert_eq!(error.range, MAX_DEPTH..MAX_DEPTH + 1);
+    }
+
+    #[test]
+    fn test_leading_zeros() {
+        let error = classify("(a 0123)");
+        assert_eq!(error.range, 3..7);
+        assert_eq!(
+            error.suggestion,
+            Some(Suggestion { range: 3..7, replacement: "0o123".into() })
+        );
+        assert_eq!(
+            error.label.as_deref(),
+            Some("leading zeros not allowed; did you mean `0o123`?")
+        );
+        assert_eq!(classify("0089").suggestion.unwrap().replacement, "89");
+        assert_eq!(classify("00").suggestion, None);
+    }
+
+    #[test]
+    fn test_radix_digits() {
+        let error = classify("(0b123 a)");
+        assert_eq!(error.kind, ParseErrorKind::InvalidNumber);
+        assert_eq!(error.range, 1..6);
+        assert_eq!(error.label.as_deref(), Some("invalid digit `2` in binary literal"));
+        assert_eq!(
+            classify("0xfg").label.as_deref(),
+            Some("invalid digit `g` in hexadecimal literal")
+        );
+    }
+
+    #[test]
+    fn test_glued_list() {
+        let error = classify("(a)(b)");
+        assert_eq!(error.range, 3..4);
+        assert_eq!(error.suggestion, Some(Suggestion { range: 3..3, replacement: " ".into() }));
+        assert_eq!(error.notes.len(), 1);
+        assert_eq!(error.notes[0].range, 2..3);
+        assert_eq!(classify("a(b)").label.as_deref(), Some("add a space before the list"));
+    }
+
+    #[test]
+    fn test_unmatched_paren() {
+        let error = classify("(a \")\" (b))) c");
+        assert_eq!(error.kind, ParseErrorKind::UnmatchedParen);
+        assert_eq!(error.range, 11..12);
+        let note_ranges: Vec<_> = error.notes.iter().map(|note| note.range.clone()).collect();
+        assert_eq!(note_ranges, vec![0..1, 10..11]);
+    }
+
+    #[test]
+    fn test_codes() {
+        for &kind in ParseErrorKind::ALL {
+            assert_eq!(kind.code().parse(), Ok(kind));
+            assert!(kind.explanation().starts_with(&format!("# {}", kind.code())));
+        }
+        assert_eq!("e0003".parse(), Ok(ParseErrorKind::UnknownEscape));
+        assert_eq!("E9999".parse::<ParseErrorKind>(), Err(UnknownErrorCode("E9999".into())));
+    }
+
+    #[test]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn test_report_edge_cases() {
+        let error = ParseError::new(ParseErrorKind::UnclosedList, 0..0, None);
+        for source in &["", "\n", "((", "\"\u{e9}\u{e9}\"\n(", "a\nb"] {
+            for range in [0..0, 0..100, 100..200, 1..0, 3..4] {
+                let mut error = error.clone();
+                error.range = range;
+                let output = Report::new("test", source, &[error]).to_string();
+                assert!(output.contains("unclosed list"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_report_non_ascii() {
+        let source = "(caf\u{e9} \"\u{1F600}\")\n(na\u{ef}ve ~x)";
+        let (_, errors) = program_resilient(source);
+        let output = Report::new("test", source, &errors).to_string();
+        assert!(output.contains(" 2 | (na\u{ef}ve ~x)\n"), "{}", output);
+        assert!(output.contains("test:2:8"), "{}", output);
+        assert!(output.contains("   |        ^^"), "{}", output);
+        let error = ParseError::new(ParseErrorKind::UnclosedString, 7..13, None);
+        let output = Report::new("test", source, &[error]).to_string();
+        assert!(output.contains(" 1 | (caf\u{e9} \"\u{1F600}\")\n"), "{}", output);
+        assert!(output.contains("test:1:7"), "{}", output);
+        assert!(output.contains("   |       ^^^\n"), "{}", output);
+    }
+
+    #[test]
+    fn test_report_starting_at() {
+        let source = "(a ~b\n ~c)";
+        let (_, errors) = program_resilient(source);
+        let output = Report::new("test", source, &errors).starting_at(9, 5).to_string();
+        assert!(output.contains("test:9:8\n"), "{}", output);
+        assert!(output.contains(" 9 | (a ~b\n"), "{}", output);
+        assert!(output.contains("test:10:2\n"), "{}", output);
+        assert!(output.contains(" 10 |  ~c)\n"), "{}", output);
+        // Positions count from 1, but 0 doesn't underflow
+        let output = Report::new("test", source, &errors).starting_at(0, 0).to_string();
+        assert!(output.contains("test:1:4\n"), "{}", output);
+    }
+
+    /// Inputs built from characters that are meaningful to the parser, so that
+    /// the interesting error paths get hit often.
+    fn spli_like() -> impl Strategy<Value = String> {
+        proptest::string::string_regex("[()'!\"\\\\ \t\n0-9a-fxob.~\u{e9}\u{1F600}]{0,40}").unwrap()
+    }
+
+    fn assert_renders(source: &str, errors: &[ParseError]) {
+        let output = Report::new("test", source, errors).to_string();
+        assert_eq!(output.matches("error: [E").count(), errors.len());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_program_errors_render(source in spli_like()) {
+            if let Err(err) = program(Span::new(&source)) {
+                assert_renders(&source, &[classify_nom_error(&source, &err)]);
+            }
+        }
+
+        #[test]
+        fn prop_token_errors_render(source in spli_like()) {
+            if let Err(err) = token(Span::new(&source)) {
+                assert_renders(&source, &[classify_nom_error(&source, &err)]);
+            }
+        }
+
+        #[test]
+        fn prop_resilient_errors_render(source in spli_like()) {
+            let (_, errors) = program_resilient(&source);
+            assert_renders(&source, &errors);
+        }
+
+        #[test]
+        fn prop_arbitrary_input(source in any::<String>()) {
+            let (_, errors) = program_resilient(&source);
+            assert_renders(&source, &errors);
+        }
+
+        #[test]
+        fn prop_error_from_other_source(source in spli_like(), other in spli_like()) {
+            if let Err(err) = program(Span::new(&other)) {
+                assert_renders(&source, &[classify_nom_error(&source, &err)]);
             }
         }
-        _ => unknown_error(err)
     }
 }
@@ -0,0 +1,342 @@
+use crate::Value;
+
+use super::resilient::{last_closed_list, Parser};
+use super::{is_multispace, syntax_tree, unmatched_paren, Child, ParseError, ParseErrorKind, SyntaxNode};
+
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Replacement of `range` of a document with `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+/// Source text together with its parse tree and the offsets of its lines,
+/// which are updated incrementally when the text is edited.
+#[derive(Debug, Clone)]
+pub struct Document {
+    text: String,
+    tree: Arc<SyntaxNode>,
+    line_starts: Vec<usize>,
+}
+
+/// Node on the path from the root to the edited list.
+struct PathNode {
+    node: Arc<SyntaxNode>,
+    start: usize,
+    index: usize,
+}
+
+impl Document {
+    pub fn new(text: impl Into<String>) -> Self {
+        let text = text.into();
+        let tree = Arc::new(syntax_tree(&text));
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        Self {
+            text,
+            tree,
+            line_starts,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn tree(&self) -> &SyntaxNode {
+        &self.tree
+    }
+
+    /// Byte offsets of the starts of lines, the first one being 0.
+    pub fn line_starts(&self) -> &[usize] {
+        &self.line_starts
+    }
+
+    pub fn errors(&self) -> Vec<ParseError> {
+        self.tree.collect_errors(0)
+    }
+
+    pub fn value(&self) -> Value<'_> {
+        self.tree.to_value(&self.text, 0)
+    }
+
+    /// Finds closed lists containing `range` between their parens,
+    /// starting from the root.
+    fn path_to(&self, range: &Range<usize>) -> Vec<PathNode> {
+        let mut path = vec![PathNode {
+            node: self.tree.clone(),
+            start: 0,
+            index: 0,
+        }];
+        'descend: loop {
+            let parent = path[path.len() - 1].node.clone();
+            let parent_start = path[path.len() - 1].start;
+            for (index, child) in parent.children().iter().enumerate() {
+                let start = parent_start + child.offset;
+                if start > range.start {
+                    break;
+                }
+                let inner = child.node.inner_range();
+                if child.node.is_closed_list()
+                    && start + inner.start <= range.start
+                    && range.end <= start + inner.end
+                {
+                    path.push(PathNode {
+                        node: child.node.clone(),
+                        start,
+                        index,
+                    });
+                    continue 'descend;
+                }
+            }
+            break path;
+        }
+    }
+
+    /// Applies `edit` to the text and updates the tree. Only the smallest closed
+    /// list around the edit is reparsed, unless the edit unbalances it: then
+    /// its parents are tried, up to the top-level form. Edits outside of lists
+    /// or unbalancing the top-level form reparse the top-level forms around
+    /// them, and the forms after them are reused once the parser reaches one.
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds or doesn't lie on char boundaries,
+    /// like [`String::replace_range`].
+    pub fn edit(&mut self, edit: &TextEdit) {
+        let mut path = self.path_to(&edit.range);
+        self.text.replace_range(edit.range.clone(), &edit.text);
+        let delta = edit.text.len() as isize - edit.range.len() as isize;
+        self.update_line_starts(edit, delta);
+
+        while path.len() > 1 {
+            let PathNode { node, start, .. } = &path[path.len() - 1];
+            let len = node.len.saturating_add_signed(delta);
+            // The root isn't a list, so the node is nested in `path.len() - 2` lists
+            let mut parser = Parser::new(&self.text[*start..*start + len]).at_depth(path.len() - 2);
+            let new_node = parser.node();
+            if parser.offset() == len && new_node.is_closed_list() {
+                let form = Child {
+                    offset: path[1].start,
+                    node: Arc::new(replace_in_path(&path[1..], new_node, &edit.range, delta)),
+                };
+                let index = path[1].index;
+                // The path holds the root too, which would make it cloned below
+                drop(path);
+                let root = Arc::make_mut(&mut self.tree);
+                let mut errors = mem::take(&mut root.errors);
+                for error in &mut errors {
+                    error.adjust_for_edit(&edit.range, delta);
+                }
+                root.splice_children(index..index + 1, vec![form], delta, errors);
+                return;
+            }
+            path.pop();
+        }
+        drop(path);
+        self.reparse_forms(&edit.range, delta);
+    }
+
+    /// Replaces starts of lines after line breaks in the edited range with the
+    /// ones in the new text and moves the following lines by `delta` bytes.
+    fn update_line_starts(&mut self, edit: &TextEdit, delta: isize) {
+        let first = self.line_starts.partition_point(|&start| start <= edit.range.start);
+        let last = self.line_starts.partition_point(|&start| start <= edit.range.end);
+        let inserted: Vec<_> = edit
+            .text
+            .match_indices('\n')
+            .map(|(idx, _)| edit.range.start + idx + 1)
+            .collect();
+        let moved = first + inserted.len();
+        self.line_starts.splice(first..last, inserted);
+        for start in &mut self.line_starts[moved..] {
+            *start = start.saturating_add_signed(delta);
+        }
+    }
+
+    /// Reparses the top-level forms around `edit`, which is already applied to
+    /// the text. Parsing starts after the last form before the edit that is
+    /// followed by whitespace and stops at the first form after the edit
+    /// preceded by whitespace, so that errors of the reused forms don't depend
+    /// on the reparsed ones.
+    fn reparse_forms(&mut self, edit: &Range<usize>, delta: isize) {
+        let text = &self.text;
+        let root = Arc::make_mut(&mut self.tree);
+        let forms = root.children();
+        let form_end = |index: usize| forms[index].offset + forms[index].node.len;
+
+        let mut first = forms.partition_point(|form| form.offset + form.node.len < edit.start);
+        while first > 0 && !text[form_end(first - 1)..].starts_with(is_multispace) {
+            first -= 1;
+        }
+        let prefix_end = first.checked_sub(1).map(form_end);
+        let start = prefix_end.unwrap_or(0);
+        let reusable = |offset: usize| {
+            let old_offset = offset.checked_add_signed(-delta)?;
+            let index = forms.binary_search_by_key(&old_offset, |form| form.offset).ok()?;
+            (old_offset > edit.end && text[..offset].ends_with(is_multispace)).then_some(index)
+        };
+
+        let last_list = last_closed_list(&forms[..first]);
+        let mut parser = Parser::new(text).starting_at(start);
+        let (new_forms, new_errors) = parser.forms(last_list, |offset| reusable(offset).is_some());
+        let end = reusable(parser.offset()).unwrap_or(forms.len());
+
+        // Errors after a modifier may start at the end of the form
+        let mut errors: Vec<_> = root
+            .errors
+            .iter()
+            .filter(|error| prefix_end.is_some_and(|end| error.range.start <= end))
+            .cloned()
+            .collect();
+        errors.extend(new_errors);
+        if let Some(tail) = forms.get(end) {
+            let last_list = last_closed_list(&new_forms).or(last_list);
+            for error in root.errors.iter().filter(|error| error.range.start >= tail.offset) {
+                // Notes of stray `)` point to the last list before them, which
+                // may have been reparsed
+                let before_lists = error.kind == ParseErrorKind::UnmatchedParen
+                    && !forms[end..]
+                        .iter()
+                        .take_while(|form| form.offset < error.range.start)
+                        .any(|form| form.node.is_closed_list());
+                errors.push(if before_lists {
+                    unmatched_paren(error.range.start.saturating_add_signed(delta), last_list)
+                } else {
+                    error.clone().shifted(delta)
+                });
+            }
+        }
+        root.splice_children(first..end, new_forms, delta, errors);
+    }
+}
+
+/// Replaces the last node of `path` with `new_node`, rebuilding its ancestors
+/// up to the first node of `path`.
+fn replace_in_path(
+    path: &[PathNode],
+    new_node: SyntaxNode,
+    edit: &Range<usize>,
+    delta: isize,
+) -> SyntaxNode {
+    let mut replacement = new_node;
+    for (parent, child) in path.iter().zip(&path[1..]).rev() {
+        let mut children = parent.node.children().to_vec();
+        children[child.index].node = Arc::new(replacement);
+        for sibling in &mut children[child.index + 1..] {
+            sibling.offset = sibling.offset.saturating_add_signed(delta);
+        }
+        let relative_edit = edit.start - parent.start..edit.end - parent.start;
+        let mut errors = parent.node.errors.clone();
+        for error in &mut errors {
+            error.adjust_for_edit(&relative_edit, delta);
+        }
+        let len = parent.node.len.saturating_add_signed(delta);
+        replacement = parent.node.with_children_replaced(len, children, errors);
+    }
+    replacement
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn edit(document: &mut Document, range: Range<usize>, text: &str) {
+        document.edit(&TextEdit {
+            range,
+            text: text.into(),
+        });
+        let expected = Document::new(document.text());
+        assert_eq!(document.tree(), expected.tree());
+        assert_eq!(document.line_starts(), expected.line_starts());
+    }
+
+    #[test]
+    fn test_reuses_siblings() {
+        let mut document = Document::new("(a (b c)) (d e)\n(f)");
+        let before: Vec<_> = document.tree().children().iter().map(|child| child.node.clone()).collect();
+        edit(&mut document, 4..5, "bb");
+        let after = document.tree().children();
+        assert!(!Arc::ptr_eq(&before[0], &after[0].node));
+        assert!(Arc::ptr_eq(&before[1], &after[1].node));
+        assert!(Arc::ptr_eq(&before[2], &after[2].node));
+        assert_eq!(after[1].offset, 11);
+        assert_eq!(document.value().to_string(), "!((a (bb c)) (d e) (f))");
+    }
+
+    #[test]
+    fn test_reuses_top_level_forms() {
+        let mut document = Document::new("(a) b (c)\n(d) ) (e)");
+        let before: Vec<_> = document.tree().children().iter().map(|child| child.node.clone()).collect();
+        edit(&mut document, 4..5, "x)");
+        let after = document.tree().children();
+        assert_eq!(after.len(), 7);
+        assert!(Arc::ptr_eq(&before[0], &after[0].node));
+        assert!(Arc::ptr_eq(&before[2], &after[3].node));
+        assert!(Arc::ptr_eq(&before[3], &after[4].node));
+        assert!(Arc::ptr_eq(&before[5], &after[6].node));
+        assert_eq!(after[6].offset, 17);
+        let errors = document.errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].notes[0].range, 0..1);
+        assert_eq!(errors[1].range, 15..16);
+
+        // An unclosed string takes all forms after it
+        edit(&mut document, 7..7, "\"");
+        assert_eq!(document.tree().children().len(), 4);
+    }
+
+    #[test]
+    fn test_unbalancing_edits() {
+        let mut document = Document::new("(a (b c)) (d)");
+        edit(&mut document, 5..5, ")");
+        edit(&mut document, 5..6, "");
+        edit(&mut document, 4..4, "\"");
+        edit(&mut document, 4..5, "");
+        edit(&mut document, 8..9, "");
+        assert_eq!(document.text(), "(a (b c) (d)");
+        assert_eq!(document.errors().len(), 1);
+    }
+
+    #[test]
+    fn test_errors_after_edit() {
+        let mut document = Document::new("(a 0123) (b)(c)");
+        edit(&mut document, 1..2, "abc");
+        let errors = document.errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].range, 5..9);
+        assert_eq!(errors[1].range, 14..15);
+        assert_eq!(errors[1].notes[0].range, 13..14);
+    }
+
+    fn char_boundary(text: &str, idx: usize) -> usize {
+        let mut idx = idx % (text.len() + 1);
+        while !text.is_char_boundary(idx) {
+            idx -= 1;
+        }
+        idx
+    }
+
+    proptest! {
+        #[test]
+        fn prop_incremental_matches_full(
+            text in "[()'!\"\\\\ \n0-9a-c.\u{e9}]{0,40}",
+            edits in proptest::collection::vec(
+                (any::<usize>(), 0..4usize, "[()'!\"\\\\ \n0-9a-c.]{0,4}"),
+                1..8,
+            ),
+        ) {
+            let mut document = Document::new(text);
+            for (start, len, replacement) in edits {
+                let start = char_boundary(document.text(), start);
+                let end = char_boundary(document.text(), start + len).max(start);
+                edit(&mut document, start..end, &replacement);
+            }
+        }
+    }
+}
@@ -3,14 +3,15 @@
 use crate::{list, list::List};
 use crate::{Value, ValueKind};
 
-use super::{float, ident, integer, string, Error, IResult, Span};
+use super::number::with_text;
+use super::{float, ident, integer, string, Context, Error, IResult, Span, MAX_DEPTH};
 
 use nom::{
     branch::alt,
     bytes::complete::take,
     character::complete::{char as one_char, digit1, multispace0, multispace1},
     combinator::{map, peek, value},
-    error::context,
+    error::{context, ErrorKind, ParseError},
     multi::separated_list,
     sequence::{self, delimited, preceded},
 };
@@ -33,26 +34,34 @@ fn modifier(i: Span) -> IResult<Modifier> {
     )(i)
 }
 
-fn token_kind<'a>(i: Span<'a>) -> IResult<ValueKind<'a>> {
+/// Kind of a token nested in `depth` lists.
+fn token_kind<'a>(i: Span<'a>, depth: usize) -> IResult<'a, ValueKind<'a>> {
     if peek::<_, _, Error<'a>, _>(one_char('"'))(i).is_ok() {
-        context("string", map(string, ValueKind::String))(i)
+        context(Context::String.name(), map(string, ValueKind::String))(i)
     } else if peek::<_, _, Error<'a>, _>(one_char('('))(i).is_ok() {
-        context("list", map(list, ValueKind::List))(i)
+        context(
+            Context::List.name(),
+            map(|i| nested_list(i, depth), ValueKind::List),
+        )(i)
     } else if peek::<_, _, Error<'a>, _>(digit1)(i).is_ok() {
-        context("number",
+        context(Context::Number.name(),
             alt((
-                map(float, ValueKind::Float),
-                map(integer, ValueKind::Integer),
+                map(with_text(float), ValueKind::Float),
+                map(with_text(integer), ValueKind::Integer),
             ))
         )(i)
     } else {
-        context("ident", map(ident, |s| ValueKind::Symbol(s.fragment())))(i)
+        context(Context::Ident.name(), map(ident, |s| ValueKind::Symbol(s.fragment())))(i)
     }
 }
 
-pub fn token<'a>(i: Span<'a>) -> IResult<Value<'a>> {
+pub fn token<'a>(i: Span<'a>) -> IResult<'a, Value<'a>> {
+    nested_token(i, 0)
+}
+
+fn nested_token<'a>(i: Span<'a>, depth: usize) -> IResult<'a, Value<'a>> {
     map(
-        sequence::tuple((modifier, token_kind)),
+        sequence::tuple((modifier, |i| token_kind(i, depth))),
         |(modifier, kind)| Value {
             kind,
             raw: (modifier == Modifier::Raw),
@@ -61,10 +70,26 @@ pub fn token<'a>(i: Span<'a>) -> IResult<Value<'a>> {
     )(i)
 }
 
-pub fn list<'a>(i: Span<'a>) -> IResult<List<Value<'a>>> {
+/// Parses a list. Lists nested deeper than [`MAX_DEPTH`] fail with the
+/// `nesting` context.
+pub fn list<'a>(i: Span<'a>) -> IResult<'a, List<Value<'a>>> {
+    nested_list(i, 0)
+}
+
+/// List nested in `depth` other lists.
+fn nested_list<'a>(i: Span<'a>, depth: usize) -> IResult<'a, List<Value<'a>>> {
     let mut result = Vec::new();
     let mut first_token = true;
+    let start = i;
     let (mut i, _) = one_char('(')(i)?;
+    if depth >= MAX_DEPTH {
+        let error = Error::from_error_kind(start, ErrorKind::TooLarge);
+        return Err(nom::Err::Failure(Error::add_context(
+            start,
+            Context::Nesting.name(),
+            error,
+        )));
+    }
     loop {
         if let Ok((i, _)) = preceded(multispace0, one_char::<_, Error>(')'))(i) {
             break Ok((i, List::from_double_ended_iter(result)));
@@ -75,7 +100,7 @@ pub fn list<'a>(i: Span<'a>) -> IResult<List<Value<'a>>> {
             i = multispace0(i)?.0;
             first_token = false;
         }
-        let (new_i, token) = token(i)?;
+        let (new_i, token) = nested_token(i, depth + 1)?;
         i = new_i;
         result.push(token);
     }
@@ -118,11 +143,11 @@ mod tests {
     fn test_token() {
         assert_ok_t(
             token(Span::new("1")),
-            (Span::new(""), simple_value(Integer(1))),
+            (Span::new(""), simple_value(Integer(1.into()))),
         );
         assert_ok_t(
             token(Span::new("1.2")),
-            (Span::new(""), simple_value(Float(1.2))),
+            (Span::new(""), simple_value(Float(1.2.into()))),
         );
         assert_ok_t(
             token(Span::new("\"3\"")),
@@ -141,9 +166,9 @@ mod tests {
             (
                 Span::new(""),
                 raw_value(List(list![
-                    simple_value(Integer(1)),
-                    simple_value(Integer(2)),
-                    simple_value(Integer(3)),
+                    simple_value(Integer(1.into())),
+                    simple_value(Integer(2.into())),
+                    simple_value(Integer(3.into())),
                 ])),
             ),
         );
@@ -152,9 +177,9 @@ mod tests {
             (
                 Span::new(""),
                 sequential_value(List(list![
-                    simple_value(Integer(1)),
-                    simple_value(Integer(2)),
-                    simple_value(Integer(3)),
+                    simple_value(Integer(1.into())),
+                    simple_value(Integer(2.into())),
+                    simple_value(Integer(3.into())),
                 ])),
             ),
         );
@@ -167,9 +192,9 @@ mod tests {
             (
                 Span::new(""),
                 list![
-                    simple_value(Integer(1)),
-                    simple_value(Integer(2)),
-                    simple_value(Integer(3)),
+                    simple_value(Integer(1.into())),
+                    simple_value(Integer(2.into())),
+                    simple_value(Integer(3.into())),
                 ],
             ),
         );
@@ -182,8 +207,8 @@ mod tests {
             (
                 Span::new(""),
                 list![
-                    simple_value(Integer(1)),
-                    simple_value(Float(1.2)),
+                    simple_value(Integer(1.into())),
+                    simple_value(Float(1.2.into())),
                     simple_value(String("3".into())),
                     simple_value(Symbol("four"))
                 ],
@@ -198,9 +223,9 @@ mod tests {
             (
                 Span::new("4"),
                 list![
-                    simple_value(Integer(1)),
-                    simple_value(Integer(2)),
-                    simple_value(Integer(3)),
+                    simple_value(Integer(1.into())),
+                    simple_value(Integer(2.into())),
+                    simple_value(Integer(3.into())),
                 ],
             ),
         );
@@ -216,23 +241,33 @@ mod tests {
                     simple_value(Symbol("+")),
                     sequential_value(List(list![
                         simple_value(Symbol("/")),
-                        simple_value(Integer(2)),
-                        simple_value(Integer(3)),
+                        simple_value(Integer(2.into())),
+                        simple_value(Integer(3.into())),
                     ])),
                     simple_value(List(list![
                         simple_value(Symbol("eval")),
                         raw_value(List(list![
                             simple_value(Symbol("*")),
-                            simple_value(Integer(2)),
-                            simple_value(Integer(4)),
+                            simple_value(Integer(2.into())),
+                            simple_value(Integer(4.into())),
                         ]))
                     ])),
-                    simple_value(Integer(6))
+                    simple_value(Integer(6.into()))
                 ],
             ),
         );
     }
 
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth| "(".repeat(depth) + &")".repeat(depth);
+        assert!(list(Span::new(&nested(MAX_DEPTH))).is_ok());
+        assert!(matches!(
+            list(Span::new(&nested(100_000))),
+            Err(nom::Err::Failure(_))
+        ));
+    }
+
     #[test]
     fn test_space_before_closing_paren() {
         assert_ok_t(
@@ -2,16 +2,29 @@ use crate::list::List;
 use crate::{Value, ValueKind};
 
 mod ident;
+mod incremental;
 mod list;
 mod number;
+mod resilient;
+mod stream;
 mod string;
+mod syntax;
 mod error_handling;
 
 pub use ident::ident;
+pub use incremental::{Document, TextEdit};
 pub use list::{list, token};
 pub use number::{float, integer};
+pub(crate) use number::{negative_number, NegativeNumber};
+pub use resilient::{is_incomplete, program_resilient, syntax_tree, syntax_tree_with_max_depth};
+pub use stream::{Form, FormReader};
 pub use string::string;
-pub use error_handling::determine_error;
+pub use syntax::{Child, SyntaxKind, SyntaxNode};
+pub use error_handling::{
+    classify_error, classify_nom_error, determine_error, Note, ParseError, ParseErrorKind, Report,
+    Suggestion, UnknownErrorCode,
+};
+pub(crate) use error_handling::{nesting_too_deep, unmatched_paren, whitespace_error, Context};
 
 use nom::character::complete::{multispace0, multispace1};
 
@@ -19,18 +32,31 @@ pub type Span<'a> = nom_locate::LocatedSpan<&'a str>;
 pub type Error<'a> = nom_greedyerror::GreedyError<Span<'a>>;
 pub type IResult<'a, O> = nom::IResult<Span<'a>, O, Error<'a>>;
 
-pub fn program<'a>(i: Span<'a>) -> IResult<Value<'a>> {
+/// How deep lists can be nested by default. Deeper lists are reported as
+/// [`ParseErrorKind::NestingTooDeep`], so that untrusted input can't overflow
+/// the stack of the parser or of the code walking the parsed values.
+pub const MAX_DEPTH: usize = 256;
+
+pub(crate) fn is_multispace(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\r' || c == '\n'
+}
+
+pub(crate) fn is_token_end(c: char) -> bool {
+    is_multispace(c) || c == '(' || c == ')'
+}
+
+pub fn program<'a>(i: Span<'a>) -> IResult<'a, Value<'a>> {
     let (mut i, _) = multispace0(i)?;
     let mut result = Vec::new();
     let mut first_token = true;
-    while i.fragment().len() != 0 {
+    while !i.fragment().is_empty() {
         if !first_token {
             i = multispace1(i)?.0;
         } else {
             i = multispace0(i)?.0;
             first_token = false;
         }
-        if i.fragment().len() == 0 {
+        if i.fragment().is_empty() {
             break;
         }
         let i_value = token(i)?;
@@ -1,52 +1,110 @@
-use super::{IResult, Span};
+use super::{is_token_end, IResult, Span};
+use crate::Number;
 use std::str::FromStr;
 
 use nom::{
     branch::alt,
     bytes::complete::tag,
-    character::complete::{char as one_char, digit0, digit1, hex_digit1, oct_digit1, one_of},
-    combinator::{map_res, recognize},
-    multi::many1,
-    sequence::{self, preceded},
+    character::complete::{char as one_char, one_of},
+    combinator::{map_res, opt, recognize},
+    error::{make_error, ErrorKind},
+    multi::many0,
+    sequence::{self, pair, preceded},
 };
 
+const DECIMAL: &str = "0123456789";
+
+/// One or more of `allowed` digits, optionally separated by single underscores.
+fn digits<'a>(allowed: &'static str) -> impl Fn(Span<'a>) -> IResult<'a, Span<'a>> {
+    recognize(pair(
+        one_of(allowed),
+        many0(pair(opt(one_char('_')), one_of(allowed))),
+    ))
+}
+
+fn without_separators(n: Span) -> String {
+    n.fragment().replace('_', "")
+}
+
 fn decimal_integer(i: Span) -> IResult<i64> {
     map_res(
-        recognize(sequence::tuple((one_of("123456789"), digit0))),
-        |n: Span| i64::from_str(n.fragment()),
+        recognize(pair(
+            one_of("123456789"),
+            many0(pair(opt(one_char('_')), one_of(DECIMAL))),
+        )),
+        |n: Span| i64::from_str(&without_separators(n)),
     )(i)
 }
 
+/// Zero, which is the only decimal integer starting with `0`.
+fn zero(i: Span) -> IResult<i64> {
+    let (rest, _) = one_char('0')(i)?;
+    match rest.fragment().chars().next() {
+        Some(c) if !is_token_end(c) => Err(nom::Err::Error(make_error(i, ErrorKind::Digit))),
+        _ => Ok((rest, 0)),
+    }
+}
+
 fn hex_integer(i: Span) -> IResult<i64> {
-    map_res(preceded(tag("0x"), hex_digit1), |n: Span| {
-        i64::from_str_radix(n.fragment(), 16)
+    map_res(preceded(tag("0x"), digits("0123456789abcdefABCDEF")), |n: Span| {
+        i64::from_str_radix(&without_separators(n), 16)
     })(i)
 }
 
 fn oct_integer(i: Span) -> IResult<i64> {
-    map_res(preceded(tag("0o"), oct_digit1), |n: Span| {
-        i64::from_str_radix(n.fragment(), 8)
+    map_res(preceded(tag("0o"), digits("01234567")), |n: Span| {
+        i64::from_str_radix(&without_separators(n), 8)
     })(i)
 }
 
 fn bin_integer(i: Span) -> IResult<i64> {
-    map_res(
-        preceded(tag("0b"), recognize(many1(one_of("01")))),
-        |n: Span| i64::from_str_radix(n.fragment(), 2),
-    )(i)
+    map_res(preceded(tag("0b"), digits("01")), |n: Span| {
+        i64::from_str_radix(&without_separators(n), 2)
+    })(i)
 }
 
 pub fn integer(i: Span) -> IResult<i64> {
-    alt((decimal_integer, hex_integer, bin_integer, oct_integer))(i)
+    alt((decimal_integer, hex_integer, bin_integer, oct_integer, zero))(i)
 }
 
 pub fn float(i: Span) -> IResult<f64> {
     map_res(
-        recognize(sequence::tuple((digit1, one_char('.'), digit0))),
-        |n: Span| f64::from_str(n.fragment()),
+        recognize(sequence::tuple((digits(DECIMAL), one_char('.'), opt(digits(DECIMAL))))),
+        |n: Span| f64::from_str(&without_separators(n)),
     )(i)
 }
 
+/// Number read from a symbol by [`negative_number`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum NegativeNumber {
+    Integer(i64),
+    Float(f64),
+}
+
+/// Negative number written as a symbol, like `-5` or `-1.5`, as spli has no
+/// negative literals.
+pub(crate) fn negative_number(symbol: &str) -> Option<NegativeNumber> {
+    let digits = Span::new(symbol.strip_prefix('-')?);
+    match (float(digits), integer(digits)) {
+        (Ok((rest, x)), _) if rest.fragment().is_empty() => Some(NegativeNumber::Float(-x)),
+        (_, Ok((rest, n))) if rest.fragment().is_empty() => Some(NegativeNumber::Integer(-n)),
+        // Its magnitude doesn't fit into `i64`
+        _ if symbol == i64::MIN.to_string() => Some(NegativeNumber::Integer(i64::MIN)),
+        _ => None,
+    }
+}
+
+/// Runs a number parser, keeping the text it consumed.
+pub(crate) fn with_text<'a, T>(
+    parser: impl Fn(Span<'a>) -> IResult<'a, T>,
+) -> impl Fn(Span<'a>) -> IResult<'a, Number<'a, T>> {
+    move |i: Span<'a>| {
+        let (rest, value) = parser(i)?;
+        let text = &i.fragment()[..rest.location_offset() - i.location_offset()];
+        Ok((rest, Number::with_text(value, text)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -60,7 +118,14 @@ mod tests {
         assert_ok_t(integer(Span::new("0b1010")), (Span::new(""), 10));
         assert_ok_t(integer(Span::new("0b123")), (Span::new("23"), 1));
         assert!(integer(Span::new("0123")).is_err());
+        assert_ok_t(integer(Span::new("0")), (Span::new(""), 0));
+        assert_ok_t(integer(Span::new("0)")), (Span::new(")"), 0));
+        assert!(integer(Span::new("0_1")).is_err());
         assert!(integer(Span::new("0q123")).is_err());
+        assert_ok_t(integer(Span::new("1_000")), (Span::new(""), 1000));
+        assert_ok_t(integer(Span::new("0xFF_ff")), (Span::new(""), 0xffff));
+        assert_ok_t(integer(Span::new("1__0")), (Span::new("__0"), 1));
+        assert_ok_t(integer(Span::new("1_")), (Span::new("_"), 1));
     }
 
     #[test]
@@ -75,5 +140,27 @@ mod tests {
         assert!(float(Span::new("0x5.6")).is_err());
         assert!(float(Span::new("0o5.6")).is_err());
         assert!(float(Span::new("0b0.1")).is_err());
+        assert_ok_t(float(Span::new("1_000.000_1")), (Span::new(""), 1000.0001));
+    }
+
+    #[test]
+    fn test_negative_number() {
+        assert_eq!(negative_number("-5"), Some(NegativeNumber::Integer(-5)));
+        assert_eq!(negative_number("-0x10"), Some(NegativeNumber::Integer(-16)));
+        assert_eq!(negative_number("-1.5"), Some(NegativeNumber::Float(-1.5)));
+        assert_eq!(negative_number(&i64::MIN.to_string()), Some(NegativeNumber::Integer(i64::MIN)));
+        assert_eq!(negative_number("-0.0"), Some(NegativeNumber::Float(-0.0)));
+        assert_eq!(negative_number("5"), None);
+        assert_eq!(negative_number("-"), None);
+        assert_eq!(negative_number("-1a"), None);
+    }
+
+    #[test]
+    fn test_with_text() {
+        let (_, number) = with_text(integer)(Span::new("0x1_F)")).unwrap();
+        assert_eq!(number.value, 0x1f);
+        assert_eq!(number.text(), Some("0x1_F"));
+        assert_eq!(number.radix(), 16);
+        assert_eq!(number.to_string(), "0x1_F");
     }
 }
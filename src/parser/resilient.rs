@@ -0,0 +1,437 @@
+use crate::{Value, ValueKind};
+
+use super::{
+    classify_nom_error, is_multispace, is_token_end, nesting_too_deep, token, unmatched_paren,
+    whitespace_error, Child, ParseError, ParseErrorKind, Span, SyntaxKind, SyntaxNode, MAX_DEPTH,
+};
+
+use nom::Slice;
+use std::sync::Arc;
+
+pub(crate) struct Parser<'a> {
+    source: &'a str,
+    input: Span<'a>,
+    errors: Vec<ParseError>,
+    /// Number of lists around the input.
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            input: Span::new(source),
+            errors: Vec::new(),
+            depth: 0,
+            max_depth: MAX_DEPTH,
+        }
+    }
+
+    pub(crate) fn with_max_depth(self, max_depth: usize) -> Self {
+        Self { max_depth, ..self }
+    }
+
+    /// Parser of a part of the source nested in `depth` lists.
+    pub(crate) fn at_depth(self, depth: usize) -> Self {
+        Self { depth, ..self }
+    }
+
+    /// Parser of the source starting at `offset`.
+    pub(crate) fn starting_at(mut self, offset: usize) -> Self {
+        self.advance(offset);
+        self
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        self.input.location_offset()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input.fragment().chars().next()
+    }
+
+    fn advance(&mut self, count: usize) {
+        self.input = self.input.slice(count..);
+    }
+
+    fn skip_whitespace(&mut self) {
+        let fragment = self.input.fragment();
+        self.advance(fragment.len() - fragment.trim_start_matches(is_multispace).len());
+    }
+
+    /// Removes errors found since `from`, making them relative to `start`.
+    fn take_errors(&mut self, from: usize, start: usize) -> Vec<ParseError> {
+        self.errors
+            .drain(from..)
+            .map(|error| error.shifted(-(start as isize)))
+            .collect()
+    }
+
+    /// Checks that `node` starting at `start` is followed by a separator. Trailing
+    /// garbage that turns out to be a part of an invalid number makes `node`
+    /// an error node.
+    fn expect_separator(&mut self, start: usize, node: &mut SyntaxNode) {
+        match self.peek() {
+            Some(c) if !is_multispace(c) && c != ')' => {
+                let error = whitespace_error(self.source, self.offset());
+                if error.kind == ParseErrorKind::InvalidNumber {
+                    self.skip_token();
+                    *node = SyntaxNode::leaf(self.offset() - start, false, false, SyntaxKind::Error);
+                }
+                self.errors.push(error);
+            }
+            _ => {}
+        }
+    }
+
+    /// Skips a token that failed to parse, so that parsing can continue after it.
+    fn skip_token(&mut self) {
+        let fragment = *self.input.fragment();
+        let without_modifier = fragment.trim_start_matches(['\'', '!']);
+        let modifier_len = fragment.len() - without_modifier.len();
+        let token_len = if without_modifier.starts_with('"') {
+            let mut escaped = false;
+            without_modifier
+                .char_indices()
+                .skip(1)
+                .find(|&(_, c)| {
+                    let closing = !escaped && c == '"';
+                    escaped = !escaped && c == '\\';
+                    closing
+                })
+                .map_or(without_modifier.len(), |(idx, _)| idx + 1)
+        } else {
+            without_modifier.find(is_token_end).unwrap_or(without_modifier.len())
+        };
+        let len = modifier_len + token_len;
+        // Always make progress, e.g. on a stray `)`
+        let len = if len == 0 {
+            fragment.chars().next().map_or(0, char::len_utf8)
+        } else {
+            len
+        };
+        self.advance(len);
+    }
+
+    /// Skips a list together with the lists nested in it, up to the end of
+    /// input if it's not closed.
+    fn skip_list(&mut self) {
+        let fragment = *self.input.fragment();
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        let len = fragment
+            .char_indices()
+            .find(|&(_, c)| {
+                if in_string {
+                    in_string = escaped || c != '"';
+                    escaped = !escaped && c == '\\';
+                    return false;
+                }
+                match c {
+                    '"' => in_string = true,
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })
+            .map_or(fragment.len(), |(idx, _)| idx + 1);
+        self.advance(len);
+    }
+
+    pub(crate) fn node(&mut self) -> SyntaxNode {
+        let start = self.offset();
+        let fragment = self.input.fragment();
+        let (raw, sequential) = match fragment.chars().next() {
+            Some('\'') => (true, false),
+            Some('!') => (false, true),
+            _ => (false, false),
+        };
+        let modifier_len = (raw || sequential) as usize;
+        if fragment[modifier_len..].starts_with('(') {
+            self.advance(modifier_len);
+            return self.list(start, raw, sequential);
+        }
+
+        let kind = match token(self.input) {
+            Ok((rest, value)) => {
+                self.input = rest;
+                match value.into_kind() {
+                    ValueKind::Symbol(_) => SyntaxKind::Symbol,
+                    ValueKind::Integer(n) => SyntaxKind::Integer(n.value),
+                    ValueKind::Float(x) => SyntaxKind::Float(x.value),
+                    ValueKind::String(s) => SyntaxKind::String(s),
+                    // Lists are parsed by `Parser::list`
+                    ValueKind::List(_) | ValueKind::Native(_) | ValueKind::Error => {
+                        SyntaxKind::Error
+                    }
+                }
+            }
+            Err(err) => {
+                self.errors.push(classify_nom_error(self.source, &err));
+                self.skip_token();
+                return SyntaxNode::leaf(self.offset() - start, false, false, SyntaxKind::Error);
+            }
+        };
+        SyntaxNode::leaf(self.offset() - start, raw, sequential, kind)
+    }
+
+    fn children(&mut self, start: usize) -> Vec<Child> {
+        let mut children = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None | Some(')') => break,
+                Some(_) => {
+                    let child_start = self.offset();
+                    let mut node = self.node();
+                    self.expect_separator(child_start, &mut node);
+                    children.push(Child {
+                        offset: child_start - start,
+                        node: Arc::new(node),
+                    });
+                }
+            }
+        }
+        children
+    }
+
+    fn list(&mut self, start: usize, raw: bool, sequential: bool) -> SyntaxNode {
+        let errors_from = self.errors.len();
+        let open = self.offset();
+        if self.depth >= self.max_depth {
+            self.errors.push(nesting_too_deep(open, self.max_depth));
+            self.skip_list();
+            return SyntaxNode::leaf(self.offset() - start, false, false, SyntaxKind::Error);
+        }
+        self.advance(1);
+        self.depth += 1;
+        let children = self.children(start);
+        self.depth -= 1;
+        if self.peek().is_some() {
+            self.advance(1);
+        } else {
+            self.errors.push(ParseError::new(
+                ParseErrorKind::UnclosedList,
+                open..open + 1,
+                Some("started here"),
+            ));
+        }
+        let errors = self.take_errors(errors_from, start);
+        SyntaxNode::list(self.offset() - start, raw, sequential, children, errors)
+    }
+
+    /// Parses the whole input as a program. Stray `)` are reported and skipped.
+    pub(crate) fn program(&mut self) -> SyntaxNode {
+        let (children, errors) = self.forms(None, |_| false);
+        SyntaxNode::program(self.source.len(), children, errors)
+    }
+
+    /// Parses top-level forms up to the end of input, or until `stop` returns
+    /// true for the offset of the next form. `last_list` is the offsets of
+    /// parens of the last closed list before the input, for errors on stray
+    /// `)`. Returns the forms and the top-level errors, relative to the source.
+    pub(crate) fn forms(
+        &mut self,
+        mut last_list: Option<(usize, usize)>,
+        mut stop: impl FnMut(usize) -> bool,
+    ) -> (Vec<Child>, Vec<ParseError>) {
+        let errors_from = self.errors.len();
+        let mut children = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let child_start = self.offset();
+            let mut node = match self.peek() {
+                None => break,
+                _ if stop(child_start) => break,
+                // Stray `)`. Notes are found from the tree rather than from the text,
+                // so that they don't depend on the contents of earlier lists.
+                Some(')') => {
+                    self.errors.push(unmatched_paren(child_start, last_list));
+                    self.advance(1);
+                    SyntaxNode::leaf(1, false, false, SyntaxKind::Error)
+                }
+                Some(_) => self.node(),
+            };
+            self.expect_separator(child_start, &mut node);
+            let child = Child {
+                offset: child_start,
+                node: Arc::new(node),
+            };
+            last_list = list_parens(&child).or(last_list);
+            children.push(child);
+        }
+        (children, self.take_errors(errors_from, 0))
+    }
+}
+
+/// Offsets of parens of a closed list.
+fn list_parens(child: &Child) -> Option<(usize, usize)> {
+    if !child.node.is_closed_list() {
+        return None;
+    }
+    let inner = child.node.inner_range();
+    Some((child.offset + inner.start - 1, child.offset + inner.end))
+}
+
+/// Offsets of parens of the last closed list among `children`.
+pub(crate) fn last_closed_list(children: &[Child]) -> Option<(usize, usize)> {
+    children.iter().rev().find_map(list_parens)
+}
+
+/// Parses the whole program into a [`SyntaxNode`] tree, like
+/// [`program_resilient`] does.
+pub fn syntax_tree(source: &str) -> SyntaxNode {
+    Parser::new(source).program()
+}
+
+/// Parses the whole program like [`syntax_tree`], allowing lists to be nested
+/// in at most `max_depth` other lists instead of [`MAX_DEPTH`].
+pub fn syntax_tree_with_max_depth(source: &str, max_depth: usize) -> SyntaxNode {
+    Parser::new(source).with_max_depth(max_depth).program()
+}
+
+/// Parses the whole program like [`program`](super::program), but doesn't stop at
+/// the first error. Tokens that fail to parse are replaced with
+/// [`ValueKind::Error`] nodes and parsing continues after them, so all errors
+/// are reported together, sorted by position.
+pub fn program_resilient(source: &str) -> (Value<'_>, Vec<ParseError>) {
+    let tree = syntax_tree(source);
+    (tree.to_value(source, 0), tree.collect_errors(0))
+}
+
+/// Whether `source` ends inside a list or a string, so that more input could
+/// complete it. Used by the REPL to decide when to stop reading lines.
+pub fn is_incomplete(source: &str) -> bool {
+    syntax_tree(source).collect_errors(0).iter().any(|error| {
+        matches!(
+            error.kind,
+            ParseErrorKind::UnclosedList | ParseErrorKind::UnclosedString
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{list, parser::program, ValueKind::*};
+
+    fn simple_value(kind: ValueKind) -> Value {
+        Value {
+            raw: false,
+            sequential: false,
+            kind,
+        }
+    }
+
+    fn kinds(errors: &[ParseError]) -> Vec<ParseErrorKind> {
+        errors.iter().map(|error| error.kind).collect()
+    }
+
+    #[test]
+    fn test_valid_program() {
+        let source = "(a '(1 2) \"3\") !(b)\n4.5";
+        let (value, errors) = program_resilient(source);
+        assert!(errors.is_empty());
+        assert_eq!(value, program(Span::new(source)).unwrap().1);
+    }
+
+    #[test]
+    fn test_error_nodes() {
+        let (value, errors) = program_resilient("(a 0123 b) \"x\\q\" c");
+        assert_eq!(
+            kinds(&errors),
+            vec![ParseErrorKind::InvalidNumber, ParseErrorKind::UnknownEscape]
+        );
+        assert_eq!(
+            value.kind,
+            List(list![
+                simple_value(List(list![
+                    simple_value(Symbol("a")),
+                    simple_value(Error),
+                    simple_value(Symbol("b")),
+                ])),
+                simple_value(Error),
+                simple_value(Symbol("c")),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_invalid_radix_digits() {
+        let (value, errors) = program_resilient("(0b12 0xfg) 1");
+        assert_eq!(
+            kinds(&errors),
+            vec![ParseErrorKind::InvalidNumber, ParseErrorKind::InvalidNumber]
+        );
+        assert_eq!(
+            value.kind,
+            List(list![
+                simple_value(List(list![simple_value(Error), simple_value(Error)])),
+                simple_value(Integer(1.into())),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_number_notation() {
+        let (value, errors) = program_resilient("(0xFF 1_000 2.50 0o17 0b1_0)");
+        assert!(errors.is_empty());
+        assert_eq!(value.to_string(), "!((0xFF 1_000 2.50 0o17 0b1_0))");
+        let (_, errors) = program_resilient("0x_1");
+        assert_eq!(kinds(&errors), vec![ParseErrorKind::InvalidNumber]);
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(is_incomplete("(a (b)\n"));
+        assert!(is_incomplete("(a \"b)"));
+        assert!(is_incomplete("\"a\\\""));
+        assert!(!is_incomplete("(a) b"));
+        assert!(!is_incomplete("(a))"));
+        assert!(!is_incomplete("0123 '"));
+        assert!(!is_incomplete(""));
+    }
+
+    #[test]
+    fn test_nesting_too_deep() {
+        let deep = "(".repeat(100_000) + "\")\"" + &")".repeat(100_000);
+        let source = format!("(a {}) b", deep);
+        let (value, errors) = program_resilient(&source);
+        assert_eq!(kinds(&errors), vec![ParseErrorKind::NestingTooDeep]);
+        assert_eq!(errors[0].range, 3 + MAX_DEPTH - 1..3 + MAX_DEPTH);
+        let nested = "(".repeat(MAX_DEPTH - 1) + "<error>" + &")".repeat(MAX_DEPTH - 1);
+        assert_eq!(value.to_string(), format!("!((a {}) b)", nested));
+
+        // Lists around the skipped one are unclosed too
+        let (_, errors) = program_resilient(&"(".repeat(100_000));
+        assert_eq!(errors.len(), MAX_DEPTH + 1);
+        assert_eq!(errors[MAX_DEPTH].kind, ParseErrorKind::NestingTooDeep);
+
+        let tree = syntax_tree_with_max_depth("(a (b (c)))", 2);
+        assert_eq!(tree.collect_errors(0)[0].range, 6..7);
+        assert!(!syntax_tree_with_max_depth("(a (b (c)))", 3).has_errors());
+    }
+
+    #[test]
+    fn test_multiple_errors() {
+        let (_, errors) = program_resilient("(a)(b) ) (c 0123\n(d \"unclosed");
+        assert_eq!(
+            kinds(&errors),
+            vec![
+                ParseErrorKind::ExpectedWhitespace,
+                ParseErrorKind::UnmatchedParen,
+                ParseErrorKind::UnclosedList,
+                ParseErrorKind::InvalidNumber,
+                ParseErrorKind::UnclosedList,
+                ParseErrorKind::UnclosedString,
+            ]
+        );
+        assert_eq!(errors[0].range, 3..4);
+        assert_eq!(errors[1].range, 7..8);
+        assert_eq!(errors[2].range, 9..10);
+        assert_eq!(errors[4].range, 17..18);
+    }
+}
@@ -0,0 +1,315 @@
+use crate::Value;
+
+use super::{is_multispace, syntax_tree, ParseError, SyntaxNode};
+
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+const MIN_READ_SIZE: usize = 8 * 1024;
+
+/// Top-level form read by [`FormReader`], together with its source text.
+#[derive(Debug, Clone)]
+pub struct Form {
+    text: String,
+    offset: usize,
+    line: usize,
+    column: usize,
+    tree: Arc<SyntaxNode>,
+    errors: Vec<ParseError>,
+}
+
+impl Form {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Byte offset of the form in the stream.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Line of the form start in the stream, counting from 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Column of the form start in chars, counting from 1.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn tree(&self) -> &SyntaxNode {
+        &self.tree
+    }
+
+    pub fn value(&self) -> Value<'_> {
+        self.tree.to_value(&self.text, 0)
+    }
+
+    /// Errors in the form, relative to its text.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+}
+
+/// Pull-based parser, reading top-level forms one at a time from a reader.
+///
+/// Only the text of the forms that aren't complete yet is kept in memory.
+/// A form is complete when it's followed by whitespace, so forms glued
+/// together like `(a)(b)` are read at once. Errors are the same as in
+/// [`program_resilient`](super::program_resilient), but notes pointing
+/// into other forms are dropped.
+pub struct FormReader<R> {
+    reader: R,
+    text: String,
+    /// Trailing bytes of an incomplete UTF-8 sequence.
+    pending: Vec<u8>,
+    /// Position of the start of `text` in the stream.
+    offset: usize,
+    line: usize,
+    column: usize,
+    ready: VecDeque<Form>,
+    eof: bool,
+}
+
+impl<R: Read> FormReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            text: String::new(),
+            pending: Vec::new(),
+            offset: 0,
+            line: 1,
+            column: 1,
+            ready: VecDeque::new(),
+            eof: false,
+        }
+    }
+
+    /// Reads more input, until the end of the stream or a buffer as large as
+    /// the buffered text is full. The buffered text at least doubles between
+    /// parses, so that a large form is reparsed a logarithmic number of times.
+    fn fill(&mut self) -> io::Result<()> {
+        let mut buf = vec![0; MIN_READ_SIZE.max(self.text.len())];
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.reader.read(&mut buf[filled..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(count) => filled += count,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        self.pending.extend_from_slice(&buf[..filled]);
+        let valid_len = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+        let valid = std::str::from_utf8(&self.pending[..valid_len]).expect("checked above");
+        self.text.push_str(valid);
+        self.pending.drain(..valid_len);
+        if self.eof && !self.pending.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream ended in the middle of a UTF-8 sequence",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Moves complete forms from the buffer to `ready`.
+    fn split_forms(&mut self) {
+        let tree = syntax_tree(&self.text);
+        let children = tree.children();
+        let followed_by_whitespace = |end: usize| self.text[end..].starts_with(is_multispace);
+        let complete = if self.eof {
+            children.len()
+        } else {
+            children
+                .iter()
+                .rposition(|child| followed_by_whitespace(child.offset + child.node.len))
+                .map_or(0, |idx| idx + 1)
+        };
+        if complete == 0 {
+            return;
+        }
+
+        // Line and column of `scanned` in the buffer, advanced from form to form
+        let mut scanned = 0;
+        let mut position = (self.line, self.column);
+        let mut program_errors = tree.errors.iter().peekable();
+        for (idx, child) in children[..complete].iter().enumerate() {
+            let end = child.offset + child.node.len;
+            let next_start = children.get(idx + 1).map_or(usize::MAX, |next| next.offset);
+            let mut errors = Vec::new();
+            while let Some(error) = program_errors.next_if(|error| error.range.start < next_start) {
+                let mut error = error.clone();
+                // Notes may point into earlier forms
+                error.notes.retain(|note| note.range.start >= child.offset);
+                errors.push(error.shifted(-(child.offset as isize)));
+            }
+            errors.extend(child.node.collect_errors(0));
+            errors.sort_by_key(|error| error.range.start);
+            position = advance(position, &self.text[scanned..child.offset]);
+            scanned = child.offset;
+            let (line, column) = position;
+            self.ready.push_back(Form {
+                text: self.text[child.offset..end].to_string(),
+                offset: self.offset + child.offset,
+                line,
+                column,
+                tree: child.node.clone(),
+                errors,
+            });
+        }
+
+        let end = children[complete - 1].offset + children[complete - 1].node.len;
+        self.offset += end;
+        (self.line, self.column) = advance(position, &self.text[scanned..end]);
+        self.text.drain(..end);
+    }
+}
+
+/// Line and column after `text`, which starts at `line` and `column`.
+fn advance((line, column): (usize, usize), text: &str) -> (usize, usize) {
+    match text.rfind('\n') {
+        Some(idx) => (
+            line + text.matches('\n').count(),
+            text[idx + 1..].chars().count() + 1,
+        ),
+        None => (line, column + text.chars().count()),
+    }
+}
+
+impl<R: Read> Iterator for FormReader<R> {
+    type Item = io::Result<Form>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(form) = self.ready.pop_front() {
+                return Some(Ok(form));
+            }
+            if self.eof {
+                return None;
+            }
+            if let Err(err) = self.fill() {
+                self.eof = true;
+                return Some(Err(err));
+            }
+            self.split_forms();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{program_resilient, ParseErrorKind};
+    use crate::ValueKind;
+    use proptest::prelude::*;
+
+    /// Reader returning at most `step` bytes at a time.
+    struct Chunked<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let count = self.step.min(buf.len()).min(self.data.len());
+            buf[..count].copy_from_slice(&self.data[..count]);
+            self.data = &self.data[count..];
+            Ok(count)
+        }
+    }
+
+    fn read_forms(source: &str, step: usize) -> Vec<Form> {
+        FormReader::new(Chunked {
+            data: source.as_bytes(),
+            step,
+        })
+        .collect::<io::Result<_>>()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_forms() {
+        let forms = read_forms("(a\n (b \"\u{1f600} c\"))\n  12 'x\n)", 1);
+        let texts: Vec<_> = forms.iter().map(Form::text).collect();
+        assert_eq!(texts, vec!["(a\n (b \"\u{1f600} c\"))", "12", "'x", ")"]);
+        assert_eq!((forms[0].line(), forms[0].column()), (1, 1));
+        assert_eq!(forms[1].offset(), 20);
+        assert_eq!((forms[1].line(), forms[1].column()), (3, 3));
+        assert_eq!((forms[2].line(), forms[2].column()), (3, 6));
+        assert_eq!(forms[1].value().kind, ValueKind::Integer(12.into()));
+        assert_eq!(forms[3].errors()[0].kind, ParseErrorKind::UnmatchedParen);
+        assert_eq!(forms[3].errors()[0].range, 0..1);
+    }
+
+    #[test]
+    fn test_glued_forms() {
+        let forms = read_forms("(a)(b) c", 1);
+        assert_eq!(forms.len(), 3);
+        assert!(forms[0].errors().is_empty());
+        assert_eq!(forms[1].errors()[0].kind, ParseErrorKind::ExpectedWhitespace);
+        assert_eq!(forms[1].errors()[0].range, 0..1);
+        assert!(forms[1].errors()[0].notes.is_empty());
+    }
+
+    #[test]
+    fn test_large_form_in_small_chunks() {
+        let source = format!("({}) 1", "ab ".repeat(100_000));
+        let forms = read_forms(&source, 1);
+        assert_eq!(forms.len(), 2);
+        match &forms[0].value().kind {
+            ValueKind::List(items) => assert_eq!(items.len(), 100_000),
+            kind => panic!("expected list, got {:?}", kind),
+        }
+        assert_eq!(forms[1].offset(), 300_003);
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let mut reader = FormReader::new(&b"a \xff b"[..]);
+        assert_eq!(
+            reader.next().unwrap().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(reader.next().is_none());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_same_as_program_resilient(
+            source in "[()'!\"\\\\ \t\n0-9a-fxob.~\u{e9}\u{1F600}]{0,40}",
+            step in 1usize..8,
+        ) {
+            let (value, errors) = program_resilient(&source);
+            let forms = read_forms(&source, step);
+            let values: Vec<_> = forms.iter().map(Form::value).collect();
+            let expected: Vec<_> = match &value.kind {
+                ValueKind::List(list) => list.iter().map(|value| value.to_string()).collect(),
+                _ => unreachable!(),
+            };
+            let actual: Vec<_> = values.iter().map(|value| value.to_string()).collect();
+            prop_assert_eq!(actual, expected);
+
+            let shifted: Vec<_> = forms
+                .iter()
+                .flat_map(|form| form.errors().iter().map(move |error| (error.kind, error.range.start + form.offset())))
+                .collect();
+            let expected: Vec<_> = errors.iter().map(|error| (error.kind, error.range.start)).collect();
+            prop_assert_eq!(shifted, expected);
+
+            for form in &forms {
+                let position = advance((1, 1), &source[..form.offset()]);
+                prop_assert_eq!((form.line(), form.column()), position);
+            }
+        }
+    }
+}
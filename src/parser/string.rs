@@ -1,4 +1,4 @@
-use super::{IResult, Span};
+use super::{Context, IResult, Span};
 
 use nom::{
     branch::alt,
@@ -13,23 +13,23 @@ fn escape<'a>(val: &'static str, tag: char, i: Span<'a>) -> IResult<'a, &'a str>
     value(val, one_char(tag))(i)
 }
 
-fn escape_nl(i: Span) -> IResult<&str> {
+fn escape_nl(i: Span<'_>) -> IResult<'_, &str> {
     escape("\n", 'n', i)
 }
 
-fn escape_tab(i: Span) -> IResult<&str> {
+fn escape_tab(i: Span<'_>) -> IResult<'_, &str> {
     escape("\t", 't', i)
 }
 
-fn escape_quote(i: Span) -> IResult<&str> {
+fn escape_quote(i: Span<'_>) -> IResult<'_, &str> {
     escape("\"", '"', i)
 }
 
-fn escape_backslash(i: Span) -> IResult<&str> {
+fn escape_backslash(i: Span<'_>) -> IResult<'_, &str> {
     escape("\\", '\\', i)
 }
 
-fn invalid_escape(i: Span) -> IResult<&str> {
+fn invalid_escape(i: Span<'_>) -> IResult<'_, &str> {
     Err(nom::Err::Error(make_error(i, ErrorKind::OneOf)))
 }
 
@@ -38,7 +38,7 @@ fn string_inner(i: Span) -> IResult<String> {
         is_not("\\\""),
         '\\',
         context(
-            "escape",
+            Context::Escape.name(),
             alt((
                 escape_nl,
                 escape_tab,
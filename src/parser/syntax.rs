@@ -0,0 +1,208 @@
+use crate::list::List;
+use crate::{Number, Value, ValueKind};
+
+use super::{ParseError, ParseErrorKind};
+
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Kind of a [`SyntaxNode`]. Mirrors [`ValueKind`], but doesn't borrow the source,
+/// so that nodes can outlive it and be reused after edits.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxKind {
+    Symbol,
+    Integer(i64),
+    Float(f64),
+    String(String),
+    List(Vec<Child>),
+    Error,
+    /// Top-level forms of a program, not delimited by parens.
+    Program(Vec<Child>),
+}
+
+/// Node of a list, positioned relative to the start of the list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Child {
+    pub offset: usize,
+    pub node: Arc<SyntaxNode>,
+}
+
+/// Node of a parse tree produced by the resilient parser. Nodes only know their
+/// length and children only know their offset in the parent, so unchanged
+/// subtrees stay valid when text before them is edited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    /// Length of the node in bytes, including its modifier.
+    pub len: usize,
+    pub raw: bool,
+    pub sequential: bool,
+    pub kind: SyntaxKind,
+    /// Errors found directly in this list, but not in nested lists,
+    /// relative to the start of the node.
+    pub errors: Vec<ParseError>,
+    has_errors: bool,
+}
+
+impl SyntaxNode {
+    pub(crate) fn leaf(len: usize, raw: bool, sequential: bool, kind: SyntaxKind) -> Self {
+        Self {
+            len,
+            raw,
+            sequential,
+            kind,
+            errors: Vec::new(),
+            has_errors: false,
+        }
+    }
+
+    pub(crate) fn list(
+        len: usize,
+        raw: bool,
+        sequential: bool,
+        children: Vec<Child>,
+        errors: Vec<ParseError>,
+    ) -> Self {
+        Self::with_children(len, raw, sequential, SyntaxKind::List(children), errors)
+    }
+
+    pub(crate) fn program(len: usize, children: Vec<Child>, errors: Vec<ParseError>) -> Self {
+        Self::with_children(len, false, true, SyntaxKind::Program(children), errors)
+    }
+
+    fn with_children(
+        len: usize,
+        raw: bool,
+        sequential: bool,
+        kind: SyntaxKind,
+        errors: Vec<ParseError>,
+    ) -> Self {
+        let mut node = Self::leaf(len, raw, sequential, kind);
+        node.has_errors = !errors.is_empty() || node.children().iter().any(|child| child.node.has_errors);
+        node.errors = errors;
+        node
+    }
+
+    /// Copy of a list or program node with other children.
+    pub(crate) fn with_children_replaced(
+        &self,
+        len: usize,
+        children: Vec<Child>,
+        errors: Vec<ParseError>,
+    ) -> Self {
+        let kind = match self.kind {
+            SyntaxKind::Program(_) => SyntaxKind::Program(children),
+            _ => SyntaxKind::List(children),
+        };
+        Self::with_children(len, self.raw, self.sequential, kind, errors)
+    }
+
+    /// Replaces `range` of the children of a list or program node in place,
+    /// moving the children after it by `delta` bytes, and sets its errors.
+    pub(crate) fn splice_children(
+        &mut self,
+        range: Range<usize>,
+        children: Vec<Child>,
+        delta: isize,
+        errors: Vec<ParseError>,
+    ) {
+        let count = children.len();
+        if let SyntaxKind::List(old) | SyntaxKind::Program(old) = &mut self.kind {
+            old.splice(range.clone(), children);
+            for sibling in &mut old[range.start + count..] {
+                sibling.offset = sibling.offset.saturating_add_signed(delta);
+            }
+        }
+        self.len = self.len.saturating_add_signed(delta);
+        self.has_errors = !errors.is_empty() || self.children().iter().any(|child| child.node.has_errors);
+        self.errors = errors;
+    }
+
+    pub fn children(&self) -> &[Child] {
+        match &self.kind {
+            SyntaxKind::List(children) | SyntaxKind::Program(children) => children,
+            _ => &[],
+        }
+    }
+
+    /// Whether there are errors in this node or in any of its descendants.
+    pub fn has_errors(&self) -> bool {
+        self.has_errors
+    }
+
+    fn modifier_len(&self) -> usize {
+        match self.kind {
+            SyntaxKind::Program(_) => 0,
+            _ => (self.raw || self.sequential) as usize,
+        }
+    }
+
+    /// Whether the node is a list with both parens in place.
+    pub fn is_closed_list(&self) -> bool {
+        let open = self.modifier_len();
+        match self.kind {
+            SyntaxKind::List(_) => !self
+                .errors
+                .iter()
+                .any(|error| error.kind == ParseErrorKind::UnclosedList && error.range.start == open),
+            _ => false,
+        }
+    }
+
+    /// Range of the node contents, without modifier and parens.
+    pub fn inner_range(&self) -> Range<usize> {
+        let start = self.modifier_len();
+        match self.kind {
+            SyntaxKind::List(_) if self.is_closed_list() => start + 1..self.len - 1,
+            SyntaxKind::List(_) => start + 1..self.len,
+            SyntaxKind::Symbol
+            | SyntaxKind::Integer(_)
+            | SyntaxKind::Float(_)
+            | SyntaxKind::String(_)
+            | SyntaxKind::Error
+            | SyntaxKind::Program(_) => start..self.len,
+        }
+    }
+
+    /// All errors in the subtree, with ranges relative to `start`, sorted by position.
+    pub fn collect_errors(&self, start: usize) -> Vec<ParseError> {
+        fn collect(node: &SyntaxNode, start: usize, result: &mut Vec<ParseError>) {
+            if !node.has_errors {
+                return;
+            }
+            result.extend(node.errors.iter().map(|error| error.clone().shifted(start as isize)));
+            for child in node.children() {
+                collect(&child.node, start + child.offset, result);
+            }
+        }
+
+        let mut result = Vec::new();
+        collect(self, start, &mut result);
+        result.sort_by_key(|error| error.range.start);
+        result
+    }
+
+    /// Converts the subtree into a [`Value`]. `source` must be the text the
+    /// node was parsed from and `start` is the offset of the node in it.
+    pub fn to_value<'a>(&self, source: &'a str, start: usize) -> Value<'a> {
+        let inner = self.inner_range();
+        let text = &source[start + inner.start..start + inner.end];
+        let kind = match &self.kind {
+            SyntaxKind::Symbol => ValueKind::Symbol(text),
+            SyntaxKind::Integer(n) => ValueKind::Integer(Number::with_text(*n, text)),
+            SyntaxKind::Float(x) => ValueKind::Float(Number::with_text(*x, text)),
+            SyntaxKind::String(s) => ValueKind::String(s.clone()),
+            SyntaxKind::List(children) | SyntaxKind::Program(children) => ValueKind::List(List::from_double_ended_iter(
+                children
+                    .iter()
+                    .map(|child| child.node.to_value(source, start + child.offset))
+                    .collect::<Vec<_>>(),
+            )),
+            SyntaxKind::Error => ValueKind::Error,
+        };
+        Value {
+            raw: self.raw,
+            sequential: self.sequential,
+            kind,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+/// REPL meta-command, entered with a `:` prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Command<'a> {
+    Type(&'a str),
+    Ast(&'a str),
+    Load(&'a str),
+    Reset,
+    /// Times parsing only, as forms aren't evaluated.
+    Time(&'a str),
+    Env,
+    Help,
+    Quit,
+}
+
+pub(crate) const NAMES: &[&str] = &[
+    ":ast", ":env", ":help", ":load", ":quit", ":reset", ":time", ":type",
+];
+
+pub(crate) const HELP: &str = "\
+:type <expr>   print types of the parsed forms
+:ast <expr>    print the parsed values in full
+:load <file>   parse a file and add its definitions to the environment
+:reset         forget all definitions
+:time <expr>   parse the forms and print how long parsing took; the REPL
+               doesn't evaluate them
+:env           list definitions
+:help          print this message
+:quit          exit the REPL
+";
+
+impl<'a> Command<'a> {
+    /// Parses a line starting with `:`.
+    pub(crate) fn parse(input: &'a str) -> Result<Self, String> {
+        let input = input.trim();
+        let (name, arg) = match input.find(char::is_whitespace) {
+            Some(idx) => (&input[..idx], input[idx..].trim()),
+            None => (input, ""),
+        };
+        let with_arg = |command: fn(&'a str) -> Self, usage: &str| {
+            if arg.is_empty() {
+                Err(format!("usage: {} {}", name, usage))
+            } else {
+                Ok(command(arg))
+            }
+        };
+        let without_arg = |command: Self| {
+            if arg.is_empty() {
+                Ok(command)
+            } else {
+                Err(format!("{} doesn't take arguments", name))
+            }
+        };
+        match name {
+            ":type" | ":t" => with_arg(Self::Type, "<expr>"),
+            ":ast" => with_arg(Self::Ast, "<expr>"),
+            ":load" | ":l" => with_arg(Self::Load, "<file>"),
+            ":reset" => without_arg(Self::Reset),
+            ":time" => with_arg(Self::Time, "<expr>"),
+            ":env" => without_arg(Self::Env),
+            ":help" | ":h" | ":?" => without_arg(Self::Help),
+            ":quit" | ":q" => without_arg(Self::Quit),
+            _ => Err(format!("unknown command `{}`, see `:help`", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Command::parse(":type (a\n b) "),
+            Ok(Command::Type("(a\n b)"))
+        );
+        assert_eq!(Command::parse(":q"), Ok(Command::Quit));
+        assert_eq!(Command::parse(" :env  "), Ok(Command::Env));
+        assert_eq!(
+            Command::parse(":load"),
+            Err("usage: :load <file>".to_string())
+        );
+        assert_eq!(
+            Command::parse(":reset x"),
+            Err(":reset doesn't take arguments".to_string())
+        );
+        assert_eq!(
+            Command::parse(":eval"),
+            Err("unknown command `:eval`, see `:help`".to_string())
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use spli::{Value, ValueKind};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+fn symbol<'a>(value: &Value<'a>) -> Option<&'a str> {
+    match value.kind {
+        ValueKind::Symbol(symbol) => Some(symbol),
+        _ => None,
+    }
+}
+
+/// Name defined by a form like `(def x 1)` or `(defn (f x) x)`.
+fn definition_name<'a>(form: &Value<'a>) -> Option<&'a str> {
+    let values: Vec<_> = match &form.kind {
+        ValueKind::List(values) => values.iter().collect(),
+        _ => return None,
+    };
+    if !symbol(values.first()?)?.starts_with("def") {
+        return None;
+    }
+    let target = values.get(1)?;
+    match &target.kind {
+        ValueKind::List(signature) => symbol(&*signature.iter().next()?),
+        _ => symbol(target),
+    }
+}
+
+/// What the REPL knows about the entered code. The REPL doesn't evaluate code, so
+/// definitions are the top-level `(def… name value)` forms, as written.
+#[derive(Debug, Default)]
+pub(crate) struct Env {
+    symbols: BTreeSet<String>,
+    definitions: BTreeMap<String, String>,
+}
+
+impl Env {
+    fn add_symbols(&mut self, value: &Value) {
+        match &value.kind {
+            ValueKind::Symbol(symbol) => {
+                self.symbols.insert(symbol.to_string());
+            }
+            ValueKind::List(values) => {
+                for value in values.iter() {
+                    self.add_symbols(&value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn add_definition(&mut self, form: &Value) {
+        if let Some(name) = definition_name(form) {
+            self.definitions.insert(name.to_string(), form.to_string());
+        }
+    }
+
+    /// Records symbols and definitions of a parsed program.
+    pub(crate) fn add(&mut self, program: &Value) {
+        self.add_symbols(program);
+        if let ValueKind::List(forms) = &program.kind {
+            for form in forms.iter() {
+                self.add_definition(&form);
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub(crate) fn definitions(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.definitions
+            .iter()
+            .map(|(name, form)| (name.as_str(), form.as_str()))
+    }
+
+    /// Known symbols starting with `prefix`.
+    pub(crate) fn complete(&self, prefix: &str) -> Vec<String> {
+        self.symbols
+            .range(prefix.to_string()..)
+            .take_while(|symbol| symbol.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spli::parser::program_resilient;
+
+    #[test]
+    fn test_definitions() {
+        let mut env = Env::default();
+        let (program, _) = program_resilient("(def x 1) (defn (f a) a) (g x) (def) (def 1 2)");
+        env.add(&program);
+        let names: Vec<_> = env.definitions().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["f", "x"]);
+        assert_eq!(env.complete("d"), vec!["def", "defn"]);
+        env.clear();
+        assert!(env.complete("").is_empty());
+    }
+}
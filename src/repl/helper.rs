@@ -0,0 +1,68 @@
+use super::command;
+use super::env::Env;
+
+use rustyline::completion::Completer;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use spli::highlight::{highlight_with, matching_paren, Theme};
+
+use std::borrow::Cow;
+
+/// Editor helper: completes meta-commands and symbols known to the environment, and highlights
+/// the line and the paren matching the one at the cursor.
+#[derive(Default)]
+pub(crate) struct ReplHelper {
+    pub env: Env,
+    theme: Theme,
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '(' | ')' | '\'' | '!' | '"')
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| !is_word_char(c))
+            .map_or(0, |(idx, c)| idx + c.len_utf8());
+        let prefix = &line[start..pos];
+        if start == 0 && prefix.starts_with(':') {
+            let commands = command::NAMES
+                .iter()
+                .filter(|name| name.starts_with(prefix));
+            return Ok((start, commands.map(|name| name.to_string()).collect()));
+        }
+        Ok((start, self.env.complete(prefix)))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        Cow::Owned(highlight_with(line, &self.theme, matching_paren(line, pos)))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        // Any edit may change classes of the following tokens, e.g. by opening a string
+        true
+    }
+}
+
+/// Every line is accepted, the REPL asks for more with its continuation prompt.
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
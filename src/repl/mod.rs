@@ -0,0 +1,181 @@
+mod command;
+mod env;
+mod helper;
+
+use crate::{show_errors, use_color};
+
+use command::{Command, HELP};
+use env::Env;
+use helper::ReplHelper;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use spli::highlight::{highlight, Theme};
+use spli::parser::{is_incomplete, program_resilient};
+use spli::{Value, ValueKind};
+
+use std::borrow::Cow;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+const PROMPT: &str = "spli parser> ";
+const CONTINUATION_PROMPT: &str = "        ...> ";
+const HISTORY_FILE: &str = ".spli_history";
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Parses `input`, showing errors if there are any.
+fn parse<'a>(filename: &str, input: &'a str) -> io::Result<Option<Value<'a>>> {
+    let (program, errors) = program_resilient(input);
+    if errors.is_empty() {
+        Ok(Some(program))
+    } else {
+        show_errors(filename, input, &errors)?;
+        Ok(None)
+    }
+}
+
+/// Top-level forms of a parsed program.
+fn forms<'a>(program: &Value<'a>) -> Vec<Arc<Value<'a>>> {
+    match &program.kind {
+        ValueKind::List(forms) => forms.iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// `text` highlighted if stdout is colored.
+fn highlighted(text: &str) -> Cow<'_, str> {
+    if use_color(&io::stdout()) {
+        Cow::Owned(highlight(text, &Theme::colored()))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+fn print_typed(program: &Value) {
+    for form in forms(program) {
+        println!("{} :: {}", highlighted(&form.to_string()), form.kind.type_name());
+    }
+}
+
+/// Runs a meta-command. Returns `false` if the REPL should exit.
+fn run_command(env: &mut Env, command: Command) -> io::Result<bool> {
+    match command {
+        Command::Type(expr) => {
+            if let Some(program) = parse("<stdin>", expr)? {
+                for form in forms(&program) {
+                    println!("{}", form.kind.type_name());
+                }
+            }
+        }
+        Command::Ast(expr) => {
+            if let Some(program) = parse("<stdin>", expr)? {
+                for form in forms(&program) {
+                    println!("{:#?}", form);
+                }
+            }
+        }
+        Command::Load(filename) => match std::fs::read_to_string(filename) {
+            Ok(contents) => {
+                if let Some(program) = parse(filename, &contents)? {
+                    env.add(&program);
+                    println!("Loaded {} forms from {}", forms(&program).len(), filename);
+                }
+            }
+            Err(err) => eprintln!("{}: {}", filename, err),
+        },
+        Command::Reset => env.clear(),
+        Command::Time(expr) => {
+            let start = Instant::now();
+            let program = parse("<stdin>", expr)?;
+            let elapsed = start.elapsed();
+            if let Some(program) = program {
+                env.add(&program);
+                print_typed(&program);
+                println!("Parsed in {:?}", elapsed);
+            }
+        }
+        Command::Env => {
+            for (_, form) in env.definitions() {
+                println!("{}", highlighted(form));
+            }
+        }
+        Command::Help => print!("{}", HELP),
+        Command::Quit => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn into_io_error(err: ReadlineError) -> io::Error {
+    match err {
+        ReadlineError::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+/// Reads lines until lists and strings are closed, and prints every parsed form.
+/// Lines starting with `:` are meta-commands, see `:help`. Ctrl-C discards
+/// the current input and Ctrl-D exits.
+pub(crate) fn run() -> io::Result<()> {
+    let mut editor = Editor::new().map_err(into_io_error)?;
+    editor.set_helper(Some(ReplHelper::default()));
+    let history = history_path();
+    if let Some(history) = &history {
+        // The file doesn't exist on the first run
+        let _ = editor.load_history(history);
+    }
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(into_io_error(err)),
+        };
+        if input.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        input.push_str(&line);
+        input.push('\n');
+        if is_incomplete(&input) {
+            continue;
+        }
+        let input = std::mem::take(&mut input);
+        let input = input.trim_end();
+        editor.add_history_entry(input).map_err(into_io_error)?;
+        let env = &mut editor.helper_mut().expect("helper is set above").env;
+        if input.trim_start().starts_with(':') {
+            match Command::parse(input) {
+                Ok(command) => {
+                    if !run_command(env, command)? {
+                        break;
+                    }
+                }
+                Err(message) => eprintln!("{}", message),
+            }
+        } else if let Some(program) = parse("<stdin>", input)? {
+            env.add(&program);
+            print_typed(&program);
+        }
+    }
+    // Reports unclosed lists left at the end of input
+    if !input.is_empty() {
+        println!();
+        parse("<stdin>", &input)?;
+    }
+    if let Some(history) = &history {
+        editor.save_history(history).map_err(into_io_error)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,747 @@
+//! Serializing Rust values into spli values and source with serde.
+//!
+//! The output follows the conventions of [`de`](crate::de), so it can be read
+//! back with [`de::from_str`](crate::de::from_str). Sequences, tuples, structs
+//! and maps at the top level are written as top-level forms, without
+//! enclosing parens, unless they're in `Some`.
+
+use crate::list::List;
+use crate::{Value, ValueKind};
+
+use serde::ser::{self, Serialize};
+
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::fmt::{self, Write};
+
+/// Error of serialization.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(pub String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// How enum variant names are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnumRepr {
+    /// `strict`, `(circle 1.5)`.
+    Symbol,
+    /// `"strict"`, `("circle" 1.5)`.
+    String,
+}
+
+/// How maps and structs are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapEncoding {
+    /// Keys followed by values: `(a 1 b 2)`.
+    Flat,
+    /// Lists of a key and a value: `((a 1) (b 2))`.
+    Pairs,
+}
+
+/// Options of the serializer.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub enum_repr: EnumRepr,
+    pub map_encoding: MapEncoding,
+    /// Break lists that don't fit into `width` columns, and put top-level
+    /// forms on separate lines.
+    pub pretty: bool,
+    pub width: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enum_repr: EnumRepr::Symbol,
+            map_encoding: MapEncoding::Flat,
+            pretty: false,
+            width: 80,
+        }
+    }
+}
+
+impl Config {
+    pub fn pretty() -> Self {
+        Self {
+            pretty: true,
+            ..Self::default()
+        }
+    }
+}
+
+fn simple(kind: ValueKind<'static>) -> Value<'static> {
+    Value {
+        raw: false,
+        sequential: false,
+        kind,
+    }
+}
+
+fn list(items: Vec<Value<'static>>) -> Value<'static> {
+    simple(ValueKind::List(List::from_double_ended_iter(items)))
+}
+
+/// Serializer into a [`Value`]. Symbols of the value are names of fields and
+/// variants, so it borrows nothing.
+pub struct Serializer<'c> {
+    config: &'c Config,
+    /// Set if the serialized value is a sequence, a tuple, a struct or a map.
+    is_container: Option<&'c Cell<bool>>,
+}
+
+impl<'c> Serializer<'c> {
+    pub fn new(config: &'c Config) -> Self {
+        Self {
+            config,
+            is_container: None,
+        }
+    }
+
+    fn nested(&self) -> Serializer<'c> {
+        Serializer::new(self.config)
+    }
+
+    fn container(&self) {
+        if let Some(is_container) = self.is_container {
+            is_container.set(true);
+        }
+    }
+
+    fn name(&self, name: &'static str) -> Value<'static> {
+        match self.config.enum_repr {
+            EnumRepr::Symbol => simple(ValueKind::Symbol(name)),
+            EnumRepr::String => simple(ValueKind::String(name.into())),
+        }
+    }
+
+    fn compound(self, variant: Option<&'static str>, len: Option<usize>) -> Compound<'c> {
+        let mut items = Vec::with_capacity(len.unwrap_or(0) + 1);
+        items.extend(variant.map(|variant| self.name(variant)));
+        Compound {
+            ser: self.nested(),
+            items,
+            key: None,
+        }
+    }
+}
+
+impl<'c> ser::Serializer for Serializer<'c> {
+    type Ok = Value<'static>;
+    type Error = Error;
+    type SerializeSeq = Compound<'c>;
+    type SerializeTuple = Compound<'c>;
+    type SerializeTupleStruct = Compound<'c>;
+    type SerializeTupleVariant = Compound<'c>;
+    type SerializeMap = Compound<'c>;
+    type SerializeStruct = Compound<'c>;
+    type SerializeStructVariant = Compound<'c>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value<'static>> {
+        Ok(simple(ValueKind::Symbol(if v { "true" } else { "false" })))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value<'static>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value<'static>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value<'static>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value<'static>> {
+        // Negative numbers are written like `-5`, which is read back as a
+        // symbol and converted to a number by the deserializer
+        Ok(simple(ValueKind::Integer(v.into())))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Value<'static>> {
+        let v = i64::try_from(v).map_err(|_| Error(format!("integer {} is too large", v)))?;
+        self.serialize_i64(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value<'static>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value<'static>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value<'static>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value<'static>> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Value<'static>> {
+        let v = i64::try_from(v).map_err(|_| Error(format!("integer {} is too large", v)))?;
+        self.serialize_i64(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value<'static>> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value<'static>> {
+        if !v.is_finite() {
+            return Err(Error(format!("float {} can't be written", v)));
+        }
+        Ok(simple(ValueKind::Float(v.into())))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value<'static>> {
+        Ok(simple(ValueKind::String(v.into())))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value<'static>> {
+        Ok(simple(ValueKind::String(v.into())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value<'static>> {
+        let items = v
+            .iter()
+            .map(|&byte| simple(ValueKind::Integer(i64::from(byte).into())));
+        Ok(list(items.collect()))
+    }
+
+    fn serialize_none(self) -> Result<Value<'static>> {
+        Ok(simple(ValueKind::Symbol("nil")))
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Value<'static>> {
+        // Containers stay lists, or a single item would be read as the value
+        value.serialize(self.nested())
+    }
+
+    fn serialize_unit(self) -> Result<Value<'static>> {
+        Ok(list(Vec::new()))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value<'static>> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Value<'static>> {
+        Ok(self.name(variant))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value<'static>> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value<'static>> {
+        let value = value.serialize(self.nested())?;
+        Ok(list(vec![self.name(variant), value]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Compound<'c>> {
+        self.container();
+        Ok(self.compound(None, len))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'c>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Compound<'c>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'c>> {
+        Ok(self.compound(Some(variant), Some(len)))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound<'c>> {
+        self.container();
+        Ok(self.compound(None, len.map(|len| len * 2)))
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Compound<'c>> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'c>> {
+        Ok(self.compound(Some(variant), Some(len * 2)))
+    }
+}
+
+/// Items of a list being serialized.
+pub struct Compound<'c> {
+    ser: Serializer<'c>,
+    items: Vec<Value<'static>>,
+    /// Key of a map entry, waiting for its value.
+    key: Option<Value<'static>>,
+}
+
+impl<'c> Compound<'c> {
+    fn push<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.items.push(value.serialize(self.ser.nested())?);
+        Ok(())
+    }
+
+    fn push_entry(&mut self, key: Value<'static>, value: Value<'static>) {
+        match self.ser.config.map_encoding {
+            MapEncoding::Flat => self.items.extend([key, value]),
+            MapEncoding::Pairs => self.items.push(list(vec![key, value])),
+        }
+    }
+
+    fn push_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        let value = value.serialize(self.ser.nested())?;
+        self.push_entry(simple(ValueKind::Symbol(key)), value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value<'static>> {
+        Ok(list(self.items))
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_> {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value<'static>> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_> {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value<'static>> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_> {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value<'static>> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound<'_> {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value<'static>> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeMap for Compound<'_> {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(key.serialize(self.ser.nested())?);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        let key = self.key.take().expect("value is serialized after its key");
+        let value = value.serialize(self.ser.nested())?;
+        self.push_entry(key, value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value<'static>> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_> {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.push_field(key, value)
+    }
+
+    fn end(self) -> Result<Value<'static>> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_> {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.push_field(key, value)
+    }
+
+    fn end(self) -> Result<Value<'static>> {
+        Compound::end(self)
+    }
+}
+
+/// Serializes `value` into a [`Value`].
+pub fn to_value<T: ?Sized + Serialize>(value: &T, config: &Config) -> Result<Value<'static>> {
+    value.serialize(Serializer::new(config))
+}
+
+/// Whether `items` look like keys followed by values.
+fn is_flat_map(items: &[&Value]) -> bool {
+    items.len().is_multiple_of(2)
+        && items
+            .iter()
+            .step_by(2)
+            .all(|key| matches!(key.kind, ValueKind::Symbol(_) | ValueKind::String(_)))
+}
+
+/// Writes `value`, breaking lists that don't fit into the width. The value
+/// starts at `column`, lines are indented by `indent`.
+fn write_pretty(value: &Value, column: usize, width: usize, out: &mut String) {
+    let flat = value.to_string();
+    let items: Vec<&Value> = match &value.kind {
+        ValueKind::List(items) if column + flat.len() > width => items.values().collect(),
+        _ => return out.push_str(&flat),
+    };
+    let start = out.len();
+    if value.raw {
+        out.push('\'');
+    }
+    if value.sequential {
+        out.push('!');
+    }
+    out.push('(');
+    let column = column + out.len() - start;
+    write_items(&items, column, width, true, out);
+    out.push(')');
+}
+
+/// Writes list items one per line, or entries one per line if the items look
+/// like a map. If `head` is set, a leading variant name stays on the first line.
+fn write_items(items: &[&Value], column: usize, width: usize, head: bool, out: &mut String) {
+    let mut indent = column;
+    let mut items = items;
+    if let [first, rest @ ..] = items {
+        let is_head = matches!(first.kind, ValueKind::Symbol(_) | ValueKind::String(_));
+        if head && is_head && !rest.is_empty() && !is_flat_map(items) {
+            let first = first.to_string();
+            out.push_str(&first);
+            out.push(' ');
+            indent += first.len() + 1;
+            items = rest;
+        }
+    }
+    let step = if is_flat_map(items) { 2 } else { 1 };
+    for (idx, chunk) in items.chunks(step).enumerate() {
+        if idx > 0 {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent));
+        }
+        match chunk {
+            [key, value] => {
+                let key = key.to_string();
+                out.push_str(&key);
+                out.push(' ');
+                write_pretty(value, indent + key.len() + 1, width, out);
+            }
+            _ => write_pretty(chunk[0], indent, width, out),
+        }
+    }
+}
+
+/// Prints `value`, breaking lists that don't fit into `width` columns.
+pub fn pretty_value(value: &Value, width: usize) -> String {
+    let mut out = String::new();
+    write_pretty(value, 0, width, &mut out);
+    out
+}
+
+/// Serializes `value` into spli source.
+pub fn to_string_with<T: ?Sized + Serialize>(value: &T, config: &Config) -> Result<String> {
+    let is_container = Cell::new(false);
+    let value = value.serialize(Serializer {
+        config,
+        is_container: Some(&is_container),
+    })?;
+    let mut out = String::new();
+    match &value.kind {
+        ValueKind::List(items) if is_container.get() => {
+            let items: Vec<&Value> = items.values().collect();
+            if config.pretty {
+                write_items(&items, 0, config.width, false, &mut out);
+                out.push('\n');
+            } else {
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        out.push(' ');
+                    }
+                    write!(out, "{}", item).expect("writing to a `String` can't fail");
+                }
+            }
+        }
+        _ if config.pretty => {
+            write_pretty(&value, 0, config.width, &mut out);
+            out.push('\n');
+        }
+        _ => write!(out, "{}", value).expect("writing to a `String` can't fail"),
+    }
+    Ok(out)
+}
+
+/// Serializes `value` into spli source on a single line.
+pub fn to_string<T: ?Sized + Serialize>(value: &T) -> Result<String> {
+    to_string_with(value, &Config::default())
+}
+
+/// Serializes `value` into spli source, breaking long lists.
+pub fn to_string_pretty<T: ?Sized + Serialize>(value: &T) -> Result<String> {
+    to_string_with(value, &Config::pretty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::de::from_str;
+    use proptest::prelude::*;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    #[serde(rename_all = "kebab-case")]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Rect(u32, u32),
+        Point { x: u32, y: u32 },
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Config {
+        name: String,
+        port: u16,
+        debug: bool,
+        parent: Option<String>,
+        tags: Vec<String>,
+        shapes: Vec<Shape>,
+        limits: BTreeMap<String, u64>,
+    }
+
+    fn config() -> Config {
+        Config {
+            name: "spli \"parser\"".into(),
+            port: 8080,
+            debug: true,
+            parent: None,
+            tags: vec!["lisp".into(), "\u{3bb}\n".into()],
+            shapes: vec![
+                Shape::Empty,
+                Shape::Circle(1.5),
+                Shape::Rect(2, 3),
+                Shape::Point { x: 1, y: 2 },
+            ],
+            limits: vec![("depth".into(), 10), ("width".into(), 1000)]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_to_string() {
+        assert_eq!(
+            to_string(&config()).unwrap(),
+            concat!(
+                r#"name "spli \"parser\"" port 8080 debug true parent nil "#,
+                "tags (\"lisp\" \"\u{3bb}\\n\") ",
+                "shapes (empty (circle 1.5) (rect 2 3) (point x 1 y 2)) ",
+                r#"limits ("depth" 10 "width" 1000)"#,
+            )
+        );
+        assert_eq!(to_string(&Shape::Circle(2.0)).unwrap(), "(circle 2.0)");
+        assert_eq!(to_string(&(1, "a")).unwrap(), "1 \"a\"");
+        assert_eq!(to_string(&Some(vec![5])).unwrap(), "(5)");
+        assert_eq!(to_string(&(-1, -2.5, -0.0)).unwrap(), "-1 -2.5 -0.0");
+        assert_eq!(to_string(&1e20).unwrap(), "100000000000000000000.0");
+        assert!(to_string(&f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_pretty() {
+        assert_eq!(
+            to_string_pretty(&config()).unwrap(),
+            concat!(
+                "name \"spli \\\"parser\\\"\"\n",
+                "port 8080\n",
+                "debug true\n",
+                "parent nil\n",
+                "tags (\"lisp\" \"\u{3bb}\\n\")\n",
+                "shapes (empty (circle 1.5) (rect 2 3) (point x 1 y 2))\n",
+                "limits (\"depth\" 10 \"width\" 1000)\n",
+            )
+        );
+        let config = super::Config {
+            width: 15,
+            ..super::Config::pretty()
+        };
+        assert_eq!(
+            to_string_with(&vec![Shape::Point { x: 100, y: 200 }], &config).unwrap(),
+            "(point x 100\n       y 200)\n"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let encodings = [MapEncoding::Flat, MapEncoding::Pairs];
+        let reprs = [EnumRepr::Symbol, EnumRepr::String];
+        for &map_encoding in &encodings {
+            for &enum_repr in &reprs {
+                for &pretty in &[false, true] {
+                    let options = super::Config {
+                        enum_repr,
+                        map_encoding,
+                        pretty,
+                        width: 30,
+                    };
+                    let source = to_string_with(&config(), &options).unwrap();
+                    assert_eq!(from_str::<Config>(&source), Ok(config()), "{}", source);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_value() {
+        let options = super::Config {
+            enum_repr: EnumRepr::String,
+            map_encoding: MapEncoding::Pairs,
+            ..super::Config::default()
+        };
+        let value = to_value(&Shape::Point { x: 1, y: 2 }, &options).unwrap();
+        assert_eq!(value.to_string(), "(\"point\" (x 1) (y 2))");
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Numbers {
+        small: i8,
+        large: i64,
+        unsigned: u64,
+        single: f32,
+        double: f64,
+        list: Vec<(i32, f64)>,
+    }
+
+    fn finite_f64() -> impl Strategy<Value = f64> {
+        use proptest::num::f64::*;
+        NEGATIVE | POSITIVE | NORMAL | SUBNORMAL | ZERO
+    }
+
+    prop_compose! {
+        fn numbers()(
+            small in any::<i8>(),
+            large in any::<i64>(),
+            unsigned in 0..=i64::MAX as u64,
+            single in finite_f64().prop_map(|x| x as f32).prop_filter("finite", |x| x.is_finite()),
+            double in finite_f64(),
+            list in proptest::collection::vec((any::<i32>(), finite_f64()), 0..4),
+        ) -> Numbers {
+            Numbers { small, large, unsigned, single, double, list }
+        }
+    }
+
+    fn options(pretty: bool) -> super::Config {
+        if pretty {
+            super::Config::pretty()
+        } else {
+            super::Config::default()
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn prop_numbers_round_trip(numbers in numbers(), pretty in any::<bool>()) {
+            let source = to_string_with(&numbers, &options(pretty)).unwrap();
+            prop_assert_eq!(from_str::<Numbers>(&source), Ok(numbers), "{}", source);
+        }
+
+        #[test]
+        fn prop_options_round_trip(
+            list in proptest::option::of(proptest::collection::vec(any::<i32>(), 0..3)),
+            numbers in proptest::option::of(numbers()),
+            pretty in any::<bool>(),
+        ) {
+            let source = to_string_with(&list, &options(pretty)).unwrap();
+            prop_assert_eq!(from_str::<Option<Vec<i32>>>(&source), Ok(list), "{}", source);
+            let source = to_string_with(&numbers, &options(pretty)).unwrap();
+            prop_assert_eq!(from_str::<Option<Numbers>>(&source), Ok(numbers), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Instructions of the VM and their listing.
+
+use super::{Operand, Primitive};
+
+use std::fmt::Write;
+use std::rc::Rc;
+
+/// Instruction. Operands are indices into the constant pool, the nested
+/// functions, the stack frame or the upvalues of the running function, or
+/// into the global slots of the VM. Jump targets are instruction indices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Constant(u16),
+    GetLocal(u16),
+    GetUpvalue(u16),
+    GetGlobal(u16),
+    /// Pops a value into a global variable.
+    SetGlobal(u16),
+    /// Pops a closure into a function slot.
+    SetFunction(u16),
+    /// Pushes a closure of a nested function, capturing its upvalues.
+    Closure(u16),
+    /// Calls a function slot with the arguments on top of the stack.
+    Call(u16, u8),
+    TailCall(u16, u8),
+    /// Calls a primitive with its last argument read from a local, and the
+    /// others on top of the stack. The primitive is computed in place, unless
+    /// its function was replaced or it has to be called.
+    PrimitiveLocal(Primitive, u16),
+    /// Calls a primitive with its last argument read from the constant pool.
+    PrimitiveConstant(Primitive, u16),
+    /// Calls the closure below the arguments.
+    CallValue(u8),
+    TailCallValue(u8),
+    Jump(u16),
+    /// Pops a value and jumps if it's false.
+    JumpIfFalse(u16),
+    Pop,
+    /// Removes values below the top one, at the end of a `let`.
+    Slide(u16),
+    Return,
+}
+
+impl Op {
+    /// Change of the stack height after the instruction.
+    pub(crate) fn stack_effect(self) -> isize {
+        match self {
+            Self::Constant(_)
+            | Self::GetLocal(_)
+            | Self::GetUpvalue(_)
+            | Self::GetGlobal(_)
+            | Self::Closure(_) => 1,
+            Self::SetGlobal(_) | Self::SetFunction(_) | Self::JumpIfFalse(_) | Self::Pop => -1,
+            Self::Call(_, argc) | Self::TailCall(_, argc) => 1 - argc as isize,
+            Self::PrimitiveLocal(primitive, _) | Self::PrimitiveConstant(primitive, _) => {
+                2 - primitive.arity() as isize
+            }
+            Self::CallValue(argc) | Self::TailCallValue(argc) => -(argc as isize),
+            Self::Slide(count) => -(count as isize),
+            Self::Jump(_) => 0,
+            Self::Return => -1,
+        }
+    }
+}
+
+/// Where a closure takes an upvalue from when it's created: a local or an
+/// upvalue of the function creating it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Capture {
+    Local(u16),
+    Upvalue(u16),
+}
+
+/// Compiled function.
+#[derive(Default)]
+pub(crate) struct Proto<'a> {
+    /// Name of a `defn` function, anonymous functions and top-level forms
+    /// have none.
+    pub(crate) name: Option<&'a str>,
+    pub(crate) arity: usize,
+    pub(crate) code: Vec<Op>,
+    pub(crate) constants: Vec<Operand<'a>>,
+    pub(crate) protos: Vec<Rc<Proto<'a>>>,
+    pub(crate) captures: Vec<Capture>,
+}
+
+impl Proto<'_> {
+    /// Lists the code of the function and then of the nested ones. Global
+    /// slots are shown with the names in `globals` and `functions`.
+    pub(crate) fn disassemble(&self, globals: &[String], functions: &[String], out: &mut String) {
+        let _ = writeln!(
+            out,
+            "== {} ({} params, {} upvalues) ==",
+            self.name.unwrap_or("<fn>"),
+            self.arity,
+            self.captures.len()
+        );
+        for (idx, op) in self.code.iter().enumerate() {
+            let _ = write!(out, "{:04} ", idx);
+            let _ = match *op {
+                Op::Constant(idx) => {
+                    let value = &self.constants[idx as usize];
+                    writeln!(out, "constant {} ; {}", idx, value)
+                }
+                Op::GetLocal(slot) => writeln!(out, "get-local {}", slot),
+                Op::GetUpvalue(idx) => writeln!(out, "get-upvalue {}", idx),
+                Op::GetGlobal(slot) => {
+                    writeln!(out, "get-global {} ; {}", slot, globals[slot as usize])
+                }
+                Op::SetGlobal(slot) => {
+                    writeln!(out, "set-global {} ; {}", slot, globals[slot as usize])
+                }
+                Op::SetFunction(slot) => {
+                    writeln!(out, "set-function {} ; {}", slot, functions[slot as usize])
+                }
+                Op::Closure(idx) => {
+                    let name = self.protos[idx as usize].name.unwrap_or("<fn>");
+                    writeln!(out, "closure {} ; {}", idx, name)
+                }
+                Op::Call(slot, argc) => {
+                    writeln!(out, "call {} {} ; {}", slot, argc, functions[slot as usize])
+                }
+                Op::TailCall(slot, argc) => {
+                    writeln!(
+                        out,
+                        "tail-call {} {} ; {}",
+                        slot, argc, functions[slot as usize]
+                    )
+                }
+                Op::PrimitiveLocal(primitive, slot) => {
+                    writeln!(out, "primitive-local {} {}", primitive.name(), slot)
+                }
+                Op::PrimitiveConstant(primitive, idx) => {
+                    let value = &self.constants[idx as usize];
+                    writeln!(
+                        out,
+                        "primitive-constant {} {} ; {}",
+                        primitive.name(),
+                        idx,
+                        value
+                    )
+                }
+                Op::CallValue(argc) => writeln!(out, "call-value {}", argc),
+                Op::TailCallValue(argc) => writeln!(out, "tail-call-value {}", argc),
+                Op::Jump(target) => writeln!(out, "jump {:04}", target),
+                Op::JumpIfFalse(target) => writeln!(out, "jump-if-false {:04}", target),
+                Op::Pop => writeln!(out, "pop"),
+                Op::Slide(count) => writeln!(out, "slide {}", count),
+                Op::Return => writeln!(out, "return"),
+            };
+        }
+        for proto in &self.protos {
+            proto.disassemble(globals, functions, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_op_size() {
+        assert_eq!(std::mem::size_of::<Op>(), 4);
+    }
+}
@@ -0,0 +1,372 @@
+//! Compilation of expanded forms to bytecode.
+
+use super::bytecode::{Capture, Op, Proto};
+use super::{Primitive, Vm};
+use crate::interpreter::{items, lambda, nil, symbol, Error};
+use crate::list::List;
+use crate::{Value, ValueKind};
+
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+/// Function being compiled.
+struct Scope<'a> {
+    proto: Proto<'a>,
+    /// Names of the locals, with their slots in the stack frame.
+    locals: Vec<(&'a str, u16)>,
+    /// Names of the upvalues, in the order of `proto.captures`.
+    upvalues: Vec<&'a str>,
+    /// Whether the function captures the locals of the enclosing one.
+    closure: bool,
+    /// Number of values in the stack frame at the current instruction.
+    height: usize,
+}
+
+impl<'a> Scope<'a> {
+    fn new(name: Option<&'a str>, params: &[&'a str], closure: bool) -> Self {
+        Self {
+            proto: Proto {
+                name,
+                arity: params.len(),
+                ..Proto::default()
+            },
+            locals: params.iter().copied().zip(0..).collect(),
+            upvalues: Vec::new(),
+            closure,
+            height: params.len(),
+        }
+    }
+}
+
+/// How a name is resolved in a function.
+#[derive(Clone, Copy)]
+enum Variable {
+    Local(u16),
+    Upvalue(u16),
+}
+
+fn index(idx: usize) -> Result<u16, Error> {
+    u16::try_from(idx).map_err(|_| Error::InvalidForm("function is too large to compile"))
+}
+
+fn arg_count(count: usize) -> Result<u8, Error> {
+    u8::try_from(count).map_err(|_| Error::InvalidForm("too many arguments in a call"))
+}
+
+pub(super) struct Compiler<'v, 'a> {
+    vm: &'v mut Vm<'a>,
+    /// Functions being compiled, innermost last.
+    scopes: Vec<Scope<'a>>,
+}
+
+impl<'v, 'a> Compiler<'v, 'a> {
+    /// Compiles a form with expanded macros into a function without params.
+    pub(super) fn compile(vm: &'v mut Vm<'a>, value: &Value<'a>) -> Result<Proto<'a>, Error> {
+        let mut compiler = Self {
+            vm,
+            scopes: vec![Scope::new(None, &[], false)],
+        };
+        compiler.expr(value, true)?;
+        compiler.emit(Op::Return);
+        Ok(compiler.scopes.remove(0).proto)
+    }
+
+    fn scope(&mut self) -> &mut Scope<'a> {
+        self.scopes.last_mut().expect("no function is compiled")
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let scope = self.scope();
+        scope.height = scope.height.wrapping_add_signed(op.stack_effect());
+        scope.proto.code.push(op);
+        scope.proto.code.len() - 1
+    }
+
+    /// Makes the jump at `from` go to the next instruction.
+    fn patch_jump(&mut self, from: usize) -> Result<(), Error> {
+        let code = &mut self.scope().proto.code;
+        let target = index(code.len())?;
+        match &mut code[from] {
+            Op::Jump(to) | Op::JumpIfFalse(to) => *to = target,
+            op => unreachable!("{:?} isn't a jump", op),
+        }
+        Ok(())
+    }
+
+    fn add_constant(&mut self, value: Value<'a>) -> Result<u16, Error> {
+        let constants = &mut self.scope().proto.constants;
+        let idx = index(constants.len())?;
+        constants.push(value.into());
+        Ok(idx)
+    }
+
+    fn constant(&mut self, value: Value<'a>) -> Result<(), Error> {
+        let idx = self.add_constant(value)?;
+        self.emit(Op::Constant(idx));
+        Ok(())
+    }
+
+    /// Resolves `name` in the function at `depth`, capturing it from the
+    /// enclosing functions if needed.
+    fn resolve(&mut self, depth: usize, name: &'a str) -> Result<Option<Variable>, Error> {
+        let scope = &self.scopes[depth];
+        if let Some(&(_, slot)) = scope.locals.iter().rev().find(|(local, _)| *local == name) {
+            return Ok(Some(Variable::Local(slot)));
+        }
+        if let Some(idx) = scope.upvalues.iter().position(|upvalue| *upvalue == name) {
+            return Ok(Some(Variable::Upvalue(index(idx)?)));
+        }
+        if !scope.closure || depth == 0 {
+            return Ok(None);
+        }
+        let capture = match self.resolve(depth - 1, name)? {
+            Some(Variable::Local(slot)) => Capture::Local(slot),
+            Some(Variable::Upvalue(idx)) => Capture::Upvalue(idx),
+            None => return Ok(None),
+        };
+        let scope = &mut self.scopes[depth];
+        let idx = index(scope.upvalues.len())?;
+        scope.upvalues.push(name);
+        scope.proto.captures.push(capture);
+        Ok(Some(Variable::Upvalue(idx)))
+    }
+
+    fn expr(&mut self, value: &Value<'a>, tail: bool) -> Result<(), Error> {
+        match &value.kind {
+            _ if value.raw => {
+                let mut value = value.clone();
+                value.raw = false;
+                self.constant(value)
+            }
+            ValueKind::Symbol(name) => self.variable(name),
+            ValueKind::List(items) if value.sequential => self.body(items.values(), tail),
+            ValueKind::List(items) => self.list(value, &items.values().collect::<Vec<_>>(), tail),
+            ValueKind::Error => Err(Error::InvalidForm("value has parsing errors")),
+            _ => self.constant(value.clone()),
+        }
+    }
+
+    /// Instruction calling `primitive` with `value` read in place as its last
+    /// argument, if it's a local or a constant.
+    fn primitive(&mut self, primitive: Primitive, value: &Value<'a>) -> Result<Option<Op>, Error> {
+        let idx = match &value.kind {
+            _ if value.raw => {
+                let mut value = value.clone();
+                value.raw = false;
+                self.add_constant(value)?
+            }
+            ValueKind::Symbol(name) => {
+                let locals = &self.scope().locals;
+                return Ok(locals
+                    .iter()
+                    .rev()
+                    .find(|(local, _)| local == name)
+                    .map(|&(_, slot)| Op::PrimitiveLocal(primitive, slot)));
+            }
+            ValueKind::List(_) | ValueKind::Error => return Ok(None),
+            _ => self.add_constant(value.clone())?,
+        };
+        Ok(Some(Op::PrimitiveConstant(primitive, idx)))
+    }
+
+    fn variable(&mut self, name: &'a str) -> Result<(), Error> {
+        let op = match self.resolve(self.scopes.len() - 1, name)? {
+            Some(Variable::Local(slot)) => Op::GetLocal(slot),
+            Some(Variable::Upvalue(idx)) => Op::GetUpvalue(idx),
+            None => Op::GetGlobal(self.vm.globals.slot(name)?),
+        };
+        self.emit(op);
+        Ok(())
+    }
+
+    /// Forms in order, the last one is in tail position if the body is.
+    fn body<'f>(
+        &mut self,
+        forms: impl Iterator<Item = &'f Value<'a>>,
+        tail: bool,
+    ) -> Result<(), Error>
+    where
+        'a: 'f,
+    {
+        let mut forms = forms.peekable();
+        if forms.peek().is_none() {
+            return self.constant(nil());
+        }
+        while let Some(form) = forms.next() {
+            if forms.peek().is_none() {
+                return self.expr(form, tail);
+            }
+            self.expr(form, false)?;
+            self.emit(Op::Pop);
+        }
+        Ok(())
+    }
+
+    fn list(&mut self, value: &Value<'a>, items: &[&Value<'a>], tail: bool) -> Result<(), Error> {
+        let (head, args) = match items.split_first() {
+            Some(split) => split,
+            None => return self.constant(ValueKind::List(List::new()).into()),
+        };
+        let name = symbol(head).ok_or_else(|| Error::NotCallable(head.kind.type_name()))?;
+        match name {
+            "def" => self.def(args),
+            "defn" => self.defn(args),
+            "defmacro" => {
+                let name = self.vm.interpreter.eval(value)?;
+                self.constant(name)
+            }
+            "if" => self.branch(args, tail),
+            "let" => self.bind(args, tail),
+            "fn" => self.closure(args),
+            "call" => {
+                let (function, args) = args
+                    .split_first()
+                    .ok_or(Error::InvalidForm("`call` takes a function and arguments"))?;
+                self.expr(function, false)?;
+                for arg in args {
+                    self.expr(arg, false)?;
+                }
+                let argc = arg_count(args.len())?;
+                self.emit(if tail {
+                    Op::TailCallValue(argc)
+                } else {
+                    Op::CallValue(argc)
+                });
+                Ok(())
+            }
+            _ => {
+                let primitive =
+                    Primitive::named(name).filter(|primitive| primitive.arity() == args.len());
+                if let (Some(primitive), Some((last, args))) = (primitive, args.split_last()) {
+                    if let Some(op) = self.primitive(primitive, last)? {
+                        for arg in args {
+                            self.expr(arg, false)?;
+                        }
+                        self.emit(op);
+                        if tail {
+                            // Lets a replaced primitive make a tail call
+                            self.emit(Op::Return);
+                            self.scope().height += 1;
+                        }
+                        return Ok(());
+                    }
+                }
+                for arg in args {
+                    self.expr(arg, false)?;
+                }
+                let slot = self.vm.functions.slot(name)?;
+                let argc = arg_count(args.len())?;
+                self.emit(if tail {
+                    Op::TailCall(slot, argc)
+                } else {
+                    Op::Call(slot, argc)
+                });
+                Ok(())
+            }
+        }
+    }
+
+    fn def(&mut self, args: &[&Value<'a>]) -> Result<(), Error> {
+        const USAGE: &str = "`def` takes a name and a value";
+        let (name, value) = match args {
+            [name, value] => (symbol(name).ok_or(Error::InvalidForm(USAGE))?, value),
+            _ => return Err(Error::InvalidForm(USAGE)),
+        };
+        self.expr(value, false)?;
+        let slot = self.vm.globals.slot(name)?;
+        self.emit(Op::SetGlobal(slot));
+        self.constant(ValueKind::Symbol(name).into())
+    }
+
+    fn defn(&mut self, args: &[&Value<'a>]) -> Result<(), Error> {
+        const USAGE: &str = "`defn` takes a signature like `(name params…)` and a body";
+        let (name, lambda) = lambda(args).ok_or(Error::InvalidForm(USAGE))?;
+        self.function(Some(name), &lambda.params, &lambda.body, false)?;
+        let slot = self.vm.functions.slot(name)?;
+        self.emit(Op::SetFunction(slot));
+        self.constant(ValueKind::Symbol(name).into())
+    }
+
+    fn closure(&mut self, args: &[&Value<'a>]) -> Result<(), Error> {
+        const USAGE: &str = "`fn` takes a list of params and a body";
+        let (params, body) = args.split_first().ok_or(Error::InvalidForm(USAGE))?;
+        let params: Vec<_> = items(params)
+            .and_then(|params| params.iter().map(|param| symbol(param)).collect())
+            .ok_or(Error::InvalidForm(USAGE))?;
+        let body = List::from_double_ended_iter(body.iter().map(|form| (*form).clone()));
+        self.function(None, &params, &body, true)
+    }
+
+    /// Compiles a nested function and pushes its closure.
+    fn function(
+        &mut self,
+        name: Option<&'a str>,
+        params: &[&'a str],
+        body: &List<Value<'a>>,
+        closure: bool,
+    ) -> Result<(), Error> {
+        arg_count(params.len())?;
+        self.scopes.push(Scope::new(name, params, closure));
+        let compiled = self.body(body.values(), true);
+        self.emit(Op::Return);
+        let scope = self.scopes.pop().expect("no function is compiled");
+        compiled?;
+        let protos = &mut self.scope().proto.protos;
+        let idx = index(protos.len())?;
+        protos.push(Rc::new(scope.proto));
+        self.emit(Op::Closure(idx));
+        Ok(())
+    }
+
+    fn branch(&mut self, args: &[&Value<'a>], tail: bool) -> Result<(), Error> {
+        let (condition, then, otherwise) = match args {
+            [condition, then] => (condition, then, None),
+            [condition, then, otherwise] => (condition, then, Some(otherwise)),
+            _ => {
+                return Err(Error::InvalidForm(
+                    "`if` takes a condition and one or two branches",
+                ))
+            }
+        };
+        self.expr(condition, false)?;
+        let to_otherwise = self.emit(Op::JumpIfFalse(0));
+        self.expr(then, tail)?;
+        let to_end = self.emit(Op::Jump(0));
+        // Only one of the branches pushes its value
+        self.scope().height -= 1;
+        self.patch_jump(to_otherwise)?;
+        match otherwise {
+            Some(otherwise) => self.expr(otherwise, tail)?,
+            None => self.constant(nil())?,
+        }
+        self.patch_jump(to_end)
+    }
+
+    fn bind(&mut self, args: &[&Value<'a>], tail: bool) -> Result<(), Error> {
+        let (bindings, body) = args
+            .split_first()
+            .and_then(|(bindings, body)| Some((items(bindings)?, body)))
+            .ok_or(Error::InvalidForm(
+                "`let` takes a list of bindings and a body",
+            ))?;
+        let scope_start = self.scope().locals.len();
+        for binding in &bindings {
+            let (name, value) = match items(binding).as_deref() {
+                Some(&[name, value]) => (symbol(name), value),
+                _ => (None, *binding),
+            };
+            let name = name.ok_or(Error::InvalidForm(
+                "`let` binding is a list of a name and a value",
+            ))?;
+            let slot = index(self.scope().height)?;
+            self.expr(value, false)?;
+            self.scope().locals.push((name, slot));
+        }
+        self.body(body.iter().copied(), tail)?;
+        if !bindings.is_empty() {
+            self.emit(Op::Slide(index(bindings.len())?));
+        }
+        self.scope().locals.truncate(scope_start);
+        Ok(())
+    }
+}
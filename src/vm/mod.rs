@@ -0,0 +1,1071 @@
+//! Bytecode compiler and stack-based virtual machine for spli programs.
+//!
+//! Programs mean the same as in the [`Interpreter`], but every top-level form
+//! is compiled before it runs: variables are resolved to slots in the stack
+//! frame or to global slots, literals go to the constant pool of their
+//! function, and calls in tail position become tail call instructions.
+//! [`Vm::disassemble`] lists the compiled code.
+//!
+//! The VM adds closures: `(fn (params…) body…)` makes an anonymous function,
+//! which captures the locals of the enclosing functions it uses as upvalues,
+//! and `(call f args…)` calls it. Locals can't be reassigned, so upvalues are
+//! copies of their values. Closures can't leave the VM, e.g. be returned from
+//! [`Vm::run`] or passed to native functions.
+//!
+//! Calls of the builtin `+`, `-`, `*` and comparisons with two numbers, and of
+//! `cons`, `head`, `tail` and `len` with lists, are computed in place, and
+//! read their last argument in place when it's a local or a literal. Loops over
+//! numbers run 12 to 15 times faster than in the interpreter. Loops calling
+//! other functions run about 9 times faster, as every call out of tail
+//! position pushes a frame and returns through it, and loops over lists only
+//! about 7 times, as allocating and releasing the nodes of lists takes as long
+//! in both.
+//!
+//! Macros are expanded by an inner [`Interpreter`], so their bodies can call
+//! native functions, but not the functions compiled by the VM. A `defmacro`
+//! form takes effect once it's compiled.
+
+mod bytecode;
+mod compiler;
+
+use bytecode::{Capture, Op, Proto};
+use compiler::Compiler;
+
+use crate::interpreter::{is_truthy, Error, Interpreter, NativeFn};
+use crate::list::List;
+use crate::parser::program_resilient;
+use crate::{Number, Value, ValueKind};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+/// Function with the values of its upvalues.
+struct Closure<'a> {
+    proto: Rc<Proto<'a>>,
+    upvalues: Vec<Operand<'a>>,
+}
+
+/// Value on the stack of the VM. Plain numbers, symbols and lists are
+/// unpacked, so that they're cheap to copy, and other values are boxed to keep
+/// it small.
+enum Operand<'a> {
+    Integer(Number<'a, i64>),
+    Float(Number<'a, f64>),
+    Symbol(&'a str),
+    List(List<Value<'a>>),
+    Value(Box<Value<'a>>),
+    Closure(Rc<Closure<'a>>),
+}
+
+impl Clone for Operand<'_> {
+    // Inlined, so that copying a number doesn't take a call
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        match self {
+            Self::Integer(number) => Self::Integer(*number),
+            Self::Float(number) => Self::Float(*number),
+            Self::Symbol(name) => Self::Symbol(name),
+            Self::List(list) => Self::List(list.clone()),
+            Self::Value(value) => Self::Value(value.clone()),
+            Self::Closure(closure) => Self::Closure(closure.clone()),
+        }
+    }
+}
+
+impl<'a> From<Value<'a>> for Operand<'a> {
+    fn from(mut value: Value<'a>) -> Self {
+        // Values with modifiers are boxed, so that they keep them
+        if value.raw || value.sequential {
+            return Self::Value(Box::new(value));
+        }
+        match value.kind {
+            ValueKind::Integer(number) => Self::Integer(number),
+            ValueKind::Float(number) => Self::Float(number),
+            ValueKind::Symbol(name) => Self::Symbol(name),
+            ValueKind::List(ref mut list) => Self::List(std::mem::take(list)),
+            _ => Self::Value(Box::new(value)),
+        }
+    }
+}
+
+impl<'a> Operand<'a> {
+    fn into_value(self) -> Result<Value<'a>, Error> {
+        match self {
+            Self::Integer(number) => Ok(ValueKind::Integer(number).into()),
+            Self::Float(number) => Ok(ValueKind::Float(number).into()),
+            Self::Symbol(name) => Ok(ValueKind::Symbol(name).into()),
+            Self::List(list) => Ok(ValueKind::List(list).into()),
+            Self::Value(value) => Ok(*value),
+            Self::Closure(_) => Err(Error::Mismatch {
+                expected: "value",
+                got: "function",
+            }),
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        match self {
+            Self::Symbol(name) => !matches!(*name, "false" | "nil"),
+            Self::Value(value) => is_truthy(value),
+            Self::Integer(_) | Self::Float(_) | Self::List(_) | Self::Closure(_) => true,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Self::Integer(_) => "integer",
+            Self::Float(_) => "float",
+            Self::Symbol(_) => "symbol",
+            Self::List(_) => "list",
+            Self::Value(value) => value.kind.type_name(),
+            Self::Closure(_) => "function",
+        }
+    }
+}
+
+impl fmt::Display for Operand<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(number) => number.fmt(fmt),
+            Self::Float(number) => number.fmt(fmt),
+            Self::Symbol(name) => fmt.write_str(name),
+            Self::List(list) => ValueKind::List(list.clone()).fmt(fmt),
+            Self::Value(value) => value.fmt(fmt),
+            Self::Closure(closure) => write!(fmt, "<{}>", closure.proto.name.unwrap_or("fn")),
+        }
+    }
+}
+
+/// Builtin function that is computed in place for numbers or lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Primitive {
+    Add,
+    Sub,
+    Mul,
+    Eq,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Cons,
+    Head,
+    Tail,
+    Len,
+}
+
+impl Primitive {
+    const ALL: &'static [(&'static str, Self)] = &[
+        ("+", Self::Add),
+        ("-", Self::Sub),
+        ("*", Self::Mul),
+        ("eq", Self::Eq),
+        ("lt", Self::Lt),
+        ("gt", Self::Gt),
+        ("le", Self::Le),
+        ("ge", Self::Ge),
+        ("cons", Self::Cons),
+        ("head", Self::Head),
+        ("tail", Self::Tail),
+        ("len", Self::Len),
+    ];
+
+    fn named(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|&&(builtin, _)| builtin == name)
+            .map(|&(_, primitive)| primitive)
+    }
+
+    fn name(self) -> &'static str {
+        Self::ALL[self.slot() as usize].0
+    }
+
+    /// Primitives have the first function slots of the VM, in the order of
+    /// [`Primitive::ALL`].
+    fn slot(self) -> u16 {
+        self as u16
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Self::Head | Self::Tail | Self::Len => 1,
+            _ => 2,
+        }
+    }
+
+    /// Result of the builtin with the arguments `first`, which unary ones
+    /// don't have, and `last`. `None` if the builtin has to be called, e.g. to
+    /// report an overflow.
+    #[inline(always)]
+    fn compute<'a>(self, first: Option<&Operand<'a>>, last: &Operand<'a>) -> Option<Operand<'a>> {
+        // Integers are the most common, and checked first without a call
+        match (first, last) {
+            (Some(Operand::Integer(a)), Operand::Integer(b)) => self.integers(a.value, b.value),
+            (first, last) => self.apply(first, last),
+        }
+    }
+
+    // Not inlined, so that it doesn't slow down the loop running the code
+    #[inline(never)]
+    fn apply<'a>(self, first: Option<&Operand<'a>>, last: &Operand<'a>) -> Option<Operand<'a>> {
+        match (first, last) {
+            (Some(Operand::Float(a)), Operand::Float(b)) => self.floats(a.value, b.value),
+            // Computed as floats, like in the builtins, but never equal
+            (Some(Operand::Integer(a)), Operand::Float(b)) if self != Self::Eq => {
+                self.floats(a.value as f64, b.value)
+            }
+            (Some(Operand::Float(a)), Operand::Integer(b)) if self != Self::Eq => {
+                self.floats(a.value, b.value as f64)
+            }
+            (Some(head), Operand::List(tail)) if self == Self::Cons => {
+                Some(Operand::List(tail.cons(head.clone().into_value().ok()?)))
+            }
+            (None, Operand::List(list)) => self.list(list),
+            _ => None,
+        }
+    }
+
+    fn list<'a>(self, list: &List<Value<'a>>) -> Option<Operand<'a>> {
+        match self {
+            Self::Head => Some(
+                list.values()
+                    .next()
+                    .map_or(Operand::Symbol("nil"), |head| head.clone().into()),
+            ),
+            Self::Tail => Some(Operand::List(list.tail().unwrap_or_default())),
+            Self::Len => Some(Operand::Integer((list.len() as i64).into())),
+            _ => None,
+        }
+    }
+
+    fn integers<'a>(self, a: i64, b: i64) -> Option<Operand<'a>> {
+        Some(match self {
+            Self::Cons | Self::Head | Self::Tail | Self::Len => return None,
+            Self::Add => Operand::Integer(a.checked_add(b)?.into()),
+            Self::Sub => Operand::Integer(a.checked_sub(b)?.into()),
+            Self::Mul => Operand::Integer(a.checked_mul(b)?.into()),
+            Self::Eq => truth(a == b),
+            Self::Lt => truth(a < b),
+            Self::Gt => truth(a > b),
+            Self::Le => truth(a <= b),
+            Self::Ge => truth(a >= b),
+        })
+    }
+
+    fn floats<'a>(self, a: f64, b: f64) -> Option<Operand<'a>> {
+        Some(match self {
+            Self::Cons | Self::Head | Self::Tail | Self::Len => return None,
+            Self::Add => Operand::Float((a + b).into()),
+            Self::Sub => Operand::Float((a - b).into()),
+            Self::Mul => Operand::Float((a * b).into()),
+            Self::Eq => truth(a == b),
+            Self::Lt => truth(a < b),
+            Self::Gt => truth(a > b),
+            Self::Le => truth(a <= b),
+            Self::Ge => truth(a >= b),
+        })
+    }
+}
+
+/// Shortens the stack to `len`. Numbers and symbols, which most values on the
+/// stack are, are dropped without a call.
+#[inline(always)]
+fn truncate(stack: &mut Vec<Operand<'_>>, len: usize) {
+    while stack.len() > len {
+        match stack.pop() {
+            Some(operand @ (Operand::Integer(_) | Operand::Float(_) | Operand::Symbol(_))) => {
+                std::mem::forget(operand)
+            }
+            operand => drop(operand),
+        }
+    }
+}
+
+fn truth<'a>(value: bool) -> Operand<'a> {
+    Operand::Symbol(if value { "true" } else { "false" })
+}
+
+#[derive(Clone)]
+enum Callee<'a> {
+    /// Native function, which is a primitive if it's the builtin one.
+    Native(NativeFn<'a>, Option<Primitive>),
+    Closure(Rc<Closure<'a>>),
+}
+
+/// Named slots of global variables or functions. Names get their slots when
+/// they're compiled, and slots are empty until they're defined.
+struct Slots<T> {
+    names: Vec<String>,
+    indices: HashMap<String, u16>,
+    values: Vec<Option<T>>,
+}
+
+impl<T> Slots<T> {
+    fn new() -> Self {
+        Self {
+            names: Vec::new(),
+            indices: HashMap::new(),
+            values: Vec::new(),
+        }
+    }
+
+    fn slot(&mut self, name: &str) -> Result<u16, Error> {
+        if let Some(&slot) = self.indices.get(name) {
+            return Ok(slot);
+        }
+        let slot =
+            u16::try_from(self.names.len()).map_err(|_| Error::custom("too many global names"))?;
+        self.names.push(name.into());
+        self.indices.insert(name.into(), slot);
+        self.values.push(None);
+        Ok(slot)
+    }
+
+    fn get(&self, name: &str) -> Option<&T> {
+        let slot = *self.indices.get(name)?;
+        self.values[slot as usize].as_ref()
+    }
+}
+
+/// Call in progress.
+struct Frame<'a> {
+    closure: Rc<Closure<'a>>,
+    ip: usize,
+    /// Position of the first local in the stack.
+    base: usize,
+    /// Stack height to restore on return, below the called closure if it's
+    /// on the stack.
+    return_to: usize,
+}
+
+/// Compiler and VM with its global variables and functions.
+pub struct Vm<'a> {
+    globals: Slots<Operand<'a>>,
+    /// Functions defined with `defn`. Natives are taken from the interpreter
+    /// when they're first called.
+    functions: Slots<Callee<'a>>,
+    /// Arguments of the native function being called.
+    args: Vec<Value<'a>>,
+    /// Builtin natives of the primitives.
+    primitives: Vec<(NativeFn<'a>, Primitive)>,
+    /// Expands macros and holds the native functions. Last, as it owns the
+    /// names made by macros, which compiled functions use.
+    interpreter: Interpreter<'a>,
+}
+
+impl Default for Vm<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Vm<'a> {
+    /// VM with the builtin functions of the [`Interpreter`].
+    pub fn new() -> Self {
+        let interpreter = Interpreter::new();
+        let primitives = Primitive::ALL
+            .iter()
+            .filter_map(|&(name, primitive)| Some((interpreter.native(name)?, primitive)))
+            .collect();
+        let mut functions = Slots::new();
+        for &(name, primitive) in Primitive::ALL {
+            let slot = functions.slot(name).ok();
+            debug_assert_eq!(slot, Some(primitive.slot()));
+        }
+        Self {
+            interpreter,
+            globals: Slots::new(),
+            functions,
+            args: Vec::new(),
+            primitives,
+        }
+    }
+
+    /// Adds a native function, like [`Interpreter::register_fn`].
+    pub fn register_fn<F, R>(&mut self, name: impl Into<String>, function: F)
+    where
+        F: Fn(&[Value<'a>]) -> Result<R, Error> + 'a,
+        R: Into<Value<'a>>,
+    {
+        let name = name.into();
+        if let Some(&slot) = self.functions.indices.get(&name) {
+            self.functions.values[slot as usize] = None;
+        }
+        self.interpreter.register_fn(name, function);
+    }
+
+    /// Sets a global variable.
+    ///
+    /// # Panics
+    /// Panics if there are more than 65536 global variables.
+    pub fn set(&mut self, name: impl Into<String>, value: Value<'a>) {
+        let slot = self
+            .globals
+            .slot(&name.into())
+            .expect("too many global names");
+        self.globals.values[slot as usize] = Some(value.into());
+    }
+
+    /// Value of a global variable, if it isn't a closure.
+    pub fn get(&self, name: &str) -> Option<Value<'a>> {
+        self.globals.get(name)?.clone().into_value().ok()
+    }
+
+    /// Parses and runs a program, returns the value of the last form.
+    pub fn run(&mut self, source: &'a str) -> Result<Value<'a>, Error> {
+        let (program, errors) = program_resilient(source);
+        if !errors.is_empty() {
+            return Err(Error::Syntax(errors));
+        }
+        self.eval(&program)
+    }
+
+    /// Compiles and runs a value. Forms of a sequential list, like a program,
+    /// are compiled and run one by one, like in [`Interpreter::eval`].
+    pub fn eval(&mut self, value: &Value<'a>) -> Result<Value<'a>, Error> {
+        match &value.kind {
+            ValueKind::List(forms) if value.sequential && !value.raw => {
+                let mut result = ValueKind::Symbol("nil").into();
+                for form in forms.values() {
+                    result = self.eval(form)?;
+                }
+                Ok(result)
+            }
+            _ => {
+                let proto = self.compile(value)?;
+                self.execute(proto)?.into_value()
+            }
+        }
+    }
+
+    /// Compiles the forms of a program one by one, and lists their code.
+    /// `defmacro` forms are compiled too, so their macros are defined.
+    pub fn disassemble(&mut self, source: &'a str) -> Result<String, Error> {
+        let (program, errors) = program_resilient(source);
+        if !errors.is_empty() {
+            return Err(Error::Syntax(errors));
+        }
+        let mut out = String::new();
+        if let ValueKind::List(forms) = &program.kind {
+            for form in forms.values() {
+                let proto = self.compile(form)?;
+                proto.disassemble(&self.globals.names, &self.functions.names, &mut out);
+            }
+        }
+        Ok(out)
+    }
+
+    fn compile(&mut self, value: &Value<'a>) -> Result<Rc<Proto<'a>>, Error> {
+        let expanded = self.interpreter.expand_form(value)?;
+        Compiler::compile(self, &expanded).map(Rc::new)
+    }
+
+    fn execute(&mut self, proto: Rc<Proto<'a>>) -> Result<Operand<'a>, Error> {
+        let frame = Frame {
+            closure: Rc::new(Closure {
+                proto,
+                upvalues: Vec::new(),
+            }),
+            ip: 0,
+            base: 0,
+            return_to: 0,
+        };
+        // The stack is passed apart from the VM, which keeps it off the
+        // borrows of `self` in the loop
+        self.run_frame(frame, &mut Vec::new())
+    }
+
+    fn global(&self, slot: u16) -> Result<Operand<'a>, Error> {
+        if let Some(value) = &self.globals.values[slot as usize] {
+            return Ok(value.clone());
+        }
+        match self.globals.names[slot as usize].as_str() {
+            "true" => Ok(Operand::Symbol("true")),
+            "false" => Ok(Operand::Symbol("false")),
+            "nil" => Ok(Operand::Symbol("nil")),
+            name => Err(Error::UnboundVariable(name.into())),
+        }
+    }
+
+    fn callee(&mut self, slot: u16) -> Result<Callee<'a>, Error> {
+        let slot = slot as usize;
+        if let Some(callee) = &self.functions.values[slot] {
+            return Ok(callee.clone());
+        }
+        let name = &self.functions.names[slot];
+        let native = self
+            .interpreter
+            .native(name)
+            .ok_or_else(|| Error::UnboundFunction(name.clone()))?;
+        let primitive = self
+            .primitives
+            .iter()
+            .find(|(builtin, _)| Rc::ptr_eq(builtin, &native))
+            .map(|&(_, primitive)| primitive);
+        let callee = Callee::Native(native, primitive);
+        self.functions.values[slot] = Some(callee.clone());
+        Ok(callee)
+    }
+
+    /// Computes a call of a primitive with the arguments on top of the stack
+    /// in place. Returns whether it did.
+    fn call_primitive(stack: &mut Vec<Operand<'a>>, primitive: Primitive) -> bool {
+        let result = match (primitive.arity(), &stack[..]) {
+            (1, [.., last]) => primitive.compute(None, last),
+            (_, [.., first, last]) => primitive.compute(Some(first), last),
+            _ => None,
+        };
+        match result {
+            Some(result) => {
+                truncate(stack, stack.len() - primitive.arity());
+                stack.push(result);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Calls the function in `slot` with the values from `start` to the top of
+    /// the stack. Returns the frame of the closure it calls.
+    fn call(
+        &mut self,
+        stack: &mut Vec<Operand<'a>>,
+        frame: &Frame<'a>,
+        slot: u16,
+        start: usize,
+        tail: bool,
+    ) -> Result<Option<Frame<'a>>, Error> {
+        match self.callee(slot)? {
+            Callee::Native(native, _) => {
+                self.call_native(stack, native, slot, start)?;
+                Ok(None)
+            }
+            Callee::Closure(closure) => {
+                Self::enter(stack, frame, closure, start, start, tail).map(Some)
+            }
+        }
+    }
+
+    /// Calls a native function with the values from `start` to the top of the
+    /// stack, and replaces them with the result.
+    fn call_native(
+        &mut self,
+        stack: &mut Vec<Operand<'a>>,
+        native: NativeFn<'a>,
+        slot: u16,
+        start: usize,
+    ) -> Result<(), Error> {
+        self.args.clear();
+        for operand in stack.drain(start..) {
+            self.args.push(operand.into_value()?);
+        }
+        let result = native(&self.args)
+            .map_err(|error| error.in_function(&self.functions.names[slot as usize]))?;
+        self.args.clear();
+        stack.push(result.into());
+        Ok(())
+    }
+
+    /// Frame of a call of `closure` with the arguments from `start` to the top
+    /// of the stack. A tail call moves them in place of `frame`.
+    fn enter(
+        stack: &mut Vec<Operand<'a>>,
+        frame: &Frame<'a>,
+        closure: Rc<Closure<'a>>,
+        start: usize,
+        return_to: usize,
+        tail: bool,
+    ) -> Result<Frame<'a>, Error> {
+        let argc = stack.len() - start;
+        if closure.proto.arity != argc {
+            return Err(Error::Arity {
+                function: closure.proto.name.map(Into::into),
+                expected: closure.proto.arity,
+                got: argc,
+            });
+        }
+        if !tail {
+            return Ok(Frame {
+                closure,
+                ip: 0,
+                base: start,
+                return_to,
+            });
+        }
+        let base = frame.return_to;
+        Self::move_args(stack, start, base);
+        Ok(Frame {
+            closure,
+            ip: 0,
+            base,
+            return_to: base,
+        })
+    }
+
+    /// Moves the values from `start` to the top of the stack down to `base`.
+    fn move_args(stack: &mut Vec<Operand<'a>>, start: usize, base: usize) {
+        let argc = stack.len() - start;
+        for idx in 0..argc {
+            stack.swap(base + idx, start + idx);
+        }
+        truncate(stack, base + argc);
+    }
+
+    fn run_frame(
+        &mut self,
+        mut frame: Frame<'a>,
+        stack: &mut Vec<Operand<'a>>,
+    ) -> Result<Operand<'a>, Error> {
+        let mut callers = Vec::new();
+        'frames: loop {
+            // The code is borrowed through its own handle, so that the frame
+            // can be replaced while it runs
+            let closure = Rc::clone(&frame.closure);
+            let Proto {
+                code,
+                constants,
+                protos,
+                ..
+            } = &*closure.proto;
+            let mut ip = frame.ip;
+            loop {
+                let op = code[ip];
+                ip += 1;
+                match op {
+                    Op::Constant(idx) => {
+                        let value = constants[idx as usize].clone();
+                        stack.push(value);
+                    }
+                    Op::GetLocal(slot) => {
+                        let value = stack[frame.base + slot as usize].clone();
+                        stack.push(value);
+                    }
+                    Op::GetUpvalue(idx) => {
+                        let value = closure.upvalues[idx as usize].clone();
+                        stack.push(value);
+                    }
+                    Op::GetGlobal(slot) => {
+                        let value = self.global(slot)?;
+                        stack.push(value);
+                    }
+                    Op::SetGlobal(slot) => {
+                        self.globals.values[slot as usize] = stack.pop();
+                    }
+                    Op::SetFunction(slot) => {
+                        if let Some(Operand::Closure(closure)) = stack.pop() {
+                            self.functions.values[slot as usize] = Some(Callee::Closure(closure));
+                        }
+                    }
+                    Op::Closure(idx) => {
+                        let proto = protos[idx as usize].clone();
+                        let upvalues = proto
+                            .captures
+                            .iter()
+                            .map(|capture| match *capture {
+                                Capture::Local(slot) => stack[frame.base + slot as usize].clone(),
+                                Capture::Upvalue(idx) => closure.upvalues[idx as usize].clone(),
+                            })
+                            .collect();
+                        let closure = Closure { proto, upvalues };
+                        stack.push(Operand::Closure(Rc::new(closure)));
+                    }
+                    Op::Call(slot, argc) | Op::TailCall(slot, argc) => {
+                        let start = stack.len() - argc as usize;
+                        match &self.functions.values[slot as usize] {
+                            Some(Callee::Native(_, Some(primitive)))
+                                if argc as usize == primitive.arity() =>
+                            {
+                                let primitive = *primitive;
+                                if Self::call_primitive(stack, primitive) {
+                                    continue;
+                                }
+                            }
+                            // A loop keeps running the same code
+                            Some(Callee::Closure(callee))
+                                if matches!(op, Op::TailCall(..))
+                                    && Rc::ptr_eq(callee, &frame.closure)
+                                    && closure.proto.arity == argc as usize =>
+                            {
+                                frame.base = frame.return_to;
+                                Self::move_args(stack, start, frame.base);
+                                ip = 0;
+                                continue;
+                            }
+                            _ => {}
+                        }
+                        frame.ip = ip;
+                        let tail = matches!(op, Op::TailCall(..));
+                        if let Some(callee) = self.call(stack, &frame, slot, start, tail)? {
+                            let caller = std::mem::replace(&mut frame, callee);
+                            if !tail {
+                                callers.push(caller);
+                            }
+                            continue 'frames;
+                        }
+                    }
+                    Op::PrimitiveLocal(primitive, idx) | Op::PrimitiveConstant(primitive, idx) => {
+                        let slot = primitive.slot();
+                        let last = match op {
+                            Op::PrimitiveLocal(..) => &stack[frame.base + idx as usize],
+                            _ => &constants[idx as usize],
+                        };
+                        if let Some(Callee::Native(_, Some(_))) =
+                            self.functions.values[slot as usize]
+                        {
+                            let first = match primitive.arity() {
+                                1 => None,
+                                _ => stack.last(),
+                            };
+                            if let Some(result) = primitive.compute(first, last) {
+                                let len = stack.len() + 1 - primitive.arity();
+                                truncate(stack, len);
+                                stack.push(result);
+                                continue;
+                            }
+                        }
+                        // Calls the function like `Op::Call`, or `Op::TailCall`
+                        // when it's followed by a return
+                        let last = last.clone();
+                        stack.push(last);
+                        let start = stack.len() - primitive.arity();
+                        frame.ip = ip;
+                        let tail = matches!(code[ip], Op::Return);
+                        if let Some(callee) = self.call(stack, &frame, slot, start, tail)? {
+                            let caller = std::mem::replace(&mut frame, callee);
+                            if !tail {
+                                callers.push(caller);
+                            }
+                            continue 'frames;
+                        }
+                    }
+                    Op::CallValue(argc) | Op::TailCallValue(argc) => {
+                        let start = stack.len() - argc as usize;
+                        let closure = match &stack[start - 1] {
+                            Operand::Closure(closure) => closure.clone(),
+                            operand => return Err(Error::NotCallable(operand.type_name())),
+                        };
+                        frame.ip = ip;
+                        let tail = matches!(op, Op::TailCallValue(_));
+                        let callee = Self::enter(stack, &frame, closure, start, start - 1, tail)?;
+                        let caller = std::mem::replace(&mut frame, callee);
+                        if !tail {
+                            callers.push(caller);
+                        }
+                        continue 'frames;
+                    }
+                    Op::Jump(target) => ip = target as usize,
+                    Op::JumpIfFalse(target) => {
+                        // Conditions are mostly symbols, which don't need dropping
+                        let truthy = match stack.pop() {
+                            Some(Operand::Symbol(name)) => !matches!(name, "false" | "nil"),
+                            Some(operand) => operand.is_truthy(),
+                            None => false,
+                        };
+                        if !truthy {
+                            ip = target as usize;
+                        }
+                    }
+                    Op::Pop => truncate(stack, stack.len() - 1),
+                    Op::Slide(count) => {
+                        let top = stack.pop();
+                        let len = stack.len() - count as usize;
+                        truncate(stack, len);
+                        stack.extend(top);
+                    }
+                    Op::Return => {
+                        let result = stack.pop().expect("function returned nothing");
+                        truncate(stack, frame.return_to);
+                        match callers.pop() {
+                            Some(caller) => {
+                                frame = caller;
+                                stack.push(result);
+                                continue 'frames;
+                            }
+                            None => return Ok(result),
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> Result<String, String> {
+        Vm::new()
+            .run(source)
+            .map(|value| value.to_string())
+            .map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(run("1 2.5 \"s\""), Ok("\"s\"".into()));
+        assert_eq!(run("'x '(a b) ()"), Ok("()".into()));
+        assert_eq!(run("'(a !(b))"), Ok("(a !(b))".into()));
+        assert_eq!(run("(+ 1 2 (* 3 4))"), Ok("15".into()));
+        assert_eq!(run("(def x 2) !((def x 3) (+ x 1))"), Ok("4".into()));
+        assert_eq!(run("(if (lt 1 2) 'yes 'no) (if nil 1)"), Ok("nil".into()));
+        assert_eq!(
+            run("(let ((x 1) (y (+ x 1))) (list x y))"),
+            Ok("(1 2)".into())
+        );
+        assert_eq!(
+            run("(list 1 (let ((x 2) (y 3)) (+ x y)) (let ((z 4)) z))"),
+            Ok("(1 5 4)".into())
+        );
+        assert_eq!(
+            run("(defn (f x) (if x 'yes)) (list (f 1) (f false))"),
+            Ok("(yes nil)".into())
+        );
+        assert_eq!(
+            run("(list 0xFF (+ 0xFF 1) (lt 1.5 2))"),
+            Ok("(0xFF 256 true)".into())
+        );
+    }
+
+    #[test]
+    fn test_primitives() {
+        let source = "
+            (list (+ 1 2) (- 1.5 1) (* 2 0.5) (eq 1 1) (eq 1 1.0) (lt 1 1.5) (ge 2.0 2))
+        ";
+        assert_eq!(run(source), Ok("(3 0.5 1.0 true false true true)".into()));
+        let source = "
+            (def l (cons 1 '(2 3)))
+            (list l (head l) (tail l) (len l) (head ()) (tail ()) (cons l ()))
+        ";
+        assert_eq!(
+            run(source),
+            Ok("((1 2 3) 1 (2 3) 3 nil () ((1 2 3)))".into())
+        );
+        assert_eq!(run("(len \"abc\")"), Ok("3".into()));
+        assert_eq!(run("(head 1)"), Err("expected list, got integer".into()));
+        assert_eq!(
+            run("(cons (fn () 1) ())"),
+            Err("expected value, got function".into())
+        );
+        let source = "
+            (defn (- a b) (+ a b))
+            (defn (len l) (head l))
+            (let ((x 1) (l '(5 6))) (list (- x 2) (len l) (len '(7))))
+        ";
+        assert_eq!(run(source), Ok("(3 5 7)".into()));
+    }
+
+    #[test]
+    fn test_closures() {
+        let source = "
+            (defn (adder n) (fn (x) (+ x n)))
+            (defn (compose f g) (fn (x) (call f (call g x))))
+            (let ((add1 (adder 1)) (add10 (adder 10)))
+                (list (call add1 1) (call (compose add1 add10) 5)))
+        ";
+        assert_eq!(run(source), Ok("(2 16)".into()));
+        let source = "
+            (let ((a 1))
+                (call (fn (b) (call (fn (c) (list a b c)) 3)) 2))
+        ";
+        assert_eq!(run(source), Ok("(1 2 3)".into()));
+        assert_eq!(
+            run("(defn (f x) (fn () x)) (def g (f 7)) (call g)"),
+            Ok("7".into())
+        );
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let source = "
+            (defn (count n acc) (if (eq n 0) acc (count (- n 1) (+ acc 1))))
+            (defn (even n) (if (eq n 0) true (let ((m (- n 1))) (odd m))))
+            (defn (odd n) !((eq n 0) (if (eq n 0) false (even (- n 1)))))
+            (defn (loop f n) (if (eq n 0) 'done (call f f (- n 1))))
+            (list (count 100000 0) (even 100000) (odd 7) (loop (fn (f n) (loop f n)) 100000))
+        ";
+        assert_eq!(run(source), Ok("(100000 true true done)".into()));
+        let source = "
+            (defn (tail n) (if (eq n 0) 'done (let ((m (- n 1))) (tail m))))
+            (tail 100000)
+        ";
+        assert_eq!(run(source), Ok("done".into()));
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let mut vm = Vm::new();
+        vm.set("base", 10.into());
+        vm.register_fn("twice", |args: &[Value]| {
+            let (n,): (i64,) = crate::interpreter::from_args(args)?;
+            Ok(n * 2)
+        });
+        assert_eq!(vm.run("(twice (+ base 1))"), Ok(22.into()));
+        vm.register_fn("twice", |_: &[Value]| Ok(0));
+        assert_eq!(vm.run("(twice 1)"), Ok(0.into()));
+        assert_eq!(vm.get("base"), Some(10.into()));
+        vm.register_fn("+", |_: &[Value]| Ok(0));
+        assert_eq!(vm.run("(+ 1 2)"), Ok(0.into()));
+        assert_eq!(vm.run("(defn (- a b) a) (- 5 3)"), Ok(5.into()));
+    }
+
+    #[test]
+    fn test_same_as_interpreter() {
+        let sources = [
+            "!5",
+            "!1.5",
+            "(list !5 !2.5 'x)",
+            "(head (list !5))",
+            "(cons !5 (tail '(1 2)))",
+            "(let ((x !5)) (list x x))",
+            "(defn (f x) x) (f !5)",
+            "(+ !5 1)",
+            "(eq !5 5)",
+        ];
+        for source in sources {
+            let interpreted = Interpreter::new()
+                .run(source)
+                .map(|value| value.to_string())
+                .map_err(|error| error.to_string());
+            assert_eq!(run(source), interpreted, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_macros() {
+        let source = "
+            (defmacro (my-or a b) (list 'let (list (list 't a)) (list 'if 't 't b)))
+            (defmacro (thunk body) (list 'fn () body))
+            (let ((t 5)) (list (my-or false t) (call (thunk t))))
+        ";
+        assert_eq!(run(source), Ok("(5 5)".into()));
+        let source = "
+            (defmacro (bind-one name body) (list 'let (list (list name 1)) body))
+            (defmacro (m body) (list 'bind-one 'tmp (list 'list 'tmp body)))
+            (let ((tmp 5)) (m tmp))
+        ";
+        assert_eq!(run(source), Ok("(1 5)".into()));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(run("(f 1)"), Err("unknown function `f`".into()));
+        assert_eq!(run("x"), Err("unbound variable `x`".into()));
+        assert_eq!(
+            run("(defn (f a) a) (f)"),
+            Err("`f` takes 1 arguments, got 0".into())
+        );
+        assert_eq!(run("(call 1)"), Err("integer can't be called".into()));
+        assert_eq!(
+            run("(fn (x) x)"),
+            Err("expected value, got function".into())
+        );
+        assert_eq!(run("(+ 1 'a)"), Err("expected number, got symbol".into()));
+        let overflow = "(* 4611686018427387904 2)";
+        assert_eq!(
+            run(overflow),
+            Err("integer overflow or division by zero".into())
+        );
+        assert_eq!(run("(1 2)"), Err("integer can't be called".into()));
+        assert_eq!(
+            run("(let (x) x)"),
+            Err("`let` binding is a list of a name and a value".into())
+        );
+        let mut vm = Vm::new();
+        assert!(vm.run("(defn (f) (g)) (f)").is_err());
+        assert_eq!(vm.run("(defn (g) 1) (f)"), Ok(1.into()));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let listing = Vm::new()
+            .disassemble("(defn (f n) (if (eq n 0) 'zero (f (- n 1))))")
+            .unwrap();
+        let expected = "\
+== <fn> (0 params, 0 upvalues) ==
+0000 closure 0 ; f
+0001 set-function 12 ; f
+0002 constant 0 ; f
+0003 return
+== f (1 params, 0 upvalues) ==
+0000 get-local 0
+0001 primitive-constant eq 0 ; 0
+0002 jump-if-false 0005
+0003 constant 1 ; zero
+0004 jump 0008
+0005 get-local 0
+0006 primitive-constant - 2 ; 1
+0007 tail-call 12 1 ; f
+0008 return
+";
+        assert_eq!(listing, expected);
+    }
+
+    /// Best time of running `source` in the interpreter divided by its best
+    /// time in the VM. Runs alternate between the two, so that they share the
+    /// load of the machine.
+    fn speedup(source: &str, expected: &str) -> f64 {
+        use std::time::{Duration, Instant};
+
+        fn time(run: impl FnOnce() -> Result<String, Error>, expected: &str) -> Duration {
+            let started = Instant::now();
+            let result = run();
+            let elapsed = started.elapsed();
+            assert_eq!(result, Ok(expected.into()));
+            elapsed
+        }
+
+        let mut interpreted = Duration::MAX;
+        let mut compiled = Duration::MAX;
+        for _ in 0..5 {
+            let interpreter = || {
+                Interpreter::new()
+                    .run(source)
+                    .map(|value| value.to_string())
+            };
+            interpreted = interpreted.min(time(interpreter, expected));
+            let vm = || Vm::new().run(source).map(|value| value.to_string());
+            compiled = compiled.min(time(vm, expected));
+        }
+        interpreted.as_secs_f64() / compiled.as_secs_f64()
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_against_interpreter() {
+        let integers = "
+            (defn (count n acc) (if (eq n 0) acc (count (- n 1) (+ acc 1))))
+            (count 1000000 0)
+        ";
+        let floats = "
+            (defn (sum n acc) (if (eq n 0) acc (sum (- n 1) (+ (* acc 0.5) 1.5))))
+            (sum 1000000 0.0)
+        ";
+        let lists = "
+            (defn (build n acc) (if (eq n 0) acc (build (- n 1) (cons n acc))))
+            (defn (total l acc) (if (eq (len l) 0) acc (total (tail l) (+ acc (head l)))))
+            (total (build 300000 ()) 0)
+        ";
+        let calls = "
+            (defn (step x) (+ x 2))
+            (defn (repeat n acc) (if (eq n 0) acc (repeat (- n 1) (step acc))))
+            (repeat 1000000 0)
+        ";
+        for (name, source, expected) in [
+            ("integers", integers, "1000000"),
+            ("floats", floats, "3.0"),
+            ("lists", lists, "45000150000"),
+            ("calls", calls, "2000000"),
+        ] {
+            let speedup = speedup(source, expected);
+            println!("{}: {:.1}x", name, speedup);
+            // Lists and calls don't reach it, see the module docs
+            if matches!(name, "integers" | "floats") {
+                assert!(speedup >= 10.0, "{} are {:.1}x faster", name, speedup);
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Drives the REPL through a pseudo-terminal, like a user would.
+#![cfg(unix)]
+
+use rexpect::error::Error;
+use rexpect::session::{spawn_command, PtySession};
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Fresh home directory, so that tests don't share history.
+fn home(name: &str) -> PathBuf {
+    let home = std::env::temp_dir().join(format!("spli-repl-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&home);
+    std::fs::create_dir_all(&home).unwrap();
+    home
+}
+
+fn spawn_with(home: &Path, color: bool) -> Result<PtySession, Error> {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_spli"));
+    command.env("HOME", home).env("TERM", "xterm");
+    if !color {
+        command.env("NO_COLOR", "1");
+    }
+    let mut session = spawn_command(command, Some(5000))?;
+    session.exp_string("spli parser> ")?;
+    Ok(session)
+}
+
+fn spawn(home: &Path) -> Result<PtySession, Error> {
+    spawn_with(home, false)
+}
+
+fn type_keys(session: &mut PtySession, keys: &str) -> Result<(), Error> {
+    session.send(keys)?;
+    session.flush()
+}
+
+#[test]
+fn test_multiline_input() -> Result<(), Error> {
+    let mut session = spawn(&home("multiline"))?;
+    type_keys(&mut session, "(a\r")?;
+    session.exp_string("        ...> ")?;
+    type_keys(&mut session, "  \"b\r")?;
+    session.exp_string("        ...> ")?;
+    type_keys(&mut session, "c\")  1\r")?;
+    session.exp_string("(a \"b\\nc\") :: list")?;
+    session.exp_string("1 :: integer")?;
+    session.send_control('d')?;
+    session.exp_eof()?;
+    Ok(())
+}
+
+#[test]
+fn test_interrupt_discards_input() -> Result<(), Error> {
+    let mut session = spawn(&home("interrupt"))?;
+    type_keys(&mut session, "(unfinished\r")?;
+    session.exp_string("        ...> ")?;
+    session.send_control('c')?;
+    session.exp_string("spli parser> ")?;
+    type_keys(&mut session, "2\r")?;
+    session.exp_string("2 :: integer")?;
+    session.send_control('d')?;
+    session.exp_eof()?;
+    Ok(())
+}
+
+#[test]
+fn test_completion() -> Result<(), Error> {
+    let mut session = spawn(&home("completion"))?;
+    type_keys(&mut session, "(define-something 1)\r")?;
+    session.exp_string("(define-something 1) :: list")?;
+    type_keys(&mut session, "(define-s\t")?;
+    type_keys(&mut session, " 2)\r")?;
+    session.exp_string("(define-something 2) :: list")?;
+    session.send_control('d')?;
+    session.exp_eof()?;
+    Ok(())
+}
+
+#[test]
+fn test_history() -> Result<(), Error> {
+    let home = home("history");
+    let mut session = spawn(&home)?;
+    type_keys(&mut session, "(first 1)\r")?;
+    session.exp_string("(first 1) :: list")?;
+    type_keys(&mut session, "(second 2)\r")?;
+    session.exp_string("(second 2) :: list")?;
+    session.send_control('d')?;
+    session.exp_eof()?;
+    let history = std::fs::read_to_string(home.join(".spli_history")).unwrap();
+    assert!(history.contains("(first 1)\n(second 2)"));
+
+    let mut session = spawn(&home)?;
+    // Reverse search
+    session.send_control('r')?;
+    type_keys(&mut session, "first")?;
+    type_keys(&mut session, "\r")?;
+    session.exp_string("(first 1) :: list")?;
+    session.send_control('d')?;
+    session.exp_eof()?;
+    Ok(())
+}
+
+#[test]
+fn test_meta_commands() -> Result<(), Error> {
+    let mut session = spawn(&home("meta"))?;
+    type_keys(&mut session, "(def x 1) (defn (f a) a)\r")?;
+    session.exp_string("(defn (f a) a) :: list")?;
+    type_keys(&mut session, ":type 0x10\r")?;
+    session.exp_string("integer")?;
+    type_keys(&mut session, ":time (g 1)\r")?;
+    session.exp_string("Parsed in")?;
+    type_keys(&mut session, ":env\r")?;
+    // Sorted by name
+    session.exp_string("(defn (f a) a)")?;
+    session.exp_string("(def x 1)")?;
+    type_keys(&mut session, ":reset\r")?;
+    // Keys typed before the next prompt may be discarded when the terminal is set up
+    session.exp_string(":reset")?;
+    session.exp_string("spli parser> ")?;
+    type_keys(&mut session, ":ty\t \"s\"\r")?;
+    session.exp_string("string")?;
+    type_keys(&mut session, ":quit\r")?;
+    session.exp_eof()?;
+    Ok(())
+}
+
+#[test]
+fn test_highlighting() -> Result<(), Error> {
+    let mut session = spawn_with(&home("highlighting"), true)?;
+    type_keys(&mut session, "(x \"s\")\r")?;
+    // Printed result, with the string in green
+    session.exp_string("(x \x1b[0m\x1b[32m\"s\"\x1b[0m) :: list")?;
+    session.send_control('d')?;
+    session.exp_eof()?;
+    Ok(())
+}
+
+#[test]
+fn test_unclosed_input_at_exit() -> Result<(), Error> {
+    let mut session = spawn(&home("unclosed"))?;
+    type_keys(&mut session, "(a\r")?;
+    session.exp_string("        ...> ")?;
+    session.send_control('d')?;
+    session.exp_string("E0001")?;
+    session.exp_eof()?;
+    Ok(())
+}